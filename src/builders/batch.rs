@@ -1,4 +1,8 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use super::ImageBuilder;
+use crate::error::ImageSlapperError;
+use crate::io::cache::QoiCache;
 
 pub struct ImageBatchBuilder {
     pub images: Vec<String>,
@@ -28,23 +32,33 @@ pub struct ImagePipeline {
 }
 
 impl ImagePipeline {
-    pub fn new(cache_dir: Option<PathBuf>, output_dir: Option<PathBuf>) -> Self {
-        let cache_dir = cache_dir.unwrap_or_else(|| {
-            let default_cache = PathBuf::from("./.cache");
-            std::fs::create_dir_all(&default_cache).expect("Failed to create cache directory");
-            default_cache
-        });
-
-        let output_dir = output_dir.unwrap_or_else(|| {
-            let default_output = PathBuf::from("./out");
-            std::fs::create_dir_all(&default_output).expect("Failed to create output directory");
-            default_output
-        });
-
-        Self {
+    pub fn new(
+        cache_dir: Option<PathBuf>,
+        output_dir: Option<PathBuf>,
+    ) -> Result<Self, ImageSlapperError> {
+        let cache_dir = cache_dir.unwrap_or_else(|| PathBuf::from("./.cache"));
+        std::fs::create_dir_all(&cache_dir)
+            .map_err(|e| ImageSlapperError::io(&cache_dir, "create cache directory", e))?;
+
+        let output_dir = output_dir.unwrap_or_else(|| PathBuf::from("./out"));
+        std::fs::create_dir_all(&output_dir)
+            .map_err(|e| ImageSlapperError::io(&output_dir, "create output directory", e))?;
+
+        Ok(Self {
             cache_dir,
             output_dir,
-        }
+        })
+    }
+
+    /// Returns the QOI cache stored in the pipeline's cache directory.
+    pub fn cache(&self) -> QoiCache {
+        QoiCache::new(&self.cache_dir)
+    }
+
+    /// Loads a record's image through the cache into a builder whose errors name the file.
+    pub fn load(&self, image_path: &Path) -> Result<ImageBuilder, ImageSlapperError> {
+        let image = self.cache().load(image_path)?;
+        Ok(ImageBuilder::from_image(image).with_source(image_path))
     }
 
     pub fn build(&self) -> Vec<(PathBuf, PathBuf)> {
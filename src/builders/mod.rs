@@ -1,11 +1,35 @@
+use std::path::PathBuf;
+
 use image::DynamicImage;
 
-use crate::{geometry::rectangle::Rectangle, rendering::draw::ImageWrapper, text::Text};
+use crate::{
+    error::ImageSlapperError,
+    geometry::rectangle::Rectangle,
+    helpers::{
+        aztec::Aztec,
+        barcode::Barcode,
+        datamatrix::DataMatrix,
+        pdf417::Pdf417,
+        qr::QRCode,
+        verify::{self, Expected, Verification, VerifyOptions},
+    },
+    io::{
+        encode::EncodeOptions,
+        metadata::{ImageMetadata, MetadataOptions},
+    },
+    rendering::{
+        color::{ColorSpace, WorkingSpace},
+        draw::ImageWrapper,
+    },
+    text::{Text, path::PathText, rich::RichText},
+};
 
 pub mod batch;
 
 pub struct ImageBuilder {
     content: ImageWrapper,
+    /// Symbols drawn so far, to read back when verifying.
+    expected: Vec<Expected>,
 }
 
 impl Default for ImageBuilder {
@@ -17,29 +41,142 @@ impl Default for ImageBuilder {
 impl ImageBuilder {
     pub fn new(width: u32, height: u32) -> Self {
         let content = ImageWrapper::new(DynamicImage::new_rgba8(width, height));
-        ImageBuilder { content }
+        ImageBuilder {
+            content,
+            expected: Vec::new(),
+        }
     }
 
     pub fn from_image(image: DynamicImage) -> Self {
         let content = ImageWrapper::new(image);
-        ImageBuilder { content }
+        ImageBuilder {
+            content,
+            expected: Vec::new(),
+        }
+    }
+
+    /// Starts from a loaded image, saving its metadata along with the result as `keep` allows.
+    pub fn from_image_with_metadata(
+        image: DynamicImage,
+        metadata: ImageMetadata,
+        keep: MetadataOptions,
+    ) -> Self {
+        let content = ImageWrapper::new(image).with_metadata(metadata, keep);
+        ImageBuilder {
+            content,
+            expected: Vec::new(),
+        }
+    }
+
+    /// Names the file the image came from in errors from drawing and saving.
+    pub fn with_source(mut self, path: impl Into<PathBuf>) -> Self {
+        self.content = self.content.with_source(path);
+        self
     }
 
-    pub fn add_text(&mut self, text: Text) -> &mut Self {
-        self.content.draw(&text);
+    /// Sets the space the pixels are in and the one to save them in.
+    pub fn with_color_spaces(mut self, working: WorkingSpace, output: ColorSpace) -> Self {
+        self.content = self.content.with_color_spaces(working, output);
         self
     }
 
-    pub fn add_rectangle(&mut self, rectangle: Rectangle) -> &mut Self {
-        self.content.draw(&rectangle);
+    pub fn add_text(&mut self, text: Text) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&text)?;
+        Ok(self)
+    }
+
+    pub fn add_rich_text(&mut self, text: RichText) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&text)?;
+        Ok(self)
+    }
+
+    pub fn add_path_text(&mut self, text: PathText) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&text)?;
+        Ok(self)
+    }
+
+    pub fn add_rectangle(&mut self, rectangle: Rectangle) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&rectangle)?;
+        Ok(self)
+    }
+
+    pub fn add_barcode(&mut self, barcode: Barcode<'_>) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&barcode)?;
+        self.expected.push(Expected::from(&barcode));
+        Ok(self)
+    }
+
+    pub fn add_qr_code(&mut self, qr: QRCode) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&qr)?;
+        self.expected.push(Expected::from(&qr));
+        Ok(self)
+    }
+
+    pub fn add_data_matrix(
+        &mut self,
+        data_matrix: DataMatrix,
+    ) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&data_matrix)?;
+        self.expected.push(Expected::from(&data_matrix));
+        Ok(self)
+    }
+
+    pub fn add_pdf417(&mut self, pdf417: Pdf417) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&pdf417)?;
+        self.expected.push(Expected::from(&pdf417));
+        Ok(self)
+    }
+
+    pub fn add_aztec(&mut self, aztec: Aztec) -> Result<&mut Self, ImageSlapperError> {
+        self.content.draw(&aztec)?;
+        self.expected.push(Expected::from(&aztec));
+        Ok(self)
+    }
+
+    /// Adds a symbol drawn by other means, such as a QR code on a layer, to the ones verified.
+    pub fn expect_symbol(&mut self, expected: Expected) -> &mut Self {
+        self.expected.push(expected);
         self
     }
 
+    /// Reads every barcode and expected symbol back from the image and checks its payload.
+    pub fn verify(&self, options: &VerifyOptions) -> Result<Vec<Verification>, ImageSlapperError> {
+        let image = self.content.get_image();
+        self.expected
+            .iter()
+            .map(|expected| verify::verify(image, expected, options).map_err(Into::into))
+            .collect()
+    }
+
+    /// Returns the image once all its symbols are verified.
+    pub fn get_verified_image(
+        &self,
+        options: &VerifyOptions,
+    ) -> Result<DynamicImage, ImageSlapperError> {
+        self.verify(options)?;
+        Ok(self.get_image())
+    }
+
     pub fn get_image(&self) -> DynamicImage {
         self.content.get_image().to_owned()
     }
 
-    pub fn save_image(&self, path: &str) {
-        self.content.save_image(path);
+    /// Encodes the image into a writer with explicit format settings.
+    pub fn encode(
+        &self,
+        options: &EncodeOptions,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), ImageSlapperError> {
+        self.content.encode(options, writer)
+    }
+
+    /// Encodes the image into a new buffer with explicit format settings.
+    pub fn encode_to_vec(&self, options: &EncodeOptions) -> Result<Vec<u8>, ImageSlapperError> {
+        self.content.encode_to_vec(options)
+    }
+
+    pub fn save_image(&self, path: &str) -> Result<(), ImageSlapperError> {
+        self.content.save_image(path)?;
+        Ok(())
     }
 }
@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::helpers::verify::VerifyError;
+
+/// Errors from loading, rendering and saving images.
+#[derive(Debug, Error)]
+pub enum ImageSlapperError {
+    #[error("failed to decode {}: {source}", .path.display())]
+    Decode {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+    #[error("failed to encode {}: {source}", .path.display())]
+    Encode {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+    #[error("failed to {context} {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        /// What was being done, e.g. `read image`.
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("cache entry {}: {context}", .path.display())]
+    Cache { path: PathBuf, context: String },
+    #[error("font {name}{}: {context}", in_file(.path.as_deref()))]
+    Font {
+        name: String,
+        context: String,
+        /// The image being rendered, when known.
+        path: Option<PathBuf>,
+    },
+    #[error("{symbology}{}: {context}", in_file(.path.as_deref()))]
+    Barcode {
+        symbology: String,
+        context: String,
+        /// The image being rendered, when known.
+        path: Option<PathBuf>,
+    },
+    #[error("color profile {profile}{}: {context}", in_file(.path.as_deref()))]
+    Color {
+        profile: String,
+        context: String,
+        /// The image being rendered, when known.
+        path: Option<PathBuf>,
+    },
+    #[error("layout of {element}{}: {context}", in_file(.path.as_deref()))]
+    Layout {
+        element: String,
+        context: String,
+        /// The image being rendered, when known.
+        path: Option<PathBuf>,
+    },
+    #[error(transparent)]
+    Verify(#[from] VerifyError),
+}
+
+impl ImageSlapperError {
+    pub fn io(path: &Path, context: &str, source: std::io::Error) -> Self {
+        ImageSlapperError::Io {
+            path: path.to_path_buf(),
+            context: context.to_string(),
+            source,
+        }
+    }
+
+    pub fn barcode(symbology: impl Into<String>, context: impl Into<String>) -> Self {
+        ImageSlapperError::Barcode {
+            symbology: symbology.into(),
+            context: context.into(),
+            path: None,
+        }
+    }
+
+    pub fn color(profile: impl Into<String>, context: impl Into<String>) -> Self {
+        ImageSlapperError::Color {
+            profile: profile.into(),
+            context: context.into(),
+            path: None,
+        }
+    }
+
+    pub fn layout(element: impl Into<String>, context: impl Into<String>) -> Self {
+        ImageSlapperError::Layout {
+            element: element.into(),
+            context: context.into(),
+            path: None,
+        }
+    }
+
+    /// Records the image a font, barcode, color or layout error happened in, unless one is
+    /// recorded already.
+    #[must_use]
+    pub fn with_path(mut self, image_path: &Path) -> Self {
+        if let ImageSlapperError::Font { path, .. }
+        | ImageSlapperError::Barcode { path, .. }
+        | ImageSlapperError::Color { path, .. }
+        | ImageSlapperError::Layout { path, .. } = &mut self
+            && path.is_none()
+        {
+            *path = Some(image_path.to_path_buf());
+        }
+        self
+    }
+}
+
+/// Names the image an error happened in, if known.
+fn in_file(path: Option<&Path>) -> String {
+    path.map(|path| format!(" in {}", path.display()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_path_names_the_image_once() {
+        let error = ImageSlapperError::barcode("EAN-13", "bad check digit")
+            .with_path(Path::new("records/a.png"))
+            .with_path(Path::new("records/b.png"));
+        assert_eq!(
+            error.to_string(),
+            "EAN-13 in records/a.png: bad check digit"
+        );
+        assert_eq!(
+            ImageSlapperError::layout("text", "does not fit").to_string(),
+            "layout of text: does not fit"
+        );
+    }
+
+    #[test]
+    fn with_path_keeps_errors_that_have_a_path() {
+        let error = ImageSlapperError::Cache {
+            path: PathBuf::from("cache/x.qoi"),
+            context: "corrupt".to_string(),
+        }
+        .with_path(Path::new("records/a.png"));
+        assert_eq!(error.to_string(), "cache entry cache/x.qoi: corrupt");
+    }
+}
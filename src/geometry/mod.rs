@@ -1,2 +1,3 @@
 pub mod coordinate;
+pub mod path;
 pub mod rectangle;
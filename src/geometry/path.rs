@@ -0,0 +1,196 @@
+/// A curve that text and shapes can follow, in canvas pixels.
+///
+/// Angles are in degrees, with 0° pointing right and positive angles turning clockwise on
+/// screen, so 270° is the top of a circle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Path {
+    Line {
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    /// A full clockwise circle starting at `start_deg`.
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+        start_deg: f32,
+    },
+    /// Part of a circle, clockwise when `end_deg` is greater than `start_deg` and
+    /// counter-clockwise otherwise.
+    Arc {
+        center: (f32, f32),
+        radius: f32,
+        start_deg: f32,
+        end_deg: f32,
+    },
+    QuadraticBezier {
+        from: (f32, f32),
+        control: (f32, f32),
+        to: (f32, f32),
+    },
+    CubicBezier {
+        from: (f32, f32),
+        control1: (f32, f32),
+        control2: (f32, f32),
+        to: (f32, f32),
+    },
+}
+
+/// A point on a path with the direction the path is heading in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathPoint {
+    pub x: f32,
+    pub y: f32,
+    /// Clockwise angle of the tangent in degrees, 0° pointing right.
+    pub angle_deg: f32,
+}
+
+/// A path flattened into a polyline, for looking up points by distance along it.
+#[derive(Clone, Debug)]
+pub struct MeasuredPath {
+    points: Vec<(f32, f32)>,
+    /// Distance from the start of the path to each point.
+    distances: Vec<f32>,
+    closed: bool,
+}
+
+impl Path {
+    /// Returns the point at parameter `t` in `0.0..=1.0`.
+    pub fn point_at(&self, t: f32) -> (f32, f32) {
+        match *self {
+            Path::Line { from, to } => lerp(from, to, t),
+            Path::Circle {
+                center,
+                radius,
+                start_deg,
+            } => on_circle(center, radius, start_deg + 360.0 * t),
+            Path::Arc {
+                center,
+                radius,
+                start_deg,
+                end_deg,
+            } => on_circle(center, radius, start_deg + (end_deg - start_deg) * t),
+            Path::QuadraticBezier { from, control, to } => {
+                lerp(lerp(from, control, t), lerp(control, to, t), t)
+            }
+            Path::CubicBezier {
+                from,
+                control1,
+                control2,
+                to,
+            } => {
+                let a = lerp(from, control1, t);
+                let b = lerp(control1, control2, t);
+                let c = lerp(control2, to, t);
+                lerp(lerp(a, b, t), lerp(b, c, t), t)
+            }
+        }
+    }
+
+    /// Whether the path ends where it starts.
+    pub fn is_closed(&self) -> bool {
+        matches!(self, Path::Circle { .. })
+    }
+
+    /// Flattens the path into short segments that can be measured.
+    pub fn measure(&self) -> MeasuredPath {
+        // A rough length from the control polygon decides how finely to sample
+        let rough = match *self {
+            Path::Line { .. } => 0.0,
+            Path::Circle { radius, .. } => std::f32::consts::TAU * radius.abs(),
+            Path::Arc {
+                radius,
+                start_deg,
+                end_deg,
+                ..
+            } => (end_deg - start_deg).abs().to_radians() * radius.abs(),
+            Path::QuadraticBezier { from, control, to } => {
+                distance(from, control) + distance(control, to)
+            }
+            Path::CubicBezier {
+                from,
+                control1,
+                control2,
+                to,
+            } => distance(from, control1) + distance(control1, control2) + distance(control2, to),
+        };
+        let steps = (rough / 2.0).ceil().clamp(1.0, 4096.0) as usize;
+
+        let points: Vec<(f32, f32)> = (0..=steps)
+            .map(|step| self.point_at(step as f32 / steps as f32))
+            .collect();
+        let mut distances = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        for (index, point) in points.iter().enumerate() {
+            if index > 0 {
+                total += distance(points[index - 1], *point);
+            }
+            distances.push(total);
+        }
+
+        MeasuredPath {
+            points,
+            distances,
+            closed: self.is_closed(),
+        }
+    }
+}
+
+impl MeasuredPath {
+    /// Returns the length of the path in pixels.
+    pub fn length(&self) -> f32 {
+        self.distances.last().copied().unwrap_or(0.0)
+    }
+
+    /// Returns the point `distance` pixels along the path.
+    ///
+    /// Closed paths wrap around, open paths continue in a straight line past their ends.
+    pub fn sample(&self, distance: f32) -> PathPoint {
+        let length = self.length();
+        let distance = if self.closed && length > 0.0 {
+            distance.rem_euclid(length)
+        } else {
+            distance
+        };
+
+        // Segment containing the distance, or the first or last one beyond the ends
+        let last = self.points.len().saturating_sub(2);
+        let segment = self
+            .distances
+            .partition_point(|&d| d <= distance)
+            .saturating_sub(1)
+            .min(last);
+
+        let (start, end) = match (self.points.get(segment), self.points.get(segment + 1)) {
+            (Some(&start), Some(&end)) => (start, end),
+            (Some(&start), None) => (start, start),
+            _ => ((0.0, 0.0), (0.0, 0.0)),
+        };
+        let segment_length = self.distances.get(segment + 1).copied().unwrap_or(0.0)
+            - self.distances.get(segment).copied().unwrap_or(0.0);
+        let t = if segment_length > 0.0 {
+            (distance - self.distances[segment]) / segment_length
+        } else {
+            0.0
+        };
+        let (x, y) = lerp(start, end, t);
+
+        PathPoint {
+            x,
+            y,
+            angle_deg: (end.1 - start.1).atan2(end.0 - start.0).to_degrees(),
+        }
+    }
+}
+
+fn lerp(a: (f32, f32), b: (f32, f32), t: f32) -> (f32, f32) {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+fn on_circle(center: (f32, f32), radius: f32, degrees: f32) -> (f32, f32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    (center.0 + radius * cos, center.1 + radius * sin)
+}
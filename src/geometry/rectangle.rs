@@ -1,9 +1,12 @@
-use image::DynamicImage;
+use image::{DynamicImage, RgbaImage};
 use rgb::Rgba;
 use tracing::debug;
 
+use crate::error::ImageSlapperError;
 use crate::primitive::layer::{BlendMode, Layer, PixelProvider, Transform};
+use crate::rendering::color::ColorSpace;
 use crate::rendering::draw::Drawable;
+use crate::rendering::strip::StripDrawable;
 
 /// Border types for rectangles.
 #[derive(Clone, Copy, Debug)]
@@ -43,6 +46,8 @@ pub struct Rectangle {
     pub border: Option<Border>,
     pub filled: bool,
     pub fill_color: Option<Rgba<u8>>,
+    /// The space the fill and border colors are given in, e.g. Display P3 for brand colors.
+    pub color_space: ColorSpace,
 }
 
 impl Rectangle {
@@ -57,6 +62,7 @@ impl Rectangle {
             width: x2 - x1,
             height: y2 - y1,
             fill_color: None,
+            color_space: ColorSpace::Srgb,
         }
     }
 
@@ -71,6 +77,7 @@ impl Rectangle {
             width,
             height,
             fill_color: None,
+            color_space: ColorSpace::Srgb,
         }
     }
 
@@ -86,6 +93,48 @@ impl Rectangle {
             width: self.width,
             height: self.height,
             fill_color: Some(fill_color),
+            color_space: self.color_space,
+        }
+    }
+
+    /// Gives the fill and border colors in another color space.
+    pub fn in_color_space(&self, color_space: ColorSpace) -> Self {
+        Rectangle {
+            color_space,
+            ..*self
+        }
+    }
+
+    /// Returns the rectangle with its colors converted to sRGB for drawing.
+    fn to_srgb(self) -> Self {
+        Rectangle {
+            border: self.border.map(|border| Border {
+                color: self.color_space.to_srgb(border.color),
+                ..border
+            }),
+            fill_color: self.fill_color.map(|color| self.color_space.to_srgb(color)),
+            color_space: ColorSpace::Srgb,
+            ..self
+        }
+    }
+
+    /// Returns the area shared by both rectangles, empty when they do not overlap.
+    pub fn intersection(&self, other: &Rectangle) -> Rectangle {
+        let x1 = self.x1.max(other.x1);
+        let y1 = self.y1.max(other.y1);
+        let x2 = self.x2.min(other.x2).max(x1);
+        let y2 = self.y2.min(other.y2).max(y1);
+        Rectangle {
+            x1,
+            y1,
+            x2,
+            y2,
+            width: x2 - x1,
+            height: y2 - y1,
+            border: None,
+            filled: false,
+            fill_color: None,
+            color_space: ColorSpace::Srgb,
         }
     }
 
@@ -140,12 +189,12 @@ impl PixelProvider for Rectangle {
 }
 
 impl Drawable for Rectangle {
-    fn draw(&self, image: &mut DynamicImage) {
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError> {
         debug!(
             "Drawing rectangle at ({}, {}) with dimensions {}x{}",
             self.x1, self.y1, self.width, self.height
         );
-        let layer = self.to_layer();
+        let layer = self.to_srgb().to_layer();
         let delta_buffer = layer.collect_changes(None);
 
         debug!(
@@ -156,10 +205,34 @@ impl Drawable for Rectangle {
         // Apply the delta buffer to the image
         let mut img = image.to_rgba8();
         for change in delta_buffer.changes {
+            // Pixels off the canvas would wrap onto the next row or past the buffer
+            if change.x >= img.width() || change.y >= img.height() {
+                continue;
+            }
             let idx = ((change.y * img.width() + change.x) * 4) as usize;
             img.as_mut()[idx..idx + 4].copy_from_slice(&change.color);
         }
 
         *image = DynamicImage::ImageRgba8(img);
+        Ok(())
+    }
+}
+
+impl StripDrawable for Rectangle {
+    fn rows(&self) -> (u32, u32) {
+        (self.y1, self.y1 + self.height)
+    }
+
+    fn draw_strip(&mut self, strip: &mut RgbaImage, top: u32) -> Result<(), ImageSlapperError> {
+        let layer = self.to_srgb().to_layer();
+        let bottom = (top + strip.height()).min(self.y1 + self.height);
+        let right = (self.x1 + self.width).min(strip.width());
+        for y in top.max(self.y1)..bottom {
+            for x in self.x1..right {
+                let color = layer.compute_pixel_at(x, y);
+                strip.put_pixel(x, y - top, image::Rgba(color.into()));
+            }
+        }
+        Ok(())
     }
 }
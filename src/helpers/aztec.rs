@@ -0,0 +1,602 @@
+use image::{DynamicImage, RgbaImage};
+use rgb::Rgba;
+use tracing::debug;
+
+use crate::error::ImageSlapperError;
+use crate::geometry::rectangle::Rectangle;
+use crate::rendering::draw::Drawable;
+
+use super::matrix::{GridPlacement, GridStyle, ModuleGrid, draw_grid};
+use super::reed_solomon::GaloisField;
+use super::vector::SymbolGeometry;
+
+/// Single punctuation characters of the punctuation mode, from value 6.
+const PUNCTUATION: &[u8; 25] = b"!\"#$%&'()*+,-./:;<=>?[]{}";
+/// Character pairs the punctuation mode encodes as one value, from value 2.
+const PUNCTUATION_PAIRS: [&[u8; 2]; 4] = [b"\r\n", b". ", b", ", b": "];
+/// Characters of the mixed mode from value 20, after the control characters.
+const MIXED: &[u8; 8] = b"@\\^_`|~\x7F";
+
+/// Binary shift runs are limited to this many bytes.
+const MAX_BINARY_RUN: usize = 2078;
+
+/// The character modes of the high level encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Upper,
+    Lower,
+    Mixed,
+    Punctuation,
+    Digit,
+}
+
+const MODES: [Mode; 5] = [
+    Mode::Upper,
+    Mode::Lower,
+    Mode::Mixed,
+    Mode::Punctuation,
+    Mode::Digit,
+];
+
+impl Mode {
+    /// Bits per value, 4 in digit mode and 5 in the others.
+    fn bits(self) -> usize {
+        if self == Mode::Digit { 4 } else { 5 }
+    }
+
+    /// The value of a character in this mode.
+    fn value(self, byte: u8) -> Option<u32> {
+        let value = match (self, byte) {
+            (Mode::Punctuation, b' ') => return None,
+            (_, b' ') => 1,
+            (Mode::Upper, b'A'..=b'Z') => byte - b'A' + 2,
+            (Mode::Lower, b'a'..=b'z') => byte - b'a' + 2,
+            (Mode::Mixed, 1..=13) => byte + 1,
+            (Mode::Mixed, 27..=31) => byte - 12,
+            (Mode::Mixed, _) => MIXED.iter().position(|&c| c == byte)? as u8 + 20,
+            (Mode::Punctuation, b'\r') => 1,
+            (Mode::Punctuation, _) => PUNCTUATION.iter().position(|&c| c == byte)? as u8 + 6,
+            (Mode::Digit, b'0'..=b'9') => byte - b'0' + 2,
+            (Mode::Digit, b',') => 12,
+            (Mode::Digit, b'.') => 13,
+            _ => return None,
+        };
+        Some(u32::from(value))
+    }
+
+    /// Values that latch from this mode to another, with their widths.
+    fn latch(self, to: Mode) -> Vec<(u32, usize)> {
+        use Mode::*;
+        let upper_latch = match self {
+            Upper => vec![],
+            Lower => vec![(30, 5), (14, 4)],
+            Mixed => vec![(29, 5)],
+            Punctuation => vec![(31, 5)],
+            Digit => vec![(14, 4)],
+        };
+        match (self, to) {
+            (from, to) if from == to => vec![],
+            (_, Upper) => upper_latch,
+            (Upper | Mixed, Lower) => vec![(28, 5)],
+            (Upper | Lower, Mixed) => vec![(29, 5)],
+            (Upper | Lower, Digit) => vec![(30, 5)],
+            (Mixed, Punctuation) => vec![(30, 5)],
+            (Upper | Lower, Punctuation) => vec![(29, 5), (30, 5)],
+            // Everything else goes through upper
+            (from, to) => [from.latch(Upper), Upper.latch(to)].concat(),
+        }
+    }
+}
+
+/// The size of an Aztec symbol.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AztecSize {
+    /// The smallest symbol that fits the data.
+    #[default]
+    Auto,
+    /// A compact symbol with 1 to 4 layers around a small bullseye.
+    Compact(u8),
+    /// A full range symbol with 1 to 32 layers and a reference grid.
+    Full(u8),
+}
+
+/// Marks a symbol as part of a message split over up to 26 symbols.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AztecAppend {
+    /// Position of this symbol, from 1.
+    pub position: u8,
+    /// Number of symbols, 2 to 26.
+    pub total: u8,
+    /// Identifies the symbols of one message, without spaces.
+    pub message_id: Option<String>,
+}
+
+/// Settings for Aztec codes.
+#[derive(Clone, Debug)]
+pub struct AztecConfig {
+    pub size: AztecSize,
+    /// Share of the symbol spent on error correction, the standard recommends 23%.
+    pub error_correction_percent: u8,
+    pub structured_append: Option<AztecAppend>,
+    pub foreground_color: Rgba<u8>,
+    pub background_color: Rgba<u8>,
+    /// Width of the light border in modules, Aztec codes need none.
+    pub quiet_zone: u32,
+}
+
+impl Default for AztecConfig {
+    fn default() -> Self {
+        AztecConfig {
+            size: AztecSize::Auto,
+            error_correction_percent: 23,
+            structured_append: None,
+            foreground_color: Rgba::new(0, 0, 0, 255),
+            background_color: Rgba::new(255, 255, 255, 255),
+            quiet_zone: 0,
+        }
+    }
+}
+
+/// An encoded Aztec symbol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AztecSymbol {
+    pub compact: bool,
+    pub layers: u8,
+    /// Number of data words, the rest of the symbol holds error correction.
+    pub data_words: usize,
+    pub modules: ModuleGrid,
+}
+
+/// A growing sequence of bits, most significant first.
+#[derive(Default)]
+struct Bits(Vec<bool>);
+
+impl Bits {
+    fn push(&mut self, value: u32, length: usize) {
+        self.0
+            .extend((0..length).rev().map(|bit| (value >> bit) & 1 != 0));
+    }
+}
+
+impl AztecSymbol {
+    /// Encodes the content, switching between character modes and shifting to bytes for
+    /// anything else. Text outside ASCII is encoded as UTF-8 behind an ECI.
+    pub fn encode(content: &str, config: &AztecConfig) -> Result<Self, String> {
+        let mut bits = Bits::default();
+        let mut message = Vec::new();
+        if let Some(append) = &config.structured_append {
+            if !(2..=26).contains(&append.total) {
+                return Err(format!(
+                    "structured append needs 2 to 26 symbols, got {}",
+                    append.total
+                ));
+            }
+            if append.position == 0 || append.position > append.total {
+                return Err(format!(
+                    "symbol {} is not in a sequence of {}",
+                    append.position, append.total
+                ));
+            }
+            // A latch to mixed and straight back to upper announces structured append
+            bits.push(29, 5);
+            bits.push(29, 5);
+            if let Some(id) = &append.message_id {
+                if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_graphic()) {
+                    return Err(format!("invalid structured append message ID `{id}`"));
+                }
+                message.push(b' ');
+                message.extend(id.bytes());
+                message.push(b' ');
+            }
+            message.extend([b'A' + append.position - 1, b'A' + append.total - 1]);
+        }
+        if !content.is_ascii() {
+            // FLG(2) with the digits of ECI 26, UTF-8
+            bits.push(0, 5);
+            bits.push(0, 5);
+            bits.push(2, 3);
+            bits.push(2 + 2, 4);
+            bits.push(6 + 2, 4);
+        }
+        message.extend(content.bytes());
+        high_level(&message, &mut bits);
+
+        let percent = usize::from(config.error_correction_percent.clamp(5, 95));
+        let (compact, layers, word_size, stuffed) = choose_layers(&bits.0, percent, config.size)?;
+        let data_words = stuffed.len() / word_size;
+        debug!(
+            "Encoding {} Aztec data words in a {} symbol of {} layers",
+            data_words,
+            if compact { "compact" } else { "full range" },
+            layers
+        );
+
+        let total_bits = capacity(compact, layers);
+        let message_bits = check_words(&stuffed, total_bits, word_size);
+        let mut mode = Bits::default();
+        if compact {
+            mode.push(u32::from(layers) - 1, 2);
+            mode.push(data_words as u32 - 1, 6);
+        } else {
+            mode.push(u32::from(layers) - 1, 5);
+            mode.push(data_words as u32 - 1, 11);
+        }
+        let mode_bits = check_words(&mode.0, if compact { 28 } else { 40 }, 4);
+
+        Ok(AztecSymbol {
+            compact,
+            layers,
+            data_words,
+            modules: layout(&message_bits, &mode_bits, compact, layers),
+        })
+    }
+}
+
+/// Encodes the message greedily, shifting for single characters and latching for runs.
+fn high_level(message: &[u8], bits: &mut Bits) {
+    let mut mode = Mode::Upper;
+    let mut index = 0;
+    while index < message.len() {
+        let byte = message[index];
+        let pair = message
+            .get(index..index + 2)
+            .and_then(|pair| PUNCTUATION_PAIRS.iter().position(|&p| p == pair))
+            .map(|position| position as u32 + 2);
+
+        if mode == Mode::Punctuation
+            && let Some(value) = pair
+        {
+            bits.push(value, 5);
+            index += 2;
+            continue;
+        }
+        if let Some(value) = mode.value(byte) {
+            bits.push(value, mode.bits());
+            index += 1;
+            continue;
+        }
+
+        let targets: Vec<Mode> = MODES
+            .into_iter()
+            .filter(|target| target.value(byte).is_some())
+            .collect();
+        if targets.is_empty() {
+            // Binary shift, possible from the upper, lower and mixed modes
+            if matches!(mode, Mode::Punctuation | Mode::Digit) {
+                for (value, width) in mode.latch(Mode::Upper) {
+                    bits.push(value, width);
+                }
+                mode = Mode::Upper;
+            }
+            let run = message[index..]
+                .iter()
+                .take(MAX_BINARY_RUN)
+                .take_while(|&&byte| MODES.iter().all(|mode| mode.value(byte).is_none()))
+                .count();
+            bits.push(31, 5);
+            if run <= 31 {
+                bits.push(run as u32, 5);
+            } else {
+                bits.push(0, 5);
+                bits.push(run as u32 - 31, 11);
+            }
+            for &byte in &message[index..index + run] {
+                bits.push(u32::from(byte), 8);
+            }
+            index += run;
+            continue;
+        }
+
+        // Shift for a lone character when the one after it fits the current mode again
+        let skip = if pair.is_some() { 2 } else { 1 };
+        let returns = message
+            .get(index + skip)
+            .is_none_or(|&next| mode.value(next).is_some());
+        if returns && mode != Mode::Punctuation && targets.contains(&Mode::Punctuation) {
+            bits.push(0, mode.bits());
+            match pair {
+                Some(value) => bits.push(value, 5),
+                None => bits.push(Mode::Punctuation.value(byte).unwrap_or(0), 5),
+            }
+            index += skip;
+            continue;
+        }
+        if returns && byte.is_ascii_uppercase() && matches!(mode, Mode::Lower | Mode::Digit) {
+            bits.push(if mode == Mode::Lower { 28 } else { 15 }, mode.bits());
+            bits.push(Mode::Upper.value(byte).unwrap_or(0), 5);
+            index += 1;
+            continue;
+        }
+
+        let cost =
+            |target: &Mode| -> usize { mode.latch(*target).iter().map(|(_, width)| width).sum() };
+        let target = targets.into_iter().min_by_key(cost).unwrap_or(Mode::Upper);
+        for (value, width) in mode.latch(target) {
+            bits.push(value, width);
+        }
+        mode = target;
+    }
+}
+
+/// Bits in the data layers of a symbol.
+fn capacity(compact: bool, layers: u8) -> usize {
+    let layers = usize::from(layers);
+    ((if compact { 88 } else { 112 }) + 16 * layers) * layers
+}
+
+/// Bits per word for a number of layers.
+fn word_size(layers: u8) -> usize {
+    match layers {
+        1..=2 => 6,
+        3..=8 => 8,
+        9..=22 => 10,
+        _ => 12,
+    }
+}
+
+/// Finds the smallest symbol, or checks the configured one, that holds the data and the
+/// requested error correction. Returns it with its word size and the stuffed data.
+fn choose_layers(
+    bits: &[bool],
+    percent: usize,
+    size: AztecSize,
+) -> Result<(bool, u8, usize, Vec<bool>), String> {
+    let candidates: Vec<(bool, u8)> = match size {
+        AztecSize::Auto => (1..=4)
+            .map(|layers| (true, layers))
+            .chain((4..=32).map(|layers| (false, layers)))
+            .collect(),
+        AztecSize::Compact(layers @ 1..=4) => vec![(true, layers)],
+        AztecSize::Full(layers @ 1..=32) => vec![(false, layers)],
+        _ => return Err(format!("{size:?} is not an Aztec size")),
+    };
+
+    let error_bits = bits.len() * percent / 100 + 11;
+    for (compact, layers) in candidates {
+        let total = capacity(compact, layers);
+        if bits.len() + error_bits > total {
+            continue;
+        }
+        let word_size = word_size(layers);
+        let stuffed = stuff(bits, word_size);
+        let usable = total - total % word_size;
+        let words = stuffed.len() / word_size;
+        if (compact && words > 64) || words > 2048 {
+            continue;
+        }
+        if stuffed.len() + error_bits <= usable {
+            return Ok((compact, layers, word_size, stuffed));
+        }
+    }
+    Err(format!(
+        "{} bits of data do not fit in an Aztec code of {size:?}",
+        bits.len()
+    ))
+}
+
+/// Splits bits into words, padding with ones and keeping any word from being all zeros or all
+/// ones by turning its last bit into the opposite of the others.
+fn stuff(bits: &[bool], word_size: usize) -> Vec<bool> {
+    let mut out = Bits::default();
+    let mask = (1 << word_size) - 2;
+    let mut index = 0;
+    while index < bits.len() {
+        let mut word = 0;
+        for bit in 0..word_size {
+            if bits.get(index + bit).is_none_or(|&set| set) {
+                word |= 1 << (word_size - 1 - bit);
+            }
+        }
+        if word & mask == mask {
+            out.push(word & mask, word_size);
+            index += word_size - 1;
+        } else if word & mask == 0 {
+            out.push(word | 1, word_size);
+            index += word_size - 1;
+        } else {
+            out.push(word, word_size);
+            index += word_size;
+        }
+    }
+    out.0
+}
+
+/// Appends Reed-Solomon check words filling `total_bits`, with any leftover bits as zeros in
+/// front.
+fn check_words(bits: &[bool], total_bits: usize, word_size: usize) -> Vec<bool> {
+    let field = match word_size {
+        4 => GaloisField::new(16, 0x13, 1),
+        6 => GaloisField::new(64, 0x43, 1),
+        8 => GaloisField::new(256, 0x12D, 1),
+        10 => GaloisField::new(1024, 0x409, 1),
+        _ => GaloisField::new(4096, 0x1069, 1),
+    };
+    let words: Vec<u16> = bits
+        .chunks(word_size)
+        .map(|word| {
+            word.iter()
+                .fold(0, |value, &bit| value << 1 | u16::from(bit))
+        })
+        .collect();
+    let total_words = total_bits / word_size;
+    let check = field.check_words(&words, total_words - words.len());
+
+    let mut out = Bits::default();
+    out.push(0, total_bits % word_size);
+    for word in words.into_iter().chain(check) {
+        out.push(u32::from(word), word_size);
+    }
+    out.0
+}
+
+/// Arranges the data layers in a spiral around the bullseye, with the mode message and, in
+/// full range symbols, the reference grid.
+fn layout(message: &[bool], mode: &[bool], compact: bool, layers: u8) -> ModuleGrid {
+    let layers = usize::from(layers);
+    let base_size = if compact { 11 } else { 14 } + layers * 4;
+    // Positions of the data rows and columns, skipping the reference grid lines
+    let (size, alignment): (usize, Vec<usize>) = if compact {
+        (base_size, (0..base_size).collect())
+    } else {
+        let size = base_size + 1 + 2 * ((base_size / 2 - 1) / 15);
+        let (original_center, center) = (base_size / 2, size / 2);
+        let mut alignment = vec![0; base_size];
+        for i in 0..original_center {
+            let offset = i + i / 15;
+            alignment[original_center - i - 1] = center - offset - 1;
+            alignment[original_center + i] = center + offset + 1;
+        }
+        (size, alignment)
+    };
+    let mut grid = ModuleGrid::new(size, size);
+    let bit = |index: usize| message.get(index).copied().unwrap_or(false);
+
+    let mut row_offset = 0;
+    for i in 0..layers {
+        let row_size = (layers - i) * 4 + if compact { 9 } else { 12 };
+        let (near, far) = (i * 2, base_size - 1 - i * 2);
+        for j in 0..row_size {
+            let column_offset = j * 2;
+            for k in 0..2 {
+                let index = row_offset + column_offset + k;
+                if bit(index) {
+                    grid.set(alignment[near + k], alignment[near + j], true);
+                }
+                if bit(index + row_size * 2) {
+                    grid.set(alignment[near + j], alignment[far - k], true);
+                }
+                if bit(index + row_size * 4) {
+                    grid.set(alignment[far - k], alignment[far - j], true);
+                }
+                if bit(index + row_size * 6) {
+                    grid.set(alignment[far - j], alignment[near + k], true);
+                }
+            }
+        }
+        row_offset += row_size * 8;
+    }
+
+    // Mode message around the bullseye, clockwise from the top left
+    let center = size / 2;
+    let mode_bit = |index: usize| mode.get(index).copied().unwrap_or(false);
+    if compact {
+        for i in 0..7 {
+            let offset = center - 3 + i;
+            grid.set(offset, center - 5, mode_bit(i));
+            grid.set(center + 5, offset, mode_bit(i + 7));
+            grid.set(offset, center + 5, mode_bit(20 - i));
+            grid.set(center - 5, offset, mode_bit(27 - i));
+        }
+    } else {
+        for i in 0..10 {
+            let offset = center - 5 + i + i / 5;
+            grid.set(offset, center - 7, mode_bit(i));
+            grid.set(center + 7, offset, mode_bit(i + 10));
+            grid.set(offset, center + 7, mode_bit(29 - i));
+            grid.set(center - 7, offset, mode_bit(39 - i));
+        }
+    }
+
+    // Bullseye rings with orientation marks in three corners
+    let rings = if compact { 5 } else { 7 };
+    for ring in (0..rings).step_by(2) {
+        for j in center - ring..=center + ring {
+            grid.set(j, center - ring, true);
+            grid.set(j, center + ring, true);
+            grid.set(center - ring, j, true);
+            grid.set(center + ring, j, true);
+        }
+    }
+    for (x, y) in [
+        (center - rings, center - rings),
+        (center - rings + 1, center - rings),
+        (center - rings, center - rings + 1),
+        (center + rings, center - rings),
+        (center + rings, center - rings + 1),
+        (center + rings, center + rings - 1),
+    ] {
+        grid.set(x, y, true);
+    }
+
+    // Reference grid lines every 16 modules from the center
+    if !compact {
+        let mut line = 0;
+        while line * 15 < base_size / 2 - 1 {
+            let distance = line * 16;
+            for k in (center & 1..size).step_by(2) {
+                grid.set(center - distance, k, true);
+                grid.set(center + distance, k, true);
+                grid.set(k, center - distance, true);
+                grid.set(k, center + distance, true);
+            }
+            line += 1;
+        }
+    }
+    grid
+}
+
+/// An Aztec code drawn into a field with whole-pixel modules.
+#[derive(Clone, Debug)]
+pub struct Aztec {
+    /// The encoded text.
+    pub content: String,
+    pub symbol: AztecSymbol,
+    pub config: AztecConfig,
+    pub field: Rectangle,
+}
+
+impl Aztec {
+    /// Encodes the content for drawing in the field.
+    pub fn new(content: &str, field: Rectangle, config: AztecConfig) -> Result<Self, String> {
+        Ok(Aztec {
+            content: content.to_string(),
+            symbol: AztecSymbol::encode(content, &config)?,
+            config,
+            field,
+        })
+    }
+
+    /// Draws the symbol as large as fits in the field, returning where it was placed.
+    pub fn draw_checked(&self, image: &mut RgbaImage) -> Result<GridPlacement, String> {
+        let style = GridStyle {
+            foreground_color: self.config.foreground_color,
+            background_color: self.config.background_color,
+            quiet_zone: self.config.quiet_zone,
+            row_height: 1,
+        };
+        draw_grid(image, &self.symbol.modules, &self.field, &style)
+    }
+
+    /// Returns the symbol with its quiet zone as rectangles in module units.
+    pub fn geometry(&self) -> SymbolGeometry {
+        self.symbol.modules.geometry(self.config.quiet_zone, 1)
+    }
+}
+
+impl Drawable for Aztec {
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError> {
+        let mut img = image.to_rgba8();
+        self.draw_checked(&mut img)
+            .map_err(|error| ImageSlapperError::barcode("Aztec code", error))?;
+        *image = DynamicImage::ImageRgba8(img);
+        Ok(())
+    }
+}
+
+/// Generates an Aztec code image of the given size, with the symbol as large as fits.
+pub fn from(
+    content: &str,
+    width: u32,
+    height: u32,
+    config: AztecConfig,
+) -> Result<DynamicImage, String> {
+    let background = config.background_color;
+    let code = Aztec::new(content, Rectangle::from_xywh(0, 0, width, height), config)?;
+    let mut image = RgbaImage::from_pixel(
+        width,
+        height,
+        image::Rgba([background.r, background.g, background.b, background.a]),
+    );
+    code.draw_checked(&mut image)?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
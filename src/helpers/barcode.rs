@@ -0,0 +1,924 @@
+use image::{DynamicImage, RgbaImage};
+use rgb::Rgba;
+
+use crate::error::ImageSlapperError;
+use crate::geometry::rectangle::Rectangle;
+use crate::rendering::draw::Drawable;
+
+use super::code128::Code128;
+use super::linear::{
+    HumanReadable, LinearOptions, LinearPlacement, TextPosition, draw_modules, fill,
+};
+use super::vector::{ModuleRect, SymbolGeometry};
+
+/// Modules in a wide element of Code39, ITF and Codabar, three times a narrow one.
+const WIDE: usize = 3;
+
+/// EAN/UPC left-hand odd parity (L) patterns, by digit. Right-hand patterns are their
+/// complement and even parity (G) patterns the reverse of those.
+const EAN_L: [&str; 10] = [
+    "0001101", "0011001", "0010011", "0111101", "0100011", "0110001", "0101111", "0111011",
+    "0110111", "0001011",
+];
+
+/// Parity of the six left-hand digits of EAN-13, selected by the first digit. `G` is even.
+const EAN13_PARITY: [&str; 10] = [
+    "LLLLLL", "LLGLGG", "LLGGLG", "LLGGGL", "LGLLGG", "LGGLLG", "LGGGLL", "LGLGLG", "LGLGGL",
+    "LGGLGL",
+];
+
+/// Parity of the six UPC-E digits for number system 0, selected by the check digit.
+const UPCE_PARITY: [&str; 10] = [
+    "GGGLLL", "GGLGLL", "GGLLGL", "GGLLLG", "GLGGLL", "GLLGGL", "GLLLGG", "GLGLGL", "GLGLLG",
+    "GLLGLG",
+];
+
+/// Code39 characters in check value order.
+const CODE39_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%";
+
+/// Code39 patterns in `CODE39_CHARS` order, five bars and four spaces with 1 for wide.
+const CODE39_PATTERNS: [&str; 43] = [
+    "000110100",
+    "100100001",
+    "001100001",
+    "101100000",
+    "000110001",
+    "100110000",
+    "001110000",
+    "000100101",
+    "100100100",
+    "001100100",
+    "100001001",
+    "001001001",
+    "101001000",
+    "000011001",
+    "100011000",
+    "001011000",
+    "000001101",
+    "100001100",
+    "001001100",
+    "000011100",
+    "100000011",
+    "001000011",
+    "101000010",
+    "000010011",
+    "100010010",
+    "001010010",
+    "000000111",
+    "100000110",
+    "001000110",
+    "000010110",
+    "110000001",
+    "011000001",
+    "111000000",
+    "010010001",
+    "110010000",
+    "011010000",
+    "010000101",
+    "110000100",
+    "011000100",
+    "010101000",
+    "010100010",
+    "010001010",
+    "000101010",
+];
+
+/// Code39 start and stop character `*`.
+const CODE39_START_STOP: &str = "010010100";
+
+/// Code93 patterns, three bars and three spaces of one to four modules each. The first 43 are
+/// the characters of `CODE39_CHARS`, followed by the shifts `($)`, `(%)`, `(/)` and `(+)`.
+const CODE93_PATTERNS: [&str; 47] = [
+    "131112", "111213", "111312", "111411", "121113", "121212", "121311", "111114", "131211",
+    "141111", "211113", "211212", "211311", "221112", "221211", "231111", "112113", "112212",
+    "112311", "122112", "132111", "111123", "111222", "111321", "121122", "131121", "212112",
+    "212211", "211122", "211221", "221121", "222111", "112122", "112221", "122121", "123111",
+    "121131", "311112", "311211", "321111", "112131", "113121", "211131", "121221", "312111",
+    "311121", "122211",
+];
+
+/// Code93 start and stop character.
+const CODE93_START_STOP: &str = "111141";
+
+/// Interleaved 2 of 5 patterns by digit, five elements with 1 for wide.
+const ITF_PATTERNS: [&str; 10] = [
+    "00110", "10001", "01001", "11000", "00101", "10100", "01100", "00011", "10010", "01010",
+];
+
+/// Codabar characters, with the start and stop characters last.
+const CODABAR_CHARS: &str = "0123456789-$:/.+ABCD";
+
+/// Codabar patterns in `CODABAR_CHARS` order, four bars and three spaces with 1 for wide.
+const CODABAR_PATTERNS: [&str; 20] = [
+    "0000011", "0000110", "0001001", "1100000", "0010010", "1000010", "0100001", "0100100",
+    "0110000", "1001000", "0001100", "0011000", "1000101", "1010001", "1010100", "0010101",
+    "0011010", "0101001", "0001011", "0001110",
+];
+
+/// Linear barcode symbologies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symbology {
+    /// Full ASCII, compacting digit pairs.
+    Code128,
+    /// Code128 with GS1 element strings such as `(01)09501101530003(10)ABC`.
+    Gs1Code128,
+    /// 12 digits and a check digit, the retail standard outside North America.
+    Ean13,
+    /// 7 digits and a check digit, for small packages.
+    Ean8,
+    /// 11 digits and a check digit, the North American retail standard.
+    UpcA,
+    /// A zero-suppressed UPC-A: a number system of 0 or 1, 6 digits and a check digit. A
+    /// UPC-A number is accepted too when its zeros can be suppressed.
+    UpcE,
+    /// Digits, uppercase and `-. $/+%`, with an optional modulo 43 check character. With
+    /// `full_ascii` every ASCII character is encoded, using two characters for the others.
+    Code39 { check_digit: bool, full_ascii: bool },
+    /// Full ASCII with two check characters, more compact than Code39.
+    Code93,
+    /// A GTIN-14 of 13 digits and a check digit in Interleaved 2 of 5, for shipping cartons.
+    /// Bearer bars above and below keep a scanner from reading a partial symbol.
+    Itf14 { bearer_bars: bool },
+    /// Digits and `-$:/.+` between start and stop characters A to D, which default to A.
+    Codabar,
+}
+
+impl Symbology {
+    /// Minimum light margins left and right of the symbol, in modules.
+    pub fn quiet_zones(&self) -> (u32, u32) {
+        match self {
+            Symbology::Ean13 => (11, 7),
+            Symbology::Ean8 => (7, 7),
+            Symbology::UpcA => (9, 9),
+            Symbology::UpcE => (9, 7),
+            _ => (10, 10),
+        }
+    }
+}
+
+/// Human-readable text of EAN and UPC symbols: digit groups with their ranges in modules from
+/// the first bar, and the guard bars that extend down between them.
+struct RetailText {
+    groups: Vec<(String, i32, i32)>,
+    guards: &'static [(usize, usize)],
+}
+
+/// A linear barcode of any symbology, drawn into its field with whole-pixel modules.
+#[derive(Clone, Debug)]
+pub struct Barcode<'a> {
+    pub symbology: Symbology,
+    /// The encoded data with computed check digits, as printed under the bars.
+    pub text: String,
+    /// The symbol from the first bar to the last, 1 for bar and 0 for space.
+    pub modules: Vec<u8>,
+    pub field: Rectangle,
+    pub options: LinearOptions,
+    /// Light margin on both sides in modules, `None` for the symbology's minimum.
+    pub quiet_zone: Option<u32>,
+    /// Text printed with the bars, `None` for bars only.
+    pub human_readable: Option<HumanReadable<'a>>,
+}
+
+impl<'a> Barcode<'a> {
+    /// Validates and encodes the data, computing check digits the symbology needs. Data that
+    /// already ends in its check digit has it verified.
+    pub fn new(symbology: Symbology, data: &str, field: Rectangle) -> Result<Self, String> {
+        let (text, modules) = match symbology {
+            Symbology::Code128 => (data.to_string(), Code128::new(data)?.modules()),
+            Symbology::Gs1Code128 => (data.to_string(), Code128::gs1(data)?.modules()),
+            Symbology::Ean13 => {
+                let digits = gtin(data, 13, "EAN-13")?;
+                let modules = ean13(&digits);
+                (to_text(&digits), modules)
+            }
+            Symbology::Ean8 => {
+                let digits = gtin(data, 8, "EAN-8")?;
+                let modules = ean8(&digits);
+                (to_text(&digits), modules)
+            }
+            Symbology::UpcA => {
+                let digits = gtin(data, 12, "UPC-A")?;
+                let mut ean = vec![0];
+                ean.extend(&digits);
+                (to_text(&digits), ean13(&ean))
+            }
+            Symbology::UpcE => {
+                let digits = upc_e(data)?;
+                let modules = upc_e_modules(&digits);
+                (to_text(&digits), modules)
+            }
+            Symbology::Code39 {
+                check_digit,
+                full_ascii,
+            } => code39(data, check_digit, full_ascii)?,
+            Symbology::Code93 => code93(data)?,
+            Symbology::Itf14 { .. } => {
+                let digits = gtin(data, 14, "ITF-14")?;
+                let modules = itf(&digits);
+                (to_text(&digits), modules)
+            }
+            Symbology::Codabar => codabar(data)?,
+        };
+
+        Ok(Barcode {
+            symbology,
+            text,
+            modules,
+            field,
+            options: LinearOptions::default(),
+            quiet_zone: None,
+            human_readable: None,
+        })
+    }
+
+    /// Adds human-readable text, following the standard placement for EAN and UPC symbols when
+    /// it goes below the bars.
+    pub fn with_text(mut self, human_readable: HumanReadable<'a>) -> Self {
+        self.human_readable = Some(human_readable);
+        self
+    }
+
+    /// The data a scanner reports for the symbol, e.g. full ASCII Code39 without the decoding
+    /// of its two-character sequences and Codabar without its start and stop characters.
+    pub fn payload(&self) -> String {
+        match self.symbology {
+            Symbology::Code39 {
+                full_ascii: true,
+                check_digit,
+            } => {
+                // The check character is a plain Code39 character, never a sequence
+                let mut data = self.text.clone();
+                let check = check_digit.then(|| data.pop()).flatten();
+                data.chars()
+                    .flat_map(|ch| {
+                        let basic =
+                            ch.is_ascii_digit() || ch.is_ascii_uppercase() || "-. ".contains(ch);
+                        match full_ascii(ch) {
+                            Some((shift, second)) if !basic => vec![shift, second],
+                            _ => vec![ch],
+                        }
+                    })
+                    .chain(check)
+                    .collect()
+            }
+            Symbology::Codabar => self.text[1..self.text.len() - 1].to_string(),
+            _ => self.text.clone(),
+        }
+    }
+
+    /// Returns the quiet zones in modules on the left and right.
+    pub fn quiet_zones(&self) -> (u32, u32) {
+        match self.quiet_zone {
+            Some(modules) => (modules, modules),
+            None => self.symbology.quiet_zones(),
+        }
+    }
+
+    /// Returns the bars, quiet zones and bearer bars as rectangles in module units, with bars
+    /// `height` modules high. Human-readable text is not part of the geometry.
+    pub fn geometry(&self, height: f32) -> SymbolGeometry {
+        let mut geometry = SymbolGeometry::from_modules(&self.modules, height, self.quiet_zones());
+        if let Symbology::Itf14 { bearer_bars: true } = self.symbology {
+            let thickness = (WIDE * 2) as f32;
+            for y in [0.0, (height - thickness).max(0.0)] {
+                geometry.rects.push(ModuleRect {
+                    x: 0.0,
+                    y,
+                    width: geometry.width,
+                    height: thickness.min(height),
+                });
+            }
+        }
+        geometry
+    }
+
+    /// Draws the barcode and its quiet zones as large as fits in the field, returning where the
+    /// modules were placed.
+    pub fn draw_checked(&self, image: &mut RgbaImage) -> Result<LinearPlacement, String> {
+        let (left, right) = self.quiet_zones();
+        let mut modules = vec![0; left as usize];
+        modules.extend(&self.modules);
+        modules.extend(std::iter::repeat_n(0, right as usize));
+
+        let Some(human_readable) = &self.human_readable else {
+            let placement = draw_modules(image, &modules, &self.field, &self.options)?;
+            self.draw_bearer_bars(image, &placement, &self.field);
+            return Ok(placement);
+        };
+
+        let area =
+            self.field
+                .intersection(&Rectangle::from_xywh(0, 0, image.width(), image.height()));
+        let placement = self.options.fit(modules.len(), area.x1, area.width)?;
+        let module_width = placement.module_width as f32;
+        let origin = (placement.x + left * placement.module_width) as f32;
+        let to_pixels = |modules: i32| origin + modules as f32 * module_width;
+
+        let below = human_readable.position == TextPosition::Below;
+        let retail = self.retail_text().filter(|_| below);
+        let groups = match &retail {
+            Some(retail) => retail.groups.clone(),
+            None => vec![(self.text.clone(), 0, self.modules.len() as i32)],
+        };
+        let segments: Vec<(&str, f32)> = groups
+            .iter()
+            .map(|(text, start, end)| (text.as_str(), to_pixels(*end) - to_pixels(*start)))
+            .collect();
+        let size = human_readable.fit(&segments, area.height as f32 / 3.0);
+        let all: String = groups.iter().map(|(text, _, _)| text.as_str()).collect();
+        let text_height = human_readable.ink_height(&all, size).ceil() as u32;
+        let gap = human_readable.gap * placement.module_width;
+
+        // Bars take what the text leaves of the field
+        let (bars, text_top) = if below {
+            let text_top = area.y2.saturating_sub(text_height);
+            let bottom = text_top.saturating_sub(gap).max(area.y1);
+            (Rectangle::new(area.x1, area.y1, area.x2, bottom), text_top)
+        } else {
+            let top = (area.y1 + text_height + gap).min(area.y2);
+            (Rectangle::new(area.x1, top, area.x2, area.y2), area.y1)
+        };
+        if let Some(background) = self.options.background {
+            fill(image, area.x1, area.y1, area.x2, area.y2, background);
+        }
+        draw_modules(image, &modules, &bars, &self.options)?;
+        self.draw_bearer_bars(image, &placement, &bars);
+
+        if let Some(retail) = &retail {
+            // Guard bars reach halfway down the digits
+            let bottom = text_top + text_height / 2;
+            for &(start, end) in retail.guards {
+                for index in (start..end).filter(|&index| self.modules[index] != 0) {
+                    let x = to_pixels(index as i32) as u32;
+                    fill(
+                        image,
+                        x,
+                        bars.y2,
+                        x + placement.module_width,
+                        bottom,
+                        self.options.foreground,
+                    );
+                }
+            }
+        }
+        for (text, start, end) in &groups {
+            human_readable.draw(
+                image,
+                text,
+                size,
+                (to_pixels(*start), to_pixels(*end)),
+                text_top as f32,
+                self.options.foreground,
+            );
+        }
+
+        Ok(placement)
+    }
+
+    /// Draws ITF-14 bearer bars above and below the bars in `area`, when enabled.
+    fn draw_bearer_bars(
+        &self,
+        image: &mut RgbaImage,
+        placement: &LinearPlacement,
+        area: &Rectangle,
+    ) {
+        let Symbology::Itf14 { bearer_bars: true } = self.symbology else {
+            return;
+        };
+        // Bearer bars are twice as thick as a wide bar and span the quiet zones
+        let thickness = placement.module_width * WIDE as u32 * 2;
+        let area = area.intersection(&Rectangle::from_xywh(0, 0, image.width(), image.height()));
+        let (x1, x2) = (placement.x, placement.x + placement.width);
+        let color = self.options.foreground;
+        fill(image, x1, area.y1, x2, area.y1 + thickness, color);
+        fill(
+            image,
+            x1,
+            area.y2.saturating_sub(thickness),
+            x2,
+            area.y2,
+            color,
+        );
+    }
+
+    /// Lays out the digits of EAN and UPC symbols: the first digit left of the bars, groups
+    /// between the guards, and for UPC the check digit right of the bars.
+    fn retail_text(&self) -> Option<RetailText> {
+        let digits = |range: std::ops::Range<usize>| self.text[range].to_string();
+        let retail = match self.symbology {
+            Symbology::Ean13 => RetailText {
+                groups: vec![
+                    (digits(0..1), -8, -1),
+                    (digits(1..7), 4, 44),
+                    (digits(7..13), 51, 91),
+                ],
+                guards: &[(0, 3), (45, 50), (92, 95)],
+            },
+            Symbology::Ean8 => RetailText {
+                groups: vec![(digits(0..4), 4, 30), (digits(4..8), 37, 63)],
+                guards: &[(0, 3), (31, 36), (64, 67)],
+            },
+            // The bars of the first and last digit are as long as the guards
+            Symbology::UpcA => RetailText {
+                groups: vec![
+                    (digits(0..1), -8, -1),
+                    (digits(1..6), 11, 44),
+                    (digits(6..11), 51, 84),
+                    (digits(11..12), 96, 103),
+                ],
+                guards: &[(0, 10), (45, 50), (85, 95)],
+            },
+            Symbology::UpcE => RetailText {
+                groups: vec![
+                    (digits(0..1), -8, -1),
+                    (digits(1..7), 4, 44),
+                    (digits(7..8), 52, 58),
+                ],
+                guards: &[(0, 3), (45, 51)],
+            },
+            _ => return None,
+        };
+        Some(retail)
+    }
+}
+
+impl Drawable for Barcode<'_> {
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError> {
+        let mut img = image.to_rgba8();
+        self.draw_checked(&mut img).map_err(|error| {
+            ImageSlapperError::barcode(format!("{:?} barcode", self.symbology), error)
+        })?;
+        *image = DynamicImage::ImageRgba8(img);
+        Ok(())
+    }
+}
+
+/// Generates a barcode image of any symbology, filling the space between the margins.
+pub fn from(
+    symbology: Symbology,
+    data: &str,
+    width: u32,
+    height: u32,
+    margin: u32,
+) -> Result<DynamicImage, String> {
+    render(symbology, data, width, height, margin, None)
+}
+
+/// Generates a barcode image with human-readable text, filling the space between the margins.
+pub fn from_with_text(
+    symbology: Symbology,
+    data: &str,
+    width: u32,
+    height: u32,
+    margin: u32,
+    human_readable: HumanReadable,
+) -> Result<DynamicImage, String> {
+    render(symbology, data, width, height, margin, Some(human_readable))
+}
+
+fn render(
+    symbology: Symbology,
+    data: &str,
+    width: u32,
+    height: u32,
+    margin: u32,
+    human_readable: Option<HumanReadable>,
+) -> Result<DynamicImage, String> {
+    if width <= margin * 2 || height <= margin * 2 {
+        return Err("margins leave no room for the barcode".to_string());
+    }
+    let field = Rectangle::new(margin, margin, width - margin, height - margin);
+    let mut barcode = Barcode::new(symbology, data, field)?;
+    barcode.human_readable = human_readable;
+
+    let background = barcode
+        .options
+        .background
+        .unwrap_or(Rgba::new(255, 255, 255, 255));
+    let mut canvas = RgbaImage::from_pixel(
+        width,
+        height,
+        image::Rgba([background.r, background.g, background.b, background.a]),
+    );
+    barcode.draw_checked(&mut canvas)?;
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+/// Computes the GS1 modulo 10 check digit, weighting digits 3 and 1 from the right.
+pub fn gs1_check_digit(digits: &[u8]) -> u8 {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, &digit)| u32::from(digit) * if index % 2 == 0 { 3 } else { 1 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+/// Parses a GS1 number of `length` digits, computing the check digit when it is left off.
+fn gtin(data: &str, length: usize, name: &str) -> Result<Vec<u8>, String> {
+    let mut digits = parse_digits(data, name)?;
+    if digits.len() == length - 1 {
+        digits.push(gs1_check_digit(&digits));
+    } else if digits.len() == length {
+        verify_check_digit(&digits, name)?;
+    } else {
+        return Err(format!(
+            "{name} needs {} or {length} digits, got {}",
+            length - 1,
+            digits.len()
+        ));
+    }
+    Ok(digits)
+}
+
+fn parse_digits(data: &str, name: &str) -> Result<Vec<u8>, String> {
+    data.bytes()
+        .map(|byte| byte.is_ascii_digit().then(|| byte - b'0'))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| format!("{name} can only encode digits, got `{data}`"))
+}
+
+fn verify_check_digit(digits: &[u8], name: &str) -> Result<(), String> {
+    let (&check, body) = digits.split_last().ok_or("no digits")?;
+    let expected = gs1_check_digit(body);
+    if check != expected {
+        return Err(format!(
+            "{name} check digit is {check}, expected {expected}"
+        ));
+    }
+    Ok(())
+}
+
+fn to_text(digits: &[u8]) -> String {
+    digits
+        .iter()
+        .map(|&digit| char::from(b'0' + digit))
+        .collect()
+}
+
+/// Appends an EAN/UPC digit with odd (`L`), even (`G`) or right-hand (`R`) parity.
+fn push_ean_digit(modules: &mut Vec<u8>, digit: u8, parity: char) {
+    let pattern = EAN_L[digit as usize].bytes().map(|bit| bit - b'0');
+    match parity {
+        'L' => modules.extend(pattern),
+        'R' => modules.extend(pattern.map(|bit| 1 - bit)),
+        _ => modules.extend(pattern.rev().map(|bit| 1 - bit)),
+    }
+}
+
+fn ean13(digits: &[u8]) -> Vec<u8> {
+    let mut modules = vec![1, 0, 1];
+    for (&digit, parity) in digits[1..7]
+        .iter()
+        .zip(EAN13_PARITY[digits[0] as usize].chars())
+    {
+        push_ean_digit(&mut modules, digit, parity);
+    }
+    modules.extend([0, 1, 0, 1, 0]);
+    for &digit in &digits[7..] {
+        push_ean_digit(&mut modules, digit, 'R');
+    }
+    modules.extend([1, 0, 1]);
+    modules
+}
+
+fn ean8(digits: &[u8]) -> Vec<u8> {
+    let mut modules = vec![1, 0, 1];
+    for &digit in &digits[..4] {
+        push_ean_digit(&mut modules, digit, 'L');
+    }
+    modules.extend([0, 1, 0, 1, 0]);
+    for &digit in &digits[4..] {
+        push_ean_digit(&mut modules, digit, 'R');
+    }
+    modules.extend([1, 0, 1]);
+    modules
+}
+
+/// Parses UPC-E data into number system, six digits and check digit. Accepts the six digits
+/// alone (number system 0), with the number system, with the check digit as well, or the
+/// UPC-A number they stand for.
+fn upc_e(data: &str) -> Result<Vec<u8>, String> {
+    let digits = parse_digits(data, "UPC-E")?;
+    let mut digits = match digits.len() {
+        6 => [&[0], &digits[..]].concat(),
+        7 | 8 => digits,
+        11 | 12 => {
+            let mut compressed = compress_upc_a(&digits[..11])
+                .ok_or_else(|| format!("UPC-A `{data}` cannot be zero-suppressed into UPC-E"))?;
+            if let Some(&check) = digits.get(11) {
+                compressed.push(check);
+            }
+            compressed
+        }
+        count => {
+            return Err(format!(
+                "UPC-E needs 6 to 8 or 11 to 12 digits, got {count}"
+            ));
+        }
+    };
+    if digits[0] > 1 {
+        return Err(format!(
+            "UPC-E number system must be 0 or 1, got {}",
+            digits[0]
+        ));
+    }
+
+    let expected = gs1_check_digit(&expand_upc_e(&digits[..7]));
+    match digits.get(7) {
+        Some(&check) if check != expected => {
+            return Err(format!("UPC-E check digit is {check}, expected {expected}"));
+        }
+        Some(_) => {}
+        None => digits.push(expected),
+    }
+    Ok(digits)
+}
+
+/// Expands a number system and six UPC-E digits into the 11 digits of the UPC-A number.
+fn expand_upc_e(digits: &[u8]) -> Vec<u8> {
+    let (system, d) = (digits[0], &digits[1..7]);
+    let body: [u8; 10] = match d[5] {
+        0..=2 => [d[0], d[1], d[5], 0, 0, 0, 0, d[2], d[3], d[4]],
+        3 => [d[0], d[1], d[2], 0, 0, 0, 0, 0, d[3], d[4]],
+        4 => [d[0], d[1], d[2], d[3], 0, 0, 0, 0, 0, d[4]],
+        _ => [d[0], d[1], d[2], d[3], d[4], 0, 0, 0, 0, d[5]],
+    };
+    [&[system], &body[..]].concat()
+}
+
+/// Zero-suppresses the 11 digits of a UPC-A number, if it has the zeros to drop.
+fn compress_upc_a(digits: &[u8]) -> Option<Vec<u8>> {
+    let (system, x) = (digits[0], &digits[1..11]);
+    if system > 1 {
+        return None;
+    }
+    let six = if x[2] <= 2 && x[3..7] == [0, 0, 0, 0] {
+        [x[0], x[1], x[7], x[8], x[9], x[2]]
+    } else if x[3..8] == [0, 0, 0, 0, 0] {
+        [x[0], x[1], x[2], x[8], x[9], 3]
+    } else if x[4..9] == [0, 0, 0, 0, 0] {
+        [x[0], x[1], x[2], x[3], x[9], 4]
+    } else if x[5..9] == [0, 0, 0, 0] && x[9] >= 5 {
+        [x[0], x[1], x[2], x[3], x[4], x[9]]
+    } else {
+        return None;
+    };
+    Some([&[system], &six[..]].concat())
+}
+
+/// Encodes the six digits of a UPC-E symbol, with parity carrying the number system and
+/// check digit.
+fn upc_e_modules(digits: &[u8]) -> Vec<u8> {
+    let (system, check) = (digits[0], digits[7]);
+    let mut modules = vec![1, 0, 1];
+    for (&digit, parity) in digits[1..7].iter().zip(UPCE_PARITY[check as usize].chars()) {
+        // Number system 1 uses the opposite parities
+        let parity = match (system, parity) {
+            (1, 'G') => 'L',
+            (1, _) => 'G',
+            _ => parity,
+        };
+        push_ean_digit(&mut modules, digit, parity);
+    }
+    modules.extend([0, 1, 0, 1, 0, 1]);
+    modules
+}
+
+/// Appends elements alternating between bar and space, given as widths in modules.
+fn push_widths(modules: &mut Vec<u8>, widths: impl IntoIterator<Item = usize>) {
+    for (index, width) in widths.into_iter().enumerate() {
+        let module = if index % 2 == 0 { 1 } else { 0 };
+        modules.extend(std::iter::repeat_n(module, width));
+    }
+}
+
+/// Widths of a wide and narrow pattern, `1` standing for wide.
+fn wide_narrow(pattern: &str) -> impl Iterator<Item = usize> + '_ {
+    pattern
+        .bytes()
+        .map(|bit| if bit == b'1' { WIDE } else { 1 })
+}
+
+/// Returns the two characters standing for an ASCII character in full ASCII Code39 and
+/// Code93, with `$`, `%`, `/` or `+` as the shift.
+fn full_ascii(ch: char) -> Option<(char, char)> {
+    let code = ch as u32;
+    let offset = |base: u32, first: char| char::from_u32(first as u32 + code - base);
+    let pair = match code {
+        0 => ('%', 'U'),
+        1..=26 => ('$', offset(1, 'A')?),
+        27..=31 => ('%', offset(27, 'A')?),
+        33..=44 | 47 => ('/', offset(33, 'A')?),
+        58 => ('/', 'Z'),
+        59..=63 => ('%', offset(59, 'F')?),
+        64 => ('%', 'V'),
+        91..=95 => ('%', offset(91, 'K')?),
+        96 => ('%', 'W'),
+        97..=122 => ('+', offset(97, 'A')?),
+        123..=127 => ('%', offset(123, 'P')?),
+        _ => return None,
+    };
+    Some(pair)
+}
+
+fn code39(data: &str, check_digit: bool, full: bool) -> Result<(String, Vec<u8>), String> {
+    let mut values = Vec::new();
+    for ch in data.chars() {
+        let basic = ch.is_ascii_digit() || ch.is_ascii_uppercase() || "-. ".contains(ch);
+        if full && !basic {
+            let (shift, second) =
+                full_ascii(ch).ok_or_else(|| format!("Code39 cannot encode `{ch}`"))?;
+            values.extend([shift, second].map(|ch| CODE39_CHARS.find(ch).unwrap_or(0)));
+        } else {
+            values.push(
+                CODE39_CHARS
+                    .find(ch)
+                    .ok_or_else(|| format!("Code39 cannot encode `{ch}`"))?,
+            );
+        }
+    }
+    let mut text = data.to_string();
+    if check_digit {
+        let check = values.iter().sum::<usize>() % 43;
+        values.push(check);
+        text.push(char::from(CODE39_CHARS.as_bytes()[check]));
+    }
+
+    let patterns = std::iter::once(CODE39_START_STOP)
+        .chain(values.iter().map(|&value| CODE39_PATTERNS[value]))
+        .chain(std::iter::once(CODE39_START_STOP));
+    let mut modules = Vec::new();
+    for (index, pattern) in patterns.enumerate() {
+        // Characters are separated by a narrow space
+        if index > 0 {
+            modules.push(0);
+        }
+        push_widths(&mut modules, wide_narrow(pattern));
+    }
+    Ok((text, modules))
+}
+
+fn code93(data: &str) -> Result<(String, Vec<u8>), String> {
+    let mut values = Vec::new();
+    for ch in data.chars() {
+        if let Some(value) = CODE39_CHARS.find(ch) {
+            values.push(value);
+        } else {
+            let (shift, second) =
+                full_ascii(ch).ok_or_else(|| format!("Code93 cannot encode `{ch}`"))?;
+            let shift = match shift {
+                '$' => 43,
+                '%' => 44,
+                '/' => 45,
+                _ => 46,
+            };
+            values.extend([shift, CODE39_CHARS.find(second).unwrap_or(0)]);
+        }
+    }
+
+    // Check characters C and K, weighted from the right up to 20 and 15
+    for max_weight in [20, 15] {
+        let sum: usize = values
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(index, &value)| value * (index % max_weight + 1))
+            .sum();
+        values.push(sum % 47);
+    }
+
+    let mut modules = Vec::new();
+    let patterns = std::iter::once(CODE93_START_STOP)
+        .chain(values.iter().map(|&value| CODE93_PATTERNS[value]))
+        .chain(std::iter::once(CODE93_START_STOP));
+    for pattern in patterns {
+        push_widths(
+            &mut modules,
+            pattern.bytes().map(|width| (width - b'0') as usize),
+        );
+    }
+    // Termination bar
+    modules.push(1);
+    Ok((data.to_string(), modules))
+}
+
+/// Encodes an even number of digits in Interleaved 2 of 5, pairs sharing a character with the
+/// first digit in the bars and the second in the spaces.
+fn itf(digits: &[u8]) -> Vec<u8> {
+    let mut modules = Vec::new();
+    push_widths(&mut modules, [1, 1, 1, 1]);
+    for pair in digits.chunks(2) {
+        let bars = wide_narrow(ITF_PATTERNS[pair[0] as usize]);
+        let spaces = wide_narrow(ITF_PATTERNS[pair[1] as usize]);
+        push_widths(
+            &mut modules,
+            bars.zip(spaces).flat_map(|(bar, space)| [bar, space]),
+        );
+    }
+    push_widths(&mut modules, [WIDE, 1, 1]);
+    modules
+}
+
+fn codabar(data: &str) -> Result<(String, Vec<u8>), String> {
+    let data = data.to_ascii_uppercase();
+    let is_guard = |ch: char| "ABCD".contains(ch);
+    let text = match (data.chars().next(), data.chars().last()) {
+        (Some(first), Some(last)) if is_guard(first) && is_guard(last) && data.len() > 1 => data,
+        (Some(first), _) if is_guard(first) => {
+            return Err(format!(
+                "Codabar `{data}` has a start but no stop character"
+            ));
+        }
+        _ => format!("A{data}A"),
+    };
+    if text.len() == 2 {
+        return Err("Codabar needs data between its start and stop characters".to_string());
+    }
+    if text[1..text.len() - 1].contains(is_guard) {
+        return Err(format!(
+            "Codabar start and stop characters can only be at the ends of `{text}`"
+        ));
+    }
+
+    let mut modules = Vec::new();
+    for (index, ch) in text.chars().enumerate() {
+        let value = CODABAR_CHARS
+            .find(ch)
+            .ok_or_else(|| format!("Codabar cannot encode `{ch}`"))?;
+        if index > 0 {
+            modules.push(0);
+        }
+        push_widths(&mut modules, wide_narrow(CODABAR_PATTERNS[value]));
+    }
+    Ok((text, modules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(symbology: Symbology, data: &str) -> Result<String, String> {
+        Barcode::new(symbology, data, Rectangle::new(0, 0, 400, 200)).map(|barcode| barcode.text)
+    }
+
+    #[test]
+    fn gs1_check_digit_weights_from_the_right() {
+        assert_eq!(gs1_check_digit(&[4, 0, 0, 6, 3, 8, 1, 3, 3, 3, 9, 3]), 1);
+        assert_eq!(gs1_check_digit(&[9, 6, 3, 8, 5, 0, 7]), 4);
+        assert_eq!(gs1_check_digit(&[0; 11]), 0);
+    }
+
+    #[test]
+    fn ean_and_upc_check_digits_are_appended() {
+        assert_eq!(
+            text(Symbology::Ean13, "400638133393").unwrap(),
+            "4006381333931"
+        );
+        assert_eq!(text(Symbology::Ean8, "9638507").unwrap(), "96385074");
+        assert_eq!(
+            text(Symbology::UpcA, "03600029145").unwrap(),
+            "036000291452"
+        );
+    }
+
+    #[test]
+    fn upc_e_check_digit_comes_from_the_expanded_number() {
+        assert_eq!(text(Symbology::UpcE, "123456").unwrap(), "01234565");
+        assert_eq!(text(Symbology::UpcE, "01234500006").unwrap(), "01234565");
+        assert!(text(Symbology::UpcE, "01234566").is_err());
+    }
+
+    #[test]
+    fn itf14_check_digit_is_appended() {
+        let itf14 = Symbology::Itf14 { bearer_bars: false };
+        assert_eq!(text(itf14, "1540014128876").unwrap(), "15400141288763");
+    }
+
+    #[test]
+    fn wrong_check_digits_are_rejected() {
+        assert!(text(Symbology::Ean13, "4006381333932").is_err());
+        assert!(text(Symbology::Ean8, "96385075").is_err());
+        assert!(text(Symbology::Itf14 { bearer_bars: true }, "15400141288764").is_err());
+        assert!(text(Symbology::UpcA, "036000291453").is_err());
+    }
+
+    #[test]
+    fn code39_full_ascii_check_character_is_not_expanded() {
+        let symbology = Symbology::Code39 {
+            check_digit: true,
+            full_ascii: true,
+        };
+        let barcode = Barcode::new(symbology, "Z6", Rectangle::new(0, 0, 400, 200)).unwrap();
+        assert_eq!(barcode.text, "Z6+");
+        assert_eq!(barcode.payload(), "Z6+");
+    }
+
+    #[test]
+    fn codabar_needs_data() {
+        assert!(text(Symbology::Codabar, "").is_err());
+        assert!(text(Symbology::Codabar, "AB").is_err());
+        assert_eq!(text(Symbology::Codabar, "123").unwrap(), "A123A");
+    }
+}
@@ -1,63 +1,474 @@
-use barcoders::generators::image::*;
-use barcoders::sym::code128::Code128;
-use image::{DynamicImage, Rgba, RgbaImage};
+use image::{DynamicImage, RgbaImage};
+use rgb::Rgba;
+
+use crate::geometry::rectangle::Rectangle;
+
+use super::barcode::{self, Symbology};
+use super::linear::{HumanReadable, LinearOptions, draw_modules};
+
+/// Bar and space widths of each Code128 symbol character, indexed by value.
+const PATTERNS: [&str; 106] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232",
+];
+
+/// Bar and space widths of the stop character, including the final bar.
+const STOP: &str = "2331112";
+
+const SHIFT: u8 = 98;
+const CODE_C: u8 = 99;
+const CODE_B: u8 = 100;
+const CODE_A: u8 = 101;
+const FNC1: u8 = 102;
+const START_A: u8 = 103;
+const START_B: u8 = 104;
+const START_C: u8 = 105;
+
+/// GS1 application identifier prefixes with a predefined length, which need no FNC1 separator.
+const GS1_FIXED_LENGTH: [&str; 22] = [
+    "00", "01", "02", "03", "04", "11", "12", "13", "14", "15", "16", "17", "18", "19", "20", "31",
+    "32", "33", "34", "35", "36", "41",
+];
+
+/// One element of Code128 input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code128Data {
+    /// An ASCII character.
+    Char(u8),
+    /// Function code 1, which marks GS1-128 data and separates its variable length fields.
+    Fnc1,
+}
+
+/// The three Code128 code sets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodeSet {
+    /// Uppercase, digits, punctuation and control characters.
+    A,
+    /// Upper and lowercase, digits and punctuation.
+    B,
+    /// Pairs of digits.
+    C,
+}
+
+/// A Code128 symbol, encoded with the fewest symbol characters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Code128 {
+    /// Symbol character values from the start character through the checksum, without the stop.
+    pub codewords: Vec<u8>,
+}
+
+/// A step of the cheapest encoding found by `Code128::from_data`.
+#[derive(Clone, Copy)]
+enum Step {
+    Start(CodeSet),
+    Switch(CodeSet),
+    /// Encode the next element in the current set.
+    Encode,
+    /// Encode the next character in the other of sets A and B.
+    Shift,
+}
+
+const SETS: [CodeSet; 3] = [CodeSet::A, CodeSet::B, CodeSet::C];
+
+impl CodeSet {
+    fn index(self) -> usize {
+        match self {
+            CodeSet::A => 0,
+            CodeSet::B => 1,
+            CodeSet::C => 2,
+        }
+    }
+
+    /// Returns the value of a character in this set, `None` if the set cannot encode it.
+    fn value(self, ch: u8) -> Option<u8> {
+        match (self, ch) {
+            (CodeSet::A, 0..=31) => Some(ch + 64),
+            (CodeSet::A, 32..=95) | (CodeSet::B, 32..=127) => Some(ch - 32),
+            _ => None,
+        }
+    }
+
+    /// Returns the code that switches to this set.
+    fn switch_code(self) -> u8 {
+        match self {
+            CodeSet::A => CODE_A,
+            CodeSet::B => CODE_B,
+            CodeSet::C => CODE_C,
+        }
+    }
+
+    fn start_code(self) -> u8 {
+        match self {
+            CodeSet::A => START_A,
+            CodeSet::B => START_B,
+            CodeSet::C => START_C,
+        }
+    }
+}
+
+impl Code128 {
+    /// Encodes ASCII text.
+    pub fn new(input: &str) -> Result<Self, String> {
+        if !input.is_ascii() {
+            return Err(
+                "Input string contains invalid characters. Only ASCII characters are allowed."
+                    .to_string(),
+            );
+        }
+        let data: Vec<Code128Data> = input.bytes().map(Code128Data::Char).collect();
+        Code128::from_data(&data)
+    }
+
+    /// Encodes GS1-128 data written with application identifiers in parentheses,
+    /// e.g. `(01)09501101530003(10)ABC123`.
+    pub fn gs1(input: &str) -> Result<Self, String> {
+        Code128::from_data(&parse_gs1(input)?)
+    }
+
+    /// Encodes the data with the fewest symbol characters, switching and shifting between code
+    /// sets where that saves space.
+    pub fn from_data(data: &[Code128Data]) -> Result<Self, String> {
+        if data.is_empty() {
+            return Err("Input string cannot be empty".to_string());
+        }
+        if let Some(Code128Data::Char(ch)) = data
+            .iter()
+            .find(|item| matches!(item, Code128Data::Char(ch) if *ch > 127))
+        {
+            return Err(format!("character {ch:#04x} cannot be encoded in Code128"));
+        }
+
+        // cost[i][set]: fewest symbol characters encoding data[..i] and ending in `set`
+        let length = data.len();
+        let mut cost = vec![[usize::MAX; 3]; length + 1];
+        let mut previous: Vec<[Option<(usize, CodeSet, Step)>; 3]> = vec![[None; 3]; length + 1];
+        for set in SETS {
+            cost[0][set.index()] = 1;
+            previous[0][set.index()] = Some((0, set, Step::Start(set)));
+        }
+
+        for position in 0..length {
+            // Switching sets before the next element, one switch is never worse than two
+            let before = cost[position];
+            for from in SETS {
+                for to in SETS {
+                    let switched = before[from.index()].saturating_add(1);
+                    if from != to && switched < cost[position][to.index()] {
+                        cost[position][to.index()] = switched;
+                        previous[position][to.index()] = Some((position, from, Step::Switch(to)));
+                    }
+                }
+            }
+
+            for set in SETS {
+                let current = cost[position][set.index()];
+                if current == usize::MAX {
+                    continue;
+                }
+                let mut relax = |next: usize, added: usize, step: Step| {
+                    if current + added < cost[next][set.index()] {
+                        cost[next][set.index()] = current + added;
+                        previous[next][set.index()] = Some((position, set, step));
+                    }
+                };
+
+                match (set, data[position]) {
+                    (_, Code128Data::Fnc1) => relax(position + 1, 1, Step::Encode),
+                    (CodeSet::C, Code128Data::Char(first)) => {
+                        if let Some(Code128Data::Char(second)) = data.get(position + 1)
+                            && first.is_ascii_digit()
+                            && second.is_ascii_digit()
+                        {
+                            relax(position + 2, 1, Step::Encode);
+                        }
+                    }
+                    (_, Code128Data::Char(ch)) => {
+                        let other = if set == CodeSet::A {
+                            CodeSet::B
+                        } else {
+                            CodeSet::A
+                        };
+                        if set.value(ch).is_some() {
+                            relax(position + 1, 1, Step::Encode);
+                        } else if other.value(ch).is_some() {
+                            relax(position + 1, 2, Step::Shift);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Walk back from the cheapest final set to recover the steps
+        let mut set = SETS
+            .into_iter()
+            .min_by_key(|set| cost[length][set.index()])
+            .unwrap_or(CodeSet::B);
+        let mut position = length;
+        let mut steps = Vec::new();
+        loop {
+            let Some((from, from_set, step)) = previous[position][set.index()] else {
+                return Err("input cannot be encoded in Code128".to_string());
+            };
+            steps.push((from, set, step));
+            if let Step::Start(_) = step {
+                break;
+            }
+            position = from;
+            set = from_set;
+        }
+        steps.reverse();
+
+        let mut codewords = Vec::new();
+        for (position, set, step) in steps {
+            match step {
+                Step::Start(set) => codewords.push(set.start_code()),
+                Step::Switch(to) => codewords.push(to.switch_code()),
+                Step::Encode => match (set, data[position]) {
+                    (_, Code128Data::Fnc1) => codewords.push(FNC1),
+                    (CodeSet::C, Code128Data::Char(first)) => {
+                        if let Some(Code128Data::Char(second)) = data.get(position + 1) {
+                            codewords.push((first - b'0') * 10 + (second - b'0'));
+                        }
+                    }
+                    (_, Code128Data::Char(ch)) => codewords.extend(set.value(ch)),
+                },
+                Step::Shift => {
+                    if let Code128Data::Char(ch) = data[position] {
+                        let other = if set == CodeSet::A {
+                            CodeSet::B
+                        } else {
+                            CodeSet::A
+                        };
+                        codewords.push(SHIFT);
+                        codewords.extend(other.value(ch));
+                    }
+                }
+            }
+        }
+
+        codewords.push(checksum(&codewords));
+        Ok(Code128 { codewords })
+    }
+
+    /// Returns the check character, the last of the codewords.
+    pub fn checksum(&self) -> u8 {
+        self.codewords.last().copied().unwrap_or(0)
+    }
+
+    /// Returns the symbol as modules from the first bar to the last, 1 for bar and 0 for space,
+    /// without quiet zones.
+    pub fn modules(&self) -> Vec<u8> {
+        let mut modules = Vec::with_capacity(self.codewords.len() * 11 + 13);
+        let patterns = self
+            .codewords
+            .iter()
+            .map(|&codeword| PATTERNS[codeword as usize])
+            .chain(std::iter::once(STOP));
+
+        for pattern in patterns {
+            for (index, width) in pattern.bytes().enumerate() {
+                let module = if index % 2 == 0 { 1 } else { 0 };
+                modules.extend(std::iter::repeat_n(module, (width - b'0') as usize));
+            }
+        }
+        modules
+    }
+}
+
+/// Parses GS1 element strings such as `(01)09501101530003(10)ABC` into data starting with
+/// FNC1, with FNC1 separating variable length fields from the next.
+pub fn parse_gs1(input: &str) -> Result<Vec<Code128Data>, String> {
+    let mut data = vec![Code128Data::Fnc1];
+    let mut rest = input;
+    while !rest.is_empty() {
+        let invalid = || format!("invalid GS1 element string `{input}`");
+        let after_open = rest.strip_prefix('(').ok_or_else(invalid)?;
+        let (ai, after_ai) = after_open.split_once(')').ok_or_else(invalid)?;
+        if ai.len() < 2 || ai.len() > 4 || !ai.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("invalid GS1 application identifier `{ai}`"));
+        }
+        let value_end = after_ai.find('(').unwrap_or(after_ai.len());
+        let value = &after_ai[..value_end];
+        if value.is_empty() || !value.is_ascii() {
+            return Err(format!(
+                "invalid value for GS1 application identifier `{ai}`"
+            ));
+        }
+        rest = &after_ai[value_end..];
+
+        data.extend(ai.bytes().chain(value.bytes()).map(Code128Data::Char));
+        // Variable length fields end with FNC1 unless they are last
+        if !rest.is_empty() && !GS1_FIXED_LENGTH.contains(&&ai[..2]) {
+            data.push(Code128Data::Fnc1);
+        }
+    }
+    Ok(data)
+}
+
+/// Computes the modulo 103 check character over the start character and data.
+fn checksum(codewords: &[u8]) -> u8 {
+    let sum: usize = codewords
+        .iter()
+        .enumerate()
+        .map(|(index, &codeword)| index.max(1) * codeword as usize)
+        .sum();
+    (sum % 103) as u8
+}
 
 /// Generates a Code128 barcode image from the given input string, with specified dimensions and margins.
 pub fn from(input: &str, width: u32, height: u32, margin: u32) -> Result<DynamicImage, String> {
-    if input.is_empty() {
-        return Err("Input string cannot be empty".to_string());
-    }
-    if !input.is_ascii() {
-        return Err(
-            "Input string contains invalid characters. Only ASCII characters are allowed."
-                .to_string(),
-        );
-    }
-
-    // Determine the starting character based on the character set (This is stupid, I'll pr this crate later.)
-    let starting_char = if input.chars().all(|c| c.is_ascii_alphanumeric()) {
-        'À' // Character set A
-    } else if input.is_ascii() {
-        'Ɓ' // Character set B
-    } else {
-        'Ć' // Character set C
-    };
-
-    // Prepend the starting character to the input
-    let formatted_input = format!("{}{}", starting_char, input);
-
-    // Generate the barcode
-    let barcode = Code128::new(&formatted_input).map_err(|e| e.to_string())?;
-    let png = Image::png(height); // Barcode height in pixels
-    let encoded = barcode.encode();
-    let png_vec = png.generate(&encoded).map_err(|e| e.to_string())?;
-
-    // Load the barcode image
-    let barcode_image = image::load_from_memory(&png_vec).map_err(|e| e.to_string())?;
-    let barcode_height = barcode_image.height();
-
-    // Create a blank image with the specified dimensions and a solid white background
-    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
-
-    // Resize the barcode image to fill the entire width between margins
-    let resized_barcode = image::imageops::resize(
-        &barcode_image.to_rgba8(),
-        width - margin * 2, // Fill the entire width minus margins
-        (height - margin * 2).min(barcode_height),
-        image::imageops::FilterType::Lanczos3,
-    );
+    render(
+        &Code128::new(input)?,
+        width,
+        height,
+        margin,
+        &LinearOptions::default(),
+    )
+}
+
+/// Generates a GS1-128 barcode image from element strings such as `(01)09501101530003(10)ABC`.
+pub fn from_gs1(input: &str, width: u32, height: u32, margin: u32) -> Result<DynamicImage, String> {
+    render(
+        &Code128::gs1(input)?,
+        width,
+        height,
+        margin,
+        &LinearOptions::default(),
+    )
+}
+
+/// Generates a Code128 barcode image with the input printed as human-readable text.
+pub fn from_with_text(
+    input: &str,
+    width: u32,
+    height: u32,
+    margin: u32,
+    human_readable: HumanReadable,
+) -> Result<DynamicImage, String> {
+    barcode::from_with_text(
+        Symbology::Code128,
+        input,
+        width,
+        height,
+        margin,
+        human_readable,
+    )
+}
 
-    // Set x_offset to just the margin since we're filling the width
-    let x_offset = margin;
-    let y_offset = margin + (height - margin * 2 - resized_barcode.height()) / 2;
+/// Draws an encoded symbol on a blank canvas with whole-pixel modules, as wide as fits between
+/// the margins and centered in the leftover space.
+pub fn render(
+    symbol: &Code128,
+    width: u32,
+    height: u32,
+    margin: u32,
+    options: &LinearOptions,
+) -> Result<DynamicImage, String> {
+    if width <= margin * 2 || height <= margin * 2 {
+        return Err("margins leave no room for the barcode".to_string());
+    }
 
-    // Overlay the resized barcode onto the canvas
-    image::imageops::overlay(
-        &mut canvas,
-        &resized_barcode,
-        x_offset.into(),
-        y_offset.into(),
+    let background = options.background.unwrap_or(Rgba::new(255, 255, 255, 255));
+    let mut canvas = RgbaImage::from_pixel(
+        width,
+        height,
+        image::Rgba([background.r, background.g, background.b, background.a]),
     );
+    let area = Rectangle::new(margin, margin, width - margin, height - margin);
+    draw_modules(&mut canvas, &symbol.modules(), &area, options)?;
 
     Ok(DynamicImage::ImageRgba8(canvas))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<Code128Data> {
+        text.bytes().map(Code128Data::Char).collect()
+    }
+
+    #[test]
+    fn lowercase_text_uses_code_set_b() {
+        let code = Code128::new("abc").unwrap();
+        assert_eq!(code.codewords, [START_B, 65, 66, 67, 90]);
+        assert_eq!(code.checksum(), 90);
+    }
+
+    #[test]
+    fn even_digit_runs_use_code_set_c() {
+        let code = Code128::new("123456").unwrap();
+        assert_eq!(code.codewords, [START_C, 12, 34, 56, 44]);
+    }
+
+    #[test]
+    fn odd_digit_runs_take_one_switch() {
+        let code = Code128::new("12345").unwrap();
+        assert_eq!(code.codewords.len(), 6);
+        let switches = code.codewords[1..code.codewords.len() - 1]
+            .iter()
+            .filter(|&&c| matches!(c, CODE_A | CODE_B | CODE_C))
+            .count();
+        assert_eq!(switches, 1);
+    }
+
+    #[test]
+    fn single_control_character_is_shifted() {
+        let code = Code128::new("a\nb").unwrap();
+        assert_eq!(code.codewords, [START_B, 65, SHIFT, 74, 66, 27]);
+    }
+
+    #[test]
+    fn checksum_weights_by_position() {
+        assert_eq!(checksum(&[START_B, 48, 42, 42, 17, 18, 19, 35]), 55);
+        assert_eq!(checksum(&[START_A]), START_A % 103);
+    }
+
+    #[test]
+    fn rejects_empty_and_non_ascii_input() {
+        assert!(Code128::new("").is_err());
+        assert!(Code128::new("é").is_err());
+    }
+
+    #[test]
+    fn gs1_separates_variable_length_fields() {
+        let data = parse_gs1("(01)09501101530003(10)ABC(17)250101").unwrap();
+        let mut expected = vec![Code128Data::Fnc1];
+        expected.extend(chars("0109501101530003"));
+        expected.extend(chars("10ABC"));
+        expected.push(Code128Data::Fnc1);
+        expected.extend(chars("17250101"));
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn gs1_last_field_has_no_separator() {
+        let data = parse_gs1("(10)ABC").unwrap();
+        assert_eq!(data.last(), Some(&Code128Data::Char(b'C')));
+        assert_eq!(data.iter().filter(|d| **d == Code128Data::Fnc1).count(), 1);
+    }
+
+    #[test]
+    fn gs1_rejects_malformed_element_strings() {
+        assert!(parse_gs1("01)09501101530003").is_err());
+        assert!(parse_gs1("(1)5").is_err());
+        assert!(parse_gs1("(01a)5").is_err());
+        assert!(parse_gs1("(10)").is_err());
+    }
+
+    #[test]
+    fn gs1_symbol_starts_with_fnc1() {
+        let code = Code128::gs1("(01)09501101530003").unwrap();
+        assert_eq!(code.codewords[..2], [START_C, FNC1]);
+    }
+}
@@ -0,0 +1,565 @@
+use image::{DynamicImage, RgbaImage};
+use rgb::Rgba;
+use tracing::debug;
+
+use crate::error::ImageSlapperError;
+use crate::geometry::rectangle::Rectangle;
+use crate::rendering::draw::Drawable;
+
+use super::code128::{Code128Data, parse_gs1};
+use super::matrix::{GridPlacement, GridStyle, ModuleGrid, draw_grid};
+use super::reed_solomon::GaloisField;
+use super::vector::SymbolGeometry;
+
+const PAD: u8 = 129;
+const FNC1: u8 = 232;
+const STRUCTURED_APPEND: u8 = 233;
+const UPPER_SHIFT: u8 = 235;
+const ECI: u8 = 241;
+
+/// An ECC200 symbol size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataMatrixSize {
+    pub rows: usize,
+    pub columns: usize,
+    /// Rows and columns of each data region, without its finder and timing patterns.
+    region_rows: usize,
+    region_columns: usize,
+    data_codewords: usize,
+    error_codewords: usize,
+    /// Number of interleaved error correction blocks.
+    blocks: usize,
+}
+
+const fn size(
+    rows: usize,
+    columns: usize,
+    region_rows: usize,
+    region_columns: usize,
+    data_codewords: usize,
+    error_codewords: usize,
+    blocks: usize,
+) -> DataMatrixSize {
+    DataMatrixSize {
+        rows,
+        columns,
+        region_rows,
+        region_columns,
+        data_codewords,
+        error_codewords,
+        blocks,
+    }
+}
+
+/// ECC200 sizes from smallest to largest, squares first.
+const SIZES: [DataMatrixSize; 30] = [
+    size(10, 10, 8, 8, 3, 5, 1),
+    size(12, 12, 10, 10, 5, 7, 1),
+    size(14, 14, 12, 12, 8, 10, 1),
+    size(16, 16, 14, 14, 12, 12, 1),
+    size(18, 18, 16, 16, 18, 14, 1),
+    size(20, 20, 18, 18, 22, 18, 1),
+    size(22, 22, 20, 20, 30, 20, 1),
+    size(24, 24, 22, 22, 36, 24, 1),
+    size(26, 26, 24, 24, 44, 28, 1),
+    size(32, 32, 14, 14, 62, 36, 1),
+    size(36, 36, 16, 16, 86, 42, 1),
+    size(40, 40, 18, 18, 114, 48, 1),
+    size(44, 44, 20, 20, 144, 56, 1),
+    size(48, 48, 22, 22, 174, 68, 1),
+    size(52, 52, 24, 24, 204, 84, 2),
+    size(64, 64, 14, 14, 280, 112, 2),
+    size(72, 72, 16, 16, 368, 144, 4),
+    size(80, 80, 18, 18, 456, 192, 4),
+    size(88, 88, 20, 20, 576, 224, 4),
+    size(96, 96, 22, 22, 696, 272, 4),
+    size(104, 104, 24, 24, 816, 336, 6),
+    size(120, 120, 18, 18, 1050, 408, 6),
+    size(132, 132, 20, 20, 1304, 496, 8),
+    size(144, 144, 22, 22, 1558, 620, 10),
+    size(8, 18, 6, 16, 5, 7, 1),
+    size(8, 32, 6, 14, 10, 11, 1),
+    size(12, 26, 10, 24, 16, 14, 1),
+    size(12, 36, 10, 16, 22, 18, 1),
+    size(16, 36, 14, 16, 32, 24, 1),
+    size(16, 48, 14, 22, 49, 28, 1),
+];
+
+/// Which symbol shapes to choose from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DataMatrixShape {
+    #[default]
+    Square,
+    /// Rectangles for long, narrow fields, falling back to squares for more data.
+    Rectangle,
+    /// The smallest symbol of either shape.
+    Any,
+}
+
+/// Marks a symbol as part of a message split over up to 16 symbols.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataMatrixAppend {
+    /// Position of this symbol, from 1.
+    pub position: u8,
+    /// Number of symbols, 2 to 16.
+    pub total: u8,
+    /// Identifies the symbols of one message, below 64516.
+    pub file_id: u16,
+}
+
+/// Settings for Data Matrix codes.
+#[derive(Clone, Copy, Debug)]
+pub struct DataMatrixConfig {
+    pub shape: DataMatrixShape,
+    /// Rows and columns of the symbol, `None` for the smallest that fits the data.
+    pub size: Option<(usize, usize)>,
+    /// Read the content as GS1 element strings such as `(01)09501101530003(17)250101`.
+    pub gs1: bool,
+    pub structured_append: Option<DataMatrixAppend>,
+    pub foreground_color: Rgba<u8>,
+    pub background_color: Rgba<u8>,
+    /// Width of the light border in modules, the standard asks for 1.
+    pub quiet_zone: u32,
+}
+
+impl Default for DataMatrixConfig {
+    fn default() -> Self {
+        DataMatrixConfig {
+            shape: DataMatrixShape::Square,
+            size: None,
+            gs1: false,
+            structured_append: None,
+            foreground_color: Rgba::new(0, 0, 0, 255),
+            background_color: Rgba::new(255, 255, 255, 255),
+            quiet_zone: 1,
+        }
+    }
+}
+
+/// An encoded ECC200 Data Matrix symbol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataMatrixSymbol {
+    pub size: DataMatrixSize,
+    /// Data codewords including padding, followed by the error correction codewords.
+    pub codewords: Vec<u8>,
+    pub modules: ModuleGrid,
+}
+
+impl DataMatrixSymbol {
+    /// Encodes the content with ASCII encodation, packing digit pairs into one codeword. Text
+    /// outside ASCII is encoded as UTF-8 behind an ECI.
+    pub fn encode(content: &str, config: &DataMatrixConfig) -> Result<Self, String> {
+        let mut data = Vec::new();
+        if let Some(append) = config.structured_append {
+            if !(2..=16).contains(&append.total) {
+                return Err(format!(
+                    "structured append needs 2 to 16 symbols, got {}",
+                    append.total
+                ));
+            }
+            if append.position == 0 || append.position > append.total {
+                return Err(format!(
+                    "symbol {} is not in a sequence of {}",
+                    append.position, append.total
+                ));
+            }
+            if append.file_id >= 254 * 254 {
+                return Err(format!("file ID {} is above 64515", append.file_id));
+            }
+            data.extend([
+                STRUCTURED_APPEND,
+                (append.position - 1) << 4 | (17 - append.total),
+                (append.file_id / 254 + 1) as u8,
+                (append.file_id % 254 + 1) as u8,
+            ]);
+        }
+
+        let input = if config.gs1 {
+            parse_gs1(content)?
+        } else {
+            if !content.is_ascii() {
+                // ECI 26, UTF-8, is sent as its value plus one
+                data.extend([ECI, 27]);
+            }
+            content.bytes().map(Code128Data::Char).collect()
+        };
+        let mut index = 0;
+        while index < input.len() {
+            match (input[index], input.get(index + 1)) {
+                (Code128Data::Fnc1, _) => data.push(FNC1),
+                (Code128Data::Char(first), Some(&Code128Data::Char(second)))
+                    if first.is_ascii_digit() && second.is_ascii_digit() =>
+                {
+                    data.push(130 + (first - b'0') * 10 + (second - b'0'));
+                    index += 1;
+                }
+                (Code128Data::Char(ch @ 0..128), _) => data.push(ch + 1),
+                (Code128Data::Char(ch), _) => data.extend([UPPER_SHIFT, ch - 127]),
+            }
+            index += 1;
+        }
+
+        let size = choose_size(data.len(), config)?;
+        debug!(
+            "Encoding {} Data Matrix codewords in a {}x{} symbol",
+            data.len(),
+            size.rows,
+            size.columns
+        );
+
+        // Pad, randomizing all but the first pad so they do not form patterns
+        if data.len() < size.data_codewords {
+            data.push(PAD);
+        }
+        while data.len() < size.data_codewords {
+            let position = data.len() + 1;
+            let pseudo_random = (149 * position) % 253 + 1;
+            let value = PAD as usize + pseudo_random;
+            data.push(if value > 254 { value - 254 } else { value } as u8);
+        }
+
+        let codewords = error_correction(&data, &size);
+        let modules = place(&codewords, &size);
+        Ok(DataMatrixSymbol {
+            size,
+            codewords,
+            modules,
+        })
+    }
+}
+
+/// Picks the configured size, or the smallest of the allowed shapes that fits the data.
+fn choose_size(length: usize, config: &DataMatrixConfig) -> Result<DataMatrixSize, String> {
+    if let Some((rows, columns)) = config.size {
+        let size = SIZES
+            .iter()
+            .find(|size| size.rows == rows && size.columns == columns)
+            .ok_or_else(|| format!("{rows}x{columns} is not a Data Matrix size"))?;
+        if size.data_codewords < length {
+            return Err(format!(
+                "{length} codewords do not fit in a {rows}x{columns} Data Matrix"
+            ));
+        }
+        return Ok(*size);
+    }
+
+    let fits = |size: &&DataMatrixSize| size.data_codewords >= length;
+    let square = SIZES
+        .iter()
+        .filter(|size| size.rows == size.columns)
+        .find(fits);
+    let rectangle = SIZES
+        .iter()
+        .filter(|size| size.rows != size.columns)
+        .find(fits);
+    let size = match config.shape {
+        DataMatrixShape::Square => square,
+        DataMatrixShape::Rectangle => rectangle.or(square),
+        DataMatrixShape::Any => [square, rectangle]
+            .into_iter()
+            .flatten()
+            .min_by_key(|size| size.rows * size.columns),
+    };
+    size.copied()
+        .ok_or_else(|| format!("{length} codewords do not fit in a Data Matrix"))
+}
+
+/// Appends Reed-Solomon codewords, computed for interleaved blocks of every n-th codeword.
+fn error_correction(data: &[u8], size: &DataMatrixSize) -> Vec<u8> {
+    let field = GaloisField::new(256, 0x12D, 1);
+    let per_block = size.error_codewords / size.blocks;
+    let mut codewords = data.to_vec();
+    codewords.resize(data.len() + size.error_codewords, 0);
+    for block in 0..size.blocks {
+        let block_data: Vec<u16> = data
+            .iter()
+            .skip(block)
+            .step_by(size.blocks)
+            .map(|&codeword| u16::from(codeword))
+            .collect();
+        for (index, check) in field
+            .check_words(&block_data, per_block)
+            .into_iter()
+            .enumerate()
+        {
+            codewords[data.len() + index * size.blocks + block] = check as u8;
+        }
+    }
+    codewords
+}
+
+/// Places codewords diagonally in the data regions and adds the finder and timing patterns.
+fn place(codewords: &[u8], size: &DataMatrixSize) -> ModuleGrid {
+    let vertical_regions = size.rows / (size.region_rows + 2);
+    let horizontal_regions = size.columns / (size.region_columns + 2);
+    let mut placement = Placement {
+        rows: size.region_rows * vertical_regions,
+        columns: size.region_columns * horizontal_regions,
+        bits: Vec::new(),
+        codewords,
+    };
+    placement.bits = vec![None; placement.rows * placement.columns];
+    placement.run();
+
+    let mut grid = ModuleGrid::new(size.columns, size.rows);
+    let (block_rows, block_columns) = (size.region_rows + 2, size.region_columns + 2);
+    for y in 0..size.rows {
+        for x in 0..size.columns {
+            let (local_y, local_x) = (y % block_rows, x % block_columns);
+            let dark = if local_x == 0 || local_y == block_rows - 1 {
+                // Solid finder edges on the left and bottom
+                true
+            } else if local_y == 0 {
+                // Alternating timing pattern on the top and right
+                local_x % 2 == 0
+            } else if local_x == block_columns - 1 {
+                local_y % 2 == 1
+            } else {
+                let row = y / block_rows * size.region_rows + local_y - 1;
+                let column = x / block_columns * size.region_columns + local_x - 1;
+                placement.bits[row * placement.columns + column].unwrap_or(false)
+            };
+            grid.set(x, y, dark);
+        }
+    }
+    grid
+}
+
+/// The ECC200 placement of codeword bits in the mapping matrix, the data regions joined
+/// without their patterns.
+struct Placement<'a> {
+    rows: usize,
+    columns: usize,
+    bits: Vec<Option<bool>>,
+    codewords: &'a [u8],
+}
+
+impl Placement<'_> {
+    fn run(&mut self) {
+        let (rows, columns) = (self.rows as i32, self.columns as i32);
+        let mut codeword = 0;
+        let (mut row, mut column) = (4, 0);
+        loop {
+            // The four corner cases
+            if row == rows && column == 0 {
+                self.corner(
+                    [
+                        (rows - 1, 0),
+                        (rows - 1, 1),
+                        (rows - 1, 2),
+                        (0, columns - 2),
+                        (0, columns - 1),
+                        (1, columns - 1),
+                        (2, columns - 1),
+                        (3, columns - 1),
+                    ],
+                    codeword,
+                );
+                codeword += 1;
+            }
+            if row == rows - 2 && column == 0 && columns % 4 != 0 {
+                self.corner(
+                    [
+                        (rows - 3, 0),
+                        (rows - 2, 0),
+                        (rows - 1, 0),
+                        (0, columns - 4),
+                        (0, columns - 3),
+                        (0, columns - 2),
+                        (0, columns - 1),
+                        (1, columns - 1),
+                    ],
+                    codeword,
+                );
+                codeword += 1;
+            }
+            if row == rows - 2 && column == 0 && columns % 8 == 4 {
+                self.corner(
+                    [
+                        (rows - 3, 0),
+                        (rows - 2, 0),
+                        (rows - 1, 0),
+                        (0, columns - 2),
+                        (0, columns - 1),
+                        (1, columns - 1),
+                        (2, columns - 1),
+                        (3, columns - 1),
+                    ],
+                    codeword,
+                );
+                codeword += 1;
+            }
+            if row == rows + 4 && column == 2 && columns % 8 == 0 {
+                self.corner(
+                    [
+                        (rows - 1, 0),
+                        (rows - 1, columns - 1),
+                        (0, columns - 3),
+                        (0, columns - 2),
+                        (0, columns - 1),
+                        (1, columns - 3),
+                        (1, columns - 2),
+                        (1, columns - 1),
+                    ],
+                    codeword,
+                );
+                codeword += 1;
+            }
+
+            // Sweep up and to the right
+            loop {
+                if row < rows && column >= 0 && self.bit(row, column).is_none() {
+                    self.utah(row, column, codeword);
+                    codeword += 1;
+                }
+                row -= 2;
+                column += 2;
+                if row < 0 || column >= columns {
+                    break;
+                }
+            }
+            row += 1;
+            column += 3;
+
+            // Then down and to the left
+            loop {
+                if row >= 0 && column < columns && self.bit(row, column).is_none() {
+                    self.utah(row, column, codeword);
+                    codeword += 1;
+                }
+                row += 2;
+                column -= 2;
+                if row >= rows || column < 0 {
+                    break;
+                }
+            }
+            row += 3;
+            column += 1;
+
+            if row >= rows && column >= columns {
+                break;
+            }
+        }
+
+        // Symbols with modules left over fill the bottom right corner with a fixed pattern
+        if self.bit(rows - 1, columns - 1).is_none() {
+            self.set(rows - 1, columns - 1, true);
+            self.set(rows - 2, columns - 2, true);
+        }
+    }
+
+    fn bit(&self, row: i32, column: i32) -> Option<bool> {
+        self.bits[row as usize * self.columns + column as usize]
+    }
+
+    fn set(&mut self, row: i32, column: i32, dark: bool) {
+        self.bits[row as usize * self.columns + column as usize] = Some(dark);
+    }
+
+    /// Places bit `bit` (0 the most significant) of a codeword, wrapping around the edges.
+    fn module(&mut self, mut row: i32, mut column: i32, codeword: usize, bit: usize) {
+        let (rows, columns) = (self.rows as i32, self.columns as i32);
+        if row < 0 {
+            row += rows;
+            column += 4 - ((rows + 4) % 8);
+        }
+        if column < 0 {
+            column += columns;
+            row += 4 - ((columns + 4) % 8);
+        }
+        let value = self
+            .codewords
+            .get(codeword)
+            .is_some_and(|value| (value >> (7 - bit)) & 1 != 0);
+        self.set(row, column, value);
+    }
+
+    /// Places a codeword in the standard L-shaped block ending at `row` and `column`.
+    fn utah(&mut self, row: i32, column: i32, codeword: usize) {
+        let offsets = [
+            (-2, -2),
+            (-2, -1),
+            (-1, -2),
+            (-1, -1),
+            (-1, 0),
+            (0, -2),
+            (0, -1),
+            (0, 0),
+        ];
+        for (bit, (dy, dx)) in offsets.into_iter().enumerate() {
+            self.module(row + dy, column + dx, codeword, bit);
+        }
+    }
+
+    fn corner(&mut self, positions: [(i32, i32); 8], codeword: usize) {
+        for (bit, (row, column)) in positions.into_iter().enumerate() {
+            self.module(row, column, codeword, bit);
+        }
+    }
+}
+
+/// A Data Matrix code drawn into a field with whole-pixel modules.
+#[derive(Clone, Debug)]
+pub struct DataMatrix {
+    /// The encoded text.
+    pub content: String,
+    pub symbol: DataMatrixSymbol,
+    pub config: DataMatrixConfig,
+    pub field: Rectangle,
+}
+
+impl DataMatrix {
+    /// Encodes the content for drawing in the field.
+    pub fn new(content: &str, field: Rectangle, config: DataMatrixConfig) -> Result<Self, String> {
+        Ok(DataMatrix {
+            content: content.to_string(),
+            symbol: DataMatrixSymbol::encode(content, &config)?,
+            config,
+            field,
+        })
+    }
+
+    /// Draws the symbol as large as fits in the field, returning where it was placed.
+    pub fn draw_checked(&self, image: &mut RgbaImage) -> Result<GridPlacement, String> {
+        let style = GridStyle {
+            foreground_color: self.config.foreground_color,
+            background_color: self.config.background_color,
+            quiet_zone: self.config.quiet_zone,
+            row_height: 1,
+        };
+        draw_grid(image, &self.symbol.modules, &self.field, &style)
+    }
+
+    /// Returns the symbol with its quiet zone as rectangles in module units.
+    pub fn geometry(&self) -> SymbolGeometry {
+        self.symbol.modules.geometry(self.config.quiet_zone, 1)
+    }
+}
+
+impl Drawable for DataMatrix {
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError> {
+        let mut img = image.to_rgba8();
+        self.draw_checked(&mut img)
+            .map_err(|error| ImageSlapperError::barcode("Data Matrix", error))?;
+        *image = DynamicImage::ImageRgba8(img);
+        Ok(())
+    }
+}
+
+/// Generates a Data Matrix image of the given size, with the symbol as large as fits.
+pub fn from(
+    content: &str,
+    width: u32,
+    height: u32,
+    config: DataMatrixConfig,
+) -> Result<DynamicImage, String> {
+    let code = DataMatrix::new(content, Rectangle::from_xywh(0, 0, width, height), config)?;
+    let background = config.background_color;
+    let mut image = RgbaImage::from_pixel(
+        width,
+        height,
+        image::Rgba([background.r, background.g, background.b, background.a]),
+    );
+    code.draw_checked(&mut image)?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
@@ -0,0 +1,287 @@
+use image::RgbaImage;
+use rgb::Rgba;
+use rusttype::Font;
+use tracing::warn;
+
+use crate::geometry::rectangle::Rectangle;
+use crate::text::hyphenate::Hyphenation;
+use crate::text::layout::{self, LayoutOptions, TextLayout};
+use crate::text::paragraph::ParagraphStyle;
+use crate::text::rich::{FontFamily, Span, SpanStyle};
+use crate::text::{TextAlignment, WritingMode};
+
+/// A module width, in canvas pixels or in physical units converted with the canvas DPI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModuleWidth {
+    Pixels(u32),
+    Millimeters(f32),
+}
+
+/// What to do when a barcode only fits with modules narrower than the minimum.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NarrowModules {
+    /// Log a warning and draw the barcode anyway.
+    #[default]
+    Warn,
+    /// Return an error instead of drawing.
+    Fail,
+}
+
+/// Settings for drawing linear barcodes pixel exact.
+#[derive(Clone, Copy, Debug)]
+pub struct LinearOptions {
+    /// Narrowest module scanners can read reliably, e.g. the X-dimension from a labeling spec.
+    pub min_module_width: ModuleWidth,
+    pub narrow_modules: NarrowModules,
+    /// Resolution of the canvas in dots per inch, needed for millimeter widths and snapping.
+    pub dpi: Option<f32>,
+    /// Resolution of the printer the canvas is printed on. When it differs from `dpi`, modules
+    /// and their offset are snapped so every bar edge lands on the printer's dot grid.
+    pub printer_dpi: Option<f32>,
+    pub foreground: Rgba<u8>,
+    /// Color of spaces and leftover area, `None` to leave the canvas untouched.
+    pub background: Option<Rgba<u8>>,
+}
+
+impl Default for LinearOptions {
+    fn default() -> Self {
+        LinearOptions {
+            min_module_width: ModuleWidth::Pixels(1),
+            narrow_modules: NarrowModules::Warn,
+            dpi: None,
+            printer_dpi: None,
+            foreground: Rgba::new(0, 0, 0, 255),
+            background: Some(Rgba::new(255, 255, 255, 255)),
+        }
+    }
+}
+
+/// Which side of the bars human-readable text goes on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextPosition {
+    #[default]
+    Below,
+    Above,
+}
+
+/// Human-readable text printed with a linear barcode.
+#[derive(Clone, Debug)]
+pub struct HumanReadable<'a> {
+    pub font: Font<'a>,
+    /// Largest font size in pixels, the text shrinks to fit the width of the bars.
+    pub font_size: f32,
+    pub position: TextPosition,
+    /// Space between the bars and the text in modules.
+    pub gap: u32,
+    /// Text color, `None` for the color of the bars.
+    pub color: Option<Rgba<u8>>,
+}
+
+impl<'a> HumanReadable<'a> {
+    /// Creates text below the bars, one module away from them.
+    pub fn new(font: Font<'a>, font_size: f32) -> Self {
+        HumanReadable {
+            font,
+            font_size,
+            position: TextPosition::Below,
+            gap: 1,
+            color: None,
+        }
+    }
+
+    fn layout(&self, content: &str, size: f32, color: Rgba<u8>) -> (Vec<Span<'a>>, TextLayout<'a>) {
+        let mut style = SpanStyle::new(FontFamily::new(self.font.clone()), size);
+        style.color = color;
+        let spans = vec![Span::new(content, style)];
+        let options = LayoutOptions {
+            max_width: None,
+            alignment: TextAlignment::Left,
+            line_height: None,
+            word_wrap: false,
+            writing_mode: WritingMode::Horizontal,
+            paragraph: ParagraphStyle::default(),
+            hyphenation: Hyphenation::default(),
+        };
+        let layout = layout::layout(&spans, &options);
+        (spans, layout)
+    }
+
+    /// Height of the glyph outlines of `content` at a font size.
+    pub fn ink_height(&self, content: &str, size: f32) -> f32 {
+        let (_, layout) = self.layout(content, size, Rgba::new(0, 0, 0, 255));
+        layout.ink_bounds().map_or(0.0, |bounds| bounds.height)
+    }
+
+    /// Picks the largest font size up to `font_size` at which every segment of text fits its
+    /// width in pixels and the text is at most `max_height` tall.
+    pub fn fit(&self, segments: &[(&str, f32)], max_height: f32) -> f32 {
+        let black = Rgba::new(0, 0, 0, 255);
+        let mut size = self.font_size;
+        for &(content, width) in segments {
+            let (_, layout) = self.layout(content, self.font_size, black);
+            if layout.width > 0.0 {
+                size = size.min(self.font_size * width / layout.width);
+            }
+        }
+        let all: String = segments.iter().map(|&(content, _)| content).collect();
+        let height = self.ink_height(&all, self.font_size);
+        if height > 0.0 {
+            size = size.min(self.font_size * max_height / height);
+        }
+        size.max(1.0)
+    }
+
+    /// Draws `content` centered between `x1` and `x2` with the top of its outlines at `top`.
+    pub fn draw(
+        &self,
+        image: &mut RgbaImage,
+        content: &str,
+        size: f32,
+        (x1, x2): (f32, f32),
+        top: f32,
+        foreground: Rgba<u8>,
+    ) {
+        let (spans, layout) = self.layout(content, size, self.color.unwrap_or(foreground));
+        let Some(ink) = layout.ink_bounds() else {
+            return;
+        };
+        let origin = ((x1 + x2 - ink.width) / 2.0 - ink.x, top - ink.y);
+        layout::render(&layout, &spans, image, origin);
+    }
+}
+
+/// Where a barcode's modules were drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinearPlacement {
+    /// Width of one module in pixels.
+    pub module_width: u32,
+    /// Left edge of the first module.
+    pub x: u32,
+    /// Width of all modules together.
+    pub width: u32,
+}
+
+impl LinearOptions {
+    /// Returns the minimum module width in pixels.
+    pub fn min_module_pixels(&self) -> Result<u32, String> {
+        match self.min_module_width {
+            ModuleWidth::Pixels(pixels) => Ok(pixels.max(1)),
+            ModuleWidth::Millimeters(millimeters) => {
+                let dpi = self
+                    .dpi
+                    .ok_or("a minimum module width in millimeters needs the canvas DPI")?;
+                Ok(((millimeters / 25.4 * dpi).ceil() as u32).max(1))
+            }
+        }
+    }
+
+    /// Smallest number of canvas pixels that covers a whole number of printer dots, to within a
+    /// twentieth of a dot, or 1 without snapping.
+    fn dot_step(&self) -> Result<u32, String> {
+        let (Some(dpi), Some(printer_dpi)) = (self.dpi, self.printer_dpi) else {
+            return Ok(1);
+        };
+        if dpi <= 0.0 || printer_dpi <= 0.0 {
+            return Err(format!("invalid DPI {dpi} or printer DPI {printer_dpi}"));
+        }
+        (1..=64)
+            .find(|&pixels| {
+                let dots = pixels as f32 * printer_dpi / dpi;
+                dots.round() >= 1.0 && (dots - dots.round()).abs() < 0.05
+            })
+            .ok_or_else(|| {
+                format!("a {dpi} DPI canvas cannot be snapped to a {printer_dpi} DPI printer")
+            })
+    }
+
+    /// Picks the widest integer module width that fits `modules` into the `available` pixels
+    /// starting at `x`, and centers the barcode in that space.
+    pub fn fit(&self, modules: usize, x: u32, available: u32) -> Result<LinearPlacement, String> {
+        if modules == 0 {
+            return Err("barcode has no modules".to_string());
+        }
+        let step = self.dot_step()?;
+        let widest = available / modules as u32;
+        let module_width = widest - widest % step;
+        if module_width == 0 {
+            return Err(format!(
+                "barcode of {modules} modules does not fit in {available} pixels"
+            ));
+        }
+
+        let minimum = self.min_module_pixels()?;
+        if module_width < minimum {
+            let message =
+                format!("module width of {module_width}px is below the minimum of {minimum}px");
+            match self.narrow_modules {
+                NarrowModules::Warn => warn!("{message}"),
+                NarrowModules::Fail => return Err(message),
+            }
+        }
+
+        let width = module_width * modules as u32;
+        let centered = x + (available - width) / 2;
+        // Move onto the dot grid, staying inside the available space
+        let snapped = [centered - centered % step, centered.next_multiple_of(step)]
+            .into_iter()
+            .filter(|&snapped| snapped >= x && snapped + width <= x + available)
+            .min_by_key(|&snapped| snapped.abs_diff(centered))
+            .unwrap_or(centered);
+        Ok(LinearPlacement {
+            module_width,
+            x: snapped,
+            width,
+        })
+    }
+}
+
+/// Draws modules (1 for bar, 0 for space) into `area` with whole-pixel bars, centered
+/// horizontally and filling the area's height.
+pub fn draw_modules(
+    image: &mut RgbaImage,
+    modules: &[u8],
+    area: &Rectangle,
+    options: &LinearOptions,
+) -> Result<LinearPlacement, String> {
+    let area = area.intersection(&Rectangle::from_xywh(0, 0, image.width(), image.height()));
+    let placement = options.fit(modules.len(), area.x1, area.width)?;
+
+    if let Some(background) = options.background {
+        fill(image, area.x1, area.y1, area.x2, area.y2, background);
+    }
+
+    // Draw each run of bars as one block
+    let mut index = 0;
+    while index < modules.len() {
+        if modules[index] == 0 {
+            index += 1;
+            continue;
+        }
+        let run = modules[index..]
+            .iter()
+            .take_while(|&&module| module != 0)
+            .count();
+        let x = placement.x + index as u32 * placement.module_width;
+        fill(
+            image,
+            x,
+            area.y1,
+            x + run as u32 * placement.module_width,
+            area.y2,
+            options.foreground,
+        );
+        index += run;
+    }
+
+    Ok(placement)
+}
+
+/// Paints a block of whole pixels.
+pub fn fill(image: &mut RgbaImage, x1: u32, y1: u32, x2: u32, y2: u32, color: Rgba<u8>) {
+    let pixel = image::Rgba([color.r, color.g, color.b, color.a]);
+    for y in y1..y2.min(image.height()) {
+        for x in x1..x2.min(image.width()) {
+            image.put_pixel(x, y, pixel);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use image::RgbaImage;
+use rgb::Rgba;
+
+use crate::geometry::rectangle::Rectangle;
+
+use super::vector::SymbolGeometry;
+
+/// A grid of dark and light modules making up a 2D symbol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleGrid {
+    pub width: usize,
+    pub height: usize,
+    modules: Vec<bool>,
+}
+
+/// Where a 2D symbol was drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GridPlacement {
+    /// Width of one module in pixels.
+    pub module_size: u32,
+    /// Top-left corner of the symbol with its quiet zone.
+    pub x: u32,
+    pub y: u32,
+}
+
+impl ModuleGrid {
+    /// Creates a grid with every module light.
+    pub fn new(width: usize, height: usize) -> Self {
+        ModuleGrid {
+            width,
+            height,
+            modules: vec![false; width * height],
+        }
+    }
+
+    /// Whether the module at column `x` and row `y` is dark, light outside the grid.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.modules[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, dark: bool) {
+        if x < self.width && y < self.height {
+            self.modules[y * self.width + x] = dark;
+        }
+    }
+
+    /// Returns the dark modules as rectangles in module units, with rows `row_height` modules
+    /// high inside a quiet zone.
+    pub fn geometry(&self, quiet_zone: u32, row_height: u32) -> SymbolGeometry {
+        SymbolGeometry::from_grid(
+            self.width,
+            self.height,
+            quiet_zone,
+            row_height.max(1) as f32,
+            |x, y| self.get(x, y),
+        )
+    }
+}
+
+/// Settings shared by the drawing of 2D symbols.
+#[derive(Clone, Copy, Debug)]
+pub struct GridStyle {
+    pub foreground_color: Rgba<u8>,
+    pub background_color: Rgba<u8>,
+    /// Light border in modules around the symbol.
+    pub quiet_zone: u32,
+    /// Height of a row in modules, more than 1 for stacked symbologies like PDF417.
+    pub row_height: u32,
+}
+
+/// Draws a grid into `field` with the largest whole-pixel module size that fits, centered.
+pub fn draw_grid(
+    image: &mut RgbaImage,
+    grid: &ModuleGrid,
+    field: &Rectangle,
+    style: &GridStyle,
+) -> Result<GridPlacement, String> {
+    let row_height = style.row_height.max(1);
+    let columns = grid.width as u32 + style.quiet_zone * 2;
+    let rows = grid.height as u32 * row_height + style.quiet_zone * 2;
+    let module_size = (field.width / columns).min(field.height / rows);
+    if module_size == 0 {
+        return Err(format!(
+            "symbol of {}x{} modules does not fit in {}x{}",
+            columns, rows, field.width, field.height
+        ));
+    }
+
+    let (width, height) = (columns * module_size, rows * module_size);
+    let placement = GridPlacement {
+        module_size,
+        x: field.x1 + (field.width - width) / 2,
+        y: field.y1 + (field.height - height) / 2,
+    };
+    let color = |color: Rgba<u8>| image::Rgba([color.r, color.g, color.b, color.a]);
+    let (foreground, background) = (color(style.foreground_color), color(style.background_color));
+
+    let quiet = style.quiet_zone as usize;
+    for y in 0..height.min(image.height().saturating_sub(placement.y)) {
+        let row = (y / module_size) as usize;
+        for x in 0..width.min(image.width().saturating_sub(placement.x)) {
+            let column = (x / module_size) as usize;
+            let dark = column >= quiet
+                && row >= quiet
+                && grid.get(column - quiet, (row - quiet) / row_height as usize);
+            image.put_pixel(
+                placement.x + x,
+                placement.y + y,
+                if dark { foreground } else { background },
+            );
+        }
+    }
+    Ok(placement)
+}
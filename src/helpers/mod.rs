@@ -1 +1,11 @@
+pub mod aztec;
+pub mod barcode;
 pub mod code128;
+pub mod datamatrix;
+pub mod linear;
+pub mod matrix;
+pub mod pdf417;
+pub mod qr;
+pub mod reed_solomon;
+pub mod vector;
+pub mod verify;
@@ -0,0 +1,1022 @@
+use image::{DynamicImage, RgbaImage, imageops};
+use rgb::Rgba;
+use tracing::debug;
+
+use crate::error::ImageSlapperError;
+use crate::geometry::rectangle::Rectangle;
+use crate::rendering::draw::Drawable;
+
+use super::vector::SymbolGeometry;
+
+/// Error correction codewords per block, by level and version.
+const ECC_CODEWORDS_PER_BLOCK: [[u8; 41]; 4] = [
+    [
+        0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28,
+        30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+    [
+        0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28,
+        28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    ],
+    [
+        0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30,
+        30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+    [
+        0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24,
+        30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+];
+
+/// Number of error correction blocks, by level and version.
+const ERROR_CORRECTION_BLOCKS: [[u8; 41]; 4] = [
+    [
+        0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13,
+        14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25,
+    ],
+    [
+        0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21,
+        23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+    ],
+    [
+        0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29,
+        34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68,
+    ],
+    [
+        0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32,
+        35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81,
+    ],
+];
+
+/// Characters of the alphanumeric mode, in value order.
+const ALPHANUMERIC: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// ECI designator for UTF-8.
+const ECI_UTF8: u32 = 26;
+
+/// Error correction levels, by the share of the symbol that can be restored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorCorrection {
+    /// About 7%.
+    L,
+    /// About 15%.
+    #[default]
+    M,
+    /// About 25%.
+    Q,
+    /// About 30%.
+    H,
+}
+
+impl ErrorCorrection {
+    fn index(self) -> usize {
+        match self {
+            ErrorCorrection::L => 0,
+            ErrorCorrection::M => 1,
+            ErrorCorrection::Q => 2,
+            ErrorCorrection::H => 3,
+        }
+    }
+
+    /// The two level bits of the format information.
+    fn format_bits(self) -> u32 {
+        match self {
+            ErrorCorrection::L => 1,
+            ErrorCorrection::M => 0,
+            ErrorCorrection::Q => 3,
+            ErrorCorrection::H => 2,
+        }
+    }
+}
+
+/// QR encoding modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QrMode {
+    /// Digits, 10 bits per three.
+    Numeric,
+    /// Digits, uppercase and ` $%*+-./:`, 11 bits per two.
+    Alphanumeric,
+    /// Any bytes, 8 bits each.
+    Byte,
+    /// Shift JIS double-byte characters, 13 bits each.
+    Kanji,
+    /// Extended Channel Interpretation, selecting the character set of the segments after it.
+    Eci,
+}
+
+impl QrMode {
+    fn indicator(self) -> u32 {
+        match self {
+            QrMode::Numeric => 0b0001,
+            QrMode::Alphanumeric => 0b0010,
+            QrMode::Byte => 0b0100,
+            QrMode::Kanji => 0b1000,
+            QrMode::Eci => 0b0111,
+        }
+    }
+
+    /// Width of the character count field for a version.
+    fn count_bits(self, version: u8) -> usize {
+        let column = match version {
+            1..=9 => 0,
+            10..=26 => 1,
+            _ => 2,
+        };
+        match self {
+            QrMode::Numeric => [10, 12, 14][column],
+            QrMode::Alphanumeric => [9, 11, 13][column],
+            QrMode::Byte => [8, 16, 16][column],
+            QrMode::Kanji => [8, 10, 12][column],
+            QrMode::Eci => 0,
+        }
+    }
+}
+
+/// A run of data encoded in one mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QrSegment {
+    pub mode: QrMode,
+    /// Number of characters, bytes or Kanji in the segment.
+    pub count: usize,
+    data: Vec<bool>,
+}
+
+impl QrSegment {
+    /// Encodes a string of digits.
+    pub fn numeric(digits: &str) -> Result<Self, String> {
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("`{digits}` is not numeric"));
+        }
+        let mut data = Vec::new();
+        for chunk in digits.as_bytes().chunks(3) {
+            let value = chunk
+                .iter()
+                .fold(0, |value, digit| value * 10 + u32::from(digit - b'0'));
+            push_bits(&mut data, value, chunk.len() * 3 + 1);
+        }
+        Ok(QrSegment {
+            mode: QrMode::Numeric,
+            count: digits.len(),
+            data,
+        })
+    }
+
+    /// Encodes digits, uppercase letters and ` $%*+-./:`.
+    pub fn alphanumeric(text: &str) -> Result<Self, String> {
+        let values = text
+            .chars()
+            .map(|ch| ALPHANUMERIC.find(ch).map(|value| value as u32))
+            .collect::<Option<Vec<u32>>>()
+            .ok_or_else(|| format!("`{text}` is not alphanumeric"))?;
+        let mut data = Vec::new();
+        for pair in values.chunks(2) {
+            match pair {
+                [first, second] => push_bits(&mut data, first * 45 + second, 11),
+                [single] => push_bits(&mut data, *single, 6),
+                _ => {}
+            }
+        }
+        Ok(QrSegment {
+            mode: QrMode::Alphanumeric,
+            count: values.len(),
+            data,
+        })
+    }
+
+    /// Encodes raw bytes.
+    pub fn bytes(bytes: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(bytes.len() * 8);
+        for &byte in bytes {
+            push_bits(&mut data, u32::from(byte), 8);
+        }
+        QrSegment {
+            mode: QrMode::Byte,
+            count: bytes.len(),
+            data,
+        }
+    }
+
+    /// Encodes text made only of Shift JIS double-byte characters.
+    pub fn kanji(text: &str) -> Result<Self, String> {
+        let mut data = Vec::new();
+        let mut count = 0;
+        for ch in text.chars() {
+            let value =
+                kanji_value(ch).ok_or_else(|| format!("`{ch}` cannot be encoded in Kanji mode"))?;
+            push_bits(&mut data, value, 13);
+            count += 1;
+        }
+        Ok(QrSegment {
+            mode: QrMode::Kanji,
+            count,
+            data,
+        })
+    }
+
+    /// Selects a character set by its ECI designator, e.g. 26 for UTF-8.
+    pub fn eci(designator: u32) -> Result<Self, String> {
+        let mut data = Vec::new();
+        match designator {
+            0..128 => push_bits(&mut data, designator, 8),
+            128..16_384 => push_bits(&mut data, 0b10 << 14 | designator, 16),
+            16_384..1_000_000 => push_bits(&mut data, 0b110 << 21 | designator, 24),
+            _ => return Err(format!("ECI designator {designator} is out of range")),
+        }
+        Ok(QrSegment {
+            mode: QrMode::Eci,
+            count: 0,
+            data,
+        })
+    }
+
+    /// Picks the most compact single mode for the text, falling back to UTF-8 bytes marked
+    /// with an ECI for text outside ASCII.
+    pub fn auto(text: &str) -> Vec<QrSegment> {
+        if let Ok(segment) = QrSegment::numeric(text) {
+            return vec![segment];
+        }
+        if let Ok(segment) = QrSegment::alphanumeric(text) {
+            return vec![segment];
+        }
+        if text.is_ascii() {
+            return vec![QrSegment::bytes(text.as_bytes())];
+        }
+        if let Ok(segment) = QrSegment::kanji(text) {
+            return vec![segment];
+        }
+        let mut segments: Vec<QrSegment> = QrSegment::eci(ECI_UTF8).into_iter().collect();
+        segments.push(QrSegment::bytes(text.as_bytes()));
+        segments
+    }
+
+    /// Bits the segment takes in a symbol of the given version, `None` if its count is too long.
+    fn total_bits(&self, version: u8) -> Option<usize> {
+        let count_bits = self.mode.count_bits(version);
+        if count_bits > 0 && self.count >= 1 << count_bits {
+            return None;
+        }
+        Some(4 + count_bits + self.data.len())
+    }
+}
+
+/// Settings for QR codes, named after the `QRCodeConfig` of the template format.
+#[derive(Clone, Copy, Debug)]
+pub struct QRCodeConfig {
+    pub error_correction: ErrorCorrection,
+    /// Mask pattern 0 to 7, `None` to pick the one scanners read best.
+    pub mask_pattern: Option<u8>,
+    /// Version 1 to 40, `None` for the smallest that fits the data.
+    pub version: Option<u8>,
+    pub foreground_color: Rgba<u8>,
+    pub background_color: Rgba<u8>,
+    /// Width of the light border in modules, the standard asks for 4.
+    pub quiet_zone: u32,
+}
+
+impl Default for QRCodeConfig {
+    fn default() -> Self {
+        QRCodeConfig {
+            error_correction: ErrorCorrection::M,
+            mask_pattern: None,
+            version: None,
+            foreground_color: Rgba::new(0, 0, 0, 255),
+            background_color: Rgba::new(255, 255, 255, 255),
+            quiet_zone: 4,
+        }
+    }
+}
+
+/// An encoded QR symbol: a square of dark and light modules.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QrMatrix {
+    pub version: u8,
+    pub size: usize,
+    pub error_correction: ErrorCorrection,
+    pub mask: u8,
+    modules: Vec<bool>,
+    /// Modules of finder, timing, alignment, format and version patterns.
+    function: Vec<bool>,
+}
+
+impl QrMatrix {
+    /// Encodes text with the most compact mode that can represent it.
+    pub fn encode_text(text: &str, config: &QRCodeConfig) -> Result<Self, String> {
+        QrMatrix::encode_segments(&QrSegment::auto(text), config)
+    }
+
+    /// Encodes segments in the smallest version that fits, or the configured one.
+    pub fn encode_segments(segments: &[QrSegment], config: &QRCodeConfig) -> Result<Self, String> {
+        let level = config.error_correction;
+        let versions = match config.version {
+            Some(version @ 1..=40) => version..=version,
+            Some(version) => return Err(format!("QR version {version} is not between 1 and 40")),
+            None => 1..=40,
+        };
+        if let Some(mask) = config.mask_pattern
+            && mask > 7
+        {
+            return Err(format!("QR mask pattern {mask} is not between 0 and 7"));
+        }
+
+        let (version, used) = versions
+            .into_iter()
+            .find_map(|version| {
+                let used = segments
+                    .iter()
+                    .map(|segment| segment.total_bits(version))
+                    .sum::<Option<usize>>()?;
+                (used <= data_codewords(version, level) * 8).then_some((version, used))
+            })
+            .ok_or("data does not fit in a QR code with this error correction")?;
+
+        // Segments, terminator and padding up to the data capacity
+        let capacity = data_codewords(version, level) * 8;
+        let mut bits = Vec::with_capacity(capacity);
+        for segment in segments {
+            push_bits(&mut bits, segment.mode.indicator(), 4);
+            push_bits(
+                &mut bits,
+                segment.count as u32,
+                segment.mode.count_bits(version),
+            );
+            bits.extend_from_slice(&segment.data);
+        }
+        debug_assert_eq!(bits.len(), used);
+        bits.extend(std::iter::repeat_n(false, (capacity - bits.len()).min(4)));
+        bits.extend(std::iter::repeat_n(
+            false,
+            bits.len().next_multiple_of(8) - bits.len(),
+        ));
+        for pad in [0xEC, 0x11].into_iter().cycle() {
+            if bits.len() >= capacity {
+                break;
+            }
+            push_bits(&mut bits, pad, 8);
+        }
+        let data: Vec<u8> = bits
+            .chunks(8)
+            .map(|byte| {
+                byte.iter()
+                    .fold(0, |value, &bit| value << 1 | u8::from(bit))
+            })
+            .collect();
+
+        let mut matrix = QrMatrix::blank(version, level);
+        matrix.place_data(&interleave(&data, version, level));
+
+        let mask = match config.mask_pattern {
+            Some(mask) => mask,
+            None => (0..8)
+                .min_by_key(|&mask| {
+                    let mut candidate = matrix.clone();
+                    candidate.apply_mask(mask);
+                    candidate.penalty()
+                })
+                .unwrap_or(0),
+        };
+        matrix.apply_mask(mask);
+        Ok(matrix)
+    }
+
+    /// Whether the module at column `x` and row `y` is dark.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    /// Returns the dark modules as rectangles in module units inside a quiet zone.
+    pub fn geometry(&self, quiet_zone: u32) -> SymbolGeometry {
+        SymbolGeometry::from_grid(self.size, self.size, quiet_zone, 1.0, |x, y| self.get(x, y))
+    }
+
+    /// Whether the module is part of a function pattern rather than data.
+    pub fn is_function(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.function[y * self.size + x]
+    }
+
+    /// Creates a symbol with its function patterns drawn and no data.
+    fn blank(version: u8, error_correction: ErrorCorrection) -> Self {
+        let size = version as usize * 4 + 17;
+        let mut matrix = QrMatrix {
+            version,
+            size,
+            error_correction,
+            mask: 0,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+
+        for i in 0..size {
+            matrix.set_function(6, i, i % 2 == 0);
+            matrix.set_function(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            matrix.draw_finder(x, y);
+        }
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // Skip the three that would overlap finder patterns
+                let corner =
+                    matches!((i, j), (0, 0)) || (i == 0 && j == last) || (i == last && j == 0);
+                if !corner {
+                    matrix.draw_alignment(x, y);
+                }
+            }
+        }
+        // Reserve the format area now, the real bits are drawn with the mask
+        matrix.draw_format(0);
+        matrix.draw_version();
+        matrix
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        let index = y * self.size + x;
+        self.modules[index] = dark;
+        self.function[index] = true;
+    }
+
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4..=4_i32 {
+            for dx in -4..=4_i32 {
+                let (column, row) = (x as i32 + dx, y as i32 + dy);
+                if (0..self.size as i32).contains(&column) && (0..self.size as i32).contains(&row) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(
+                        column as usize,
+                        row as usize,
+                        distance != 2 && distance != 4,
+                    );
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2..=2_i32 {
+            for dx in -2..=2_i32 {
+                self.set_function(
+                    (x as i32 + dx) as usize,
+                    (y as i32 + dy) as usize,
+                    dx.abs().max(dy.abs()) != 1,
+                );
+            }
+        }
+    }
+
+    /// Draws the error correction level and mask, protected by a BCH code.
+    fn draw_format(&mut self, mask: u8) {
+        let data = self.error_correction.format_bits() << 3 | u32::from(mask);
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        // Around the top left finder
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        // Split between the other two finders
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Draws the version information that symbols from version 7 carry.
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let version = u32::from(self.version);
+        let mut remainder = version;
+        for _ in 0..12 {
+            remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+        }
+        let bits = version << 12 | remainder;
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    /// Returns the data modules in placement order: two-column strips zigzagging up and down
+    /// from the bottom right corner.
+    fn data_positions(&self) -> Vec<(usize, usize)> {
+        let mut positions = Vec::new();
+        let mut right = self.size as i32 - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..self.size {
+                for offset in 0..2 {
+                    let x = (right - offset) as usize;
+                    let y = if upward {
+                        self.size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+                    if !self.is_function(x, y) {
+                        positions.push((x, y));
+                    }
+                }
+            }
+            right -= 2;
+        }
+        positions
+    }
+
+    fn place_data(&mut self, codewords: &[u8]) {
+        for (bit, (x, y)) in self.data_positions().into_iter().enumerate() {
+            let dark = codewords
+                .get(bit / 8)
+                .is_some_and(|codeword| (codeword >> (7 - bit % 8)) & 1 != 0);
+            self.modules[y * self.size + x] = dark;
+        }
+    }
+
+    /// Applies a mask pattern to the data modules and records it in the format information.
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if invert && !self.is_function(x, y) {
+                    self.modules[y * self.size + x] ^= true;
+                }
+            }
+        }
+        self.mask = mask;
+        self.draw_format(mask);
+    }
+
+    /// Scores how hard the symbol is to scan, lower is better.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+        let lines = (0..size).flat_map(|i| {
+            [
+                (0..size).map(|j| self.get(j, i)).collect::<Vec<bool>>(),
+                (0..size).map(|j| self.get(i, j)).collect::<Vec<bool>>(),
+            ]
+        });
+
+        for line in lines {
+            // Runs of five or more modules of one color
+            let mut run = 1;
+            for j in 1..=size {
+                if j < size && line[j] == line[j - 1] {
+                    run += 1;
+                } else {
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+            }
+            // Patterns that look like finders: 1:1:3:1:1 with four light modules on a side
+            let finder = [true, false, true, true, true, false, true];
+            for start in 0..=size.saturating_sub(7) {
+                if line[start..start + 7] == finder {
+                    let light = |range: std::ops::Range<i32>| {
+                        range
+                            .into_iter()
+                            .all(|k| k < 0 || k >= size as i32 || !line[k as usize])
+                    };
+                    let start = start as i32;
+                    if light(start - 4..start) || light(start + 7..start + 11) {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+
+        // Two by two blocks of one color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.get(x, y);
+                if self.get(x + 1, y) == color
+                    && self.get(x, y + 1) == color
+                    && self.get(x + 1, y + 1) == color
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // Balance of dark and light modules
+        let total = size * size;
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let k = (dark * 20)
+            .abs_diff(total * 10)
+            .div_ceil(total)
+            .saturating_sub(1);
+        penalty + k * 10
+    }
+
+    /// Number of data codewords in each block before error correction is added, in
+    /// interleaved order, and the error correction codewords per block.
+    fn blocks(&self) -> (Vec<usize>, usize) {
+        let level = self.error_correction.index();
+        let version = self.version as usize;
+        let blocks = ERROR_CORRECTION_BLOCKS[level][version] as usize;
+        let ecc = ECC_CODEWORDS_PER_BLOCK[level][version] as usize;
+        let raw = raw_data_modules(self.version) / 8;
+        let short = blocks - raw % blocks;
+        let short_length = raw / blocks - ecc;
+        let lengths = (0..blocks)
+            .map(|block| short_length + usize::from(block >= short))
+            .collect();
+        (lengths, ecc)
+    }
+
+    /// Counts, per error correction block, how many codewords touch a module in the region.
+    fn damaged_codewords(&self, x1: usize, y1: usize, x2: usize, y2: usize) -> Vec<usize> {
+        let (lengths, ecc) = self.blocks();
+        let count = lengths.len();
+        let longest = lengths.iter().copied().max().unwrap_or(0);
+
+        // Block of every codeword in interleaved order
+        let mut owners = Vec::new();
+        for index in 0..longest {
+            owners.extend((0..count).filter(|&block| index < lengths[block]));
+        }
+        for _ in 0..ecc {
+            owners.extend(0..count);
+        }
+
+        let mut damaged = vec![std::collections::BTreeSet::new(); count];
+        for (bit, (x, y)) in self.data_positions().into_iter().enumerate() {
+            if (x1..x2).contains(&x)
+                && (y1..y2).contains(&y)
+                && let Some(&block) = owners.get(bit / 8)
+            {
+                damaged[block].insert(bit / 8);
+            }
+        }
+        damaged.iter().map(|codewords| codewords.len()).collect()
+    }
+}
+
+/// A logo drawn over the middle of a QR code, relying on error correction for the hidden data.
+#[derive(Clone)]
+pub struct QrLogo {
+    pub image: DynamicImage,
+    /// Width and height of the hidden area as a fraction of the symbol, without quiet zone.
+    pub size: f32,
+}
+
+/// A QR code drawn into a field with whole-pixel modules.
+#[derive(Clone)]
+pub struct QRCode {
+    /// The encoded text.
+    pub content: String,
+    pub matrix: QrMatrix,
+    pub config: QRCodeConfig,
+    pub field: Rectangle,
+    pub logo: Option<QrLogo>,
+}
+
+impl QRCode {
+    /// Encodes the content for drawing in the field.
+    pub fn new(content: &str, field: Rectangle, config: QRCodeConfig) -> Result<Self, String> {
+        Ok(QRCode {
+            content: content.to_string(),
+            matrix: QrMatrix::encode_text(content, &config)?,
+            config,
+            field,
+            logo: None,
+        })
+    }
+
+    /// Adds a logo, failing if it would hide function patterns or more codewords than error
+    /// correction can restore.
+    pub fn with_logo(mut self, logo: QrLogo) -> Result<Self, String> {
+        let (x1, y1, x2, y2) = logo_modules(self.matrix.size, logo.size);
+        for y in y1..y2 {
+            for x in x1..x2 {
+                if self.matrix.is_function(x, y) && !self.is_alignment(x, y) {
+                    return Err(
+                        "the logo covers the QR code's finder or timing patterns".to_string()
+                    );
+                }
+            }
+        }
+
+        let (_, ecc) = self.matrix.blocks();
+        // Small symbols reserve some codewords to detect misdecodes
+        let reserved = match (self.matrix.version, self.matrix.error_correction) {
+            (1, ErrorCorrection::L) => 3,
+            (1, ErrorCorrection::M) | (2, ErrorCorrection::L) => 2,
+            (1, _) | (3, ErrorCorrection::L) => 1,
+            _ => 0,
+        };
+        let correctable = (ecc - reserved) / 2;
+        let damaged = self.matrix.damaged_codewords(x1, y1, x2, y2);
+        if let Some(worst) = damaged.iter().copied().max()
+            && worst > correctable
+        {
+            return Err(format!(
+                "the logo hides {worst} codewords of a block, error correction restores {correctable}"
+            ));
+        }
+
+        self.logo = Some(logo);
+        Ok(self)
+    }
+
+    /// Returns the module size in pixels and the symbol's top-left corner, quiet zone included.
+    pub fn layout(&self) -> Option<(u32, u32, u32)> {
+        let modules = self.matrix.size as u32 + self.config.quiet_zone * 2;
+        let module = self.field.width.min(self.field.height) / modules;
+        if module == 0 {
+            return None;
+        }
+        let side = module * modules;
+        Some((
+            module,
+            self.field.x1 + (self.field.width - side) / 2,
+            self.field.y1 + (self.field.height - side) / 2,
+        ))
+    }
+
+    /// Returns the symbol with its quiet zone as rectangles in module units. A logo is raster
+    /// only and not part of the geometry.
+    pub fn geometry(&self) -> SymbolGeometry {
+        self.matrix.geometry(self.config.quiet_zone)
+    }
+
+    fn is_alignment(&self, x: usize, y: usize) -> bool {
+        let positions = alignment_positions(self.matrix.version);
+        positions.iter().any(|&ax| ax.abs_diff(x) <= 2)
+            && positions.iter().any(|&ay| ay.abs_diff(y) <= 2)
+            && x > 8
+            && y > 8
+    }
+}
+
+impl Drawable for QRCode {
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError> {
+        let Some((module, left, top)) = self.layout() else {
+            return Err(ImageSlapperError::barcode(
+                "QR code",
+                format!(
+                    "{} modules do not fit in {}x{}",
+                    self.matrix.size, self.field.width, self.field.height
+                ),
+            ));
+        };
+        debug!(
+            "Drawing version {} QR code with {}px modules at ({}, {})",
+            self.matrix.version, module, left, top
+        );
+
+        let mut img = image.to_rgba8();
+        let quiet = self.config.quiet_zone;
+        let side = module * (self.matrix.size as u32 + quiet * 2);
+        let color = |color: Rgba<u8>| image::Rgba([color.r, color.g, color.b, color.a]);
+        let (foreground, background) = (
+            color(self.config.foreground_color),
+            color(self.config.background_color),
+        );
+
+        for y in 0..side {
+            for x in 0..side {
+                let column = (x / module) as usize;
+                let row = (y / module) as usize;
+                let dark = column >= quiet as usize
+                    && row >= quiet as usize
+                    && self
+                        .matrix
+                        .get(column - quiet as usize, row - quiet as usize);
+                if left + x < img.width() && top + y < img.height() {
+                    img.put_pixel(
+                        left + x,
+                        top + y,
+                        if dark { foreground } else { background },
+                    );
+                }
+            }
+        }
+
+        if let Some(logo) = &self.logo {
+            let (x1, y1, x2, y2) = logo_modules(self.matrix.size, logo.size);
+            let origin = (
+                left + (x1 as u32 + quiet) * module,
+                top + (y1 as u32 + quiet) * module,
+            );
+            let size = ((x2 - x1) as u32 * module, (y2 - y1) as u32 * module);
+            for y in origin.1..origin.1 + size.1 {
+                for x in origin.0..origin.0 + size.0 {
+                    if x < img.width() && y < img.height() {
+                        img.put_pixel(x, y, background);
+                    }
+                }
+            }
+            let scaled = logo
+                .image
+                .resize(size.0, size.1, imageops::FilterType::Lanczos3);
+            imageops::overlay(
+                &mut img,
+                &scaled.to_rgba8(),
+                i64::from(origin.0 + (size.0 - scaled.width()) / 2),
+                i64::from(origin.1 + (size.1 - scaled.height()) / 2),
+            );
+        }
+
+        *image = DynamicImage::ImageRgba8(img);
+        Ok(())
+    }
+}
+
+/// Generates a QR code image of the given size, with the symbol as large as fits.
+pub fn from(
+    content: &str,
+    width: u32,
+    height: u32,
+    config: QRCodeConfig,
+) -> Result<DynamicImage, String> {
+    let code = QRCode::new(content, Rectangle::from_xywh(0, 0, width, height), config)?;
+    if code.layout().is_none() {
+        return Err(format!("QR code does not fit in {width}x{height}"));
+    }
+    let background = config.background_color;
+    let mut image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+        width,
+        height,
+        image::Rgba([background.r, background.g, background.b, background.a]),
+    ));
+    code.draw(&mut image).map_err(|error| error.to_string())?;
+    Ok(image)
+}
+
+/// Returns the module area hidden by a centered logo covering `fraction` of the symbol.
+fn logo_modules(size: usize, fraction: f32) -> (usize, usize, usize, usize) {
+    let side = ((size as f32 * fraction.clamp(0.0, 1.0)).round() as usize).min(size);
+    // Keep the area centered on the middle module
+    let side = if side % 2 == size % 2 { side } else { side + 1 }.min(size);
+    let start = (size - side) / 2;
+    (start, start, start + side, start + side)
+}
+
+/// Appends the low `length` bits of `value`, most significant first.
+fn push_bits(bits: &mut Vec<bool>, value: u32, length: usize) {
+    bits.extend((0..length).rev().map(|i| (value >> i) & 1 != 0));
+}
+
+/// Converts a character to its 13-bit Kanji mode value.
+fn kanji_value(ch: char) -> Option<u32> {
+    let mut buffer = [0; 4];
+    let (bytes, _, errors) = encoding_rs::SHIFT_JIS.encode(ch.encode_utf8(&mut buffer));
+    let [high, low] = bytes[..] else {
+        return None;
+    };
+    if errors {
+        return None;
+    }
+    let code = u32::from(high) << 8 | u32::from(low);
+    let offset = match code {
+        0x8140..=0x9FFC => code - 0x8140,
+        0xE040..=0xEBBF => code - 0xC140,
+        _ => return None,
+    };
+    Some((offset >> 8) * 0xC0 + (offset & 0xFF))
+}
+
+/// Modules available for data and error correction in a version.
+fn raw_data_modules(version: u8) -> usize {
+    let version = version as usize;
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+/// Data codewords a version holds at an error correction level.
+fn data_codewords(version: u8, level: ErrorCorrection) -> usize {
+    let index = level.index();
+    raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[index][version as usize] as usize
+            * ERROR_CORRECTION_BLOCKS[index][version as usize] as usize
+}
+
+/// Centers of alignment patterns along each axis.
+fn alignment_positions(version: u8) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let version = version as usize;
+    let count = version / 7 + 2;
+    // Version 32 is the one spacing the standard does not round this way
+    let step = if version == 32 {
+        26
+    } else {
+        (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2
+    };
+    let mut positions = vec![6];
+    let mut position = version * 4 + 17 - 7;
+    for _ in 0..count - 1 {
+        positions.insert(1, position);
+        position -= step;
+    }
+    positions
+}
+
+/// Splits the data into blocks, adds Reed-Solomon error correction to each and interleaves them.
+fn interleave(data: &[u8], version: u8, level: ErrorCorrection) -> Vec<u8> {
+    let index = level.index();
+    let blocks = ERROR_CORRECTION_BLOCKS[index][version as usize] as usize;
+    let ecc = ECC_CODEWORDS_PER_BLOCK[index][version as usize] as usize;
+    let raw = raw_data_modules(version) / 8;
+    let short = blocks - raw % blocks;
+    let short_length = raw / blocks;
+    let divisor = reed_solomon_divisor(ecc);
+
+    let mut split = Vec::with_capacity(blocks);
+    let mut start = 0;
+    for block in 0..blocks {
+        let length = short_length - ecc + usize::from(block >= short);
+        let chunk = &data[start..start + length];
+        start += length;
+        split.push((chunk.to_vec(), reed_solomon_remainder(chunk, &divisor)));
+    }
+
+    let mut result = Vec::with_capacity(raw);
+    let longest = short_length - ecc + 1;
+    for i in 0..longest {
+        result.extend(split.iter().filter_map(|(data, _)| data.get(i)));
+    }
+    for i in 0..ecc {
+        result.extend(split.iter().map(|(_, ecc)| ecc[i]));
+    }
+    result
+}
+
+/// Multiplies in GF(2^8) with the QR polynomial 0x11D.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut product: u16 = 0;
+    for i in (0..8).rev() {
+        product = (product << 1) ^ ((product >> 7) * 0x11D);
+        product ^= u16::from((y >> i) & 1) * u16::from(x);
+    }
+    product as u8
+}
+
+/// Generator polynomial for `degree` error correction codewords, highest term omitted.
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+/// Computes the error correction codewords for a block of data.
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (value, &coefficient) in result.iter_mut().zip(divisor) {
+            *value ^= gf_multiply(coefficient, factor);
+        }
+    }
+    result
+}
@@ -0,0 +1,71 @@
+/// Arithmetic in a Galois field GF(2^m), as used by the error correction of 2D symbologies.
+#[derive(Clone, Debug)]
+pub struct GaloisField {
+    /// Powers of the generator, `exp[i]` being 2^i.
+    exp: Vec<u16>,
+    /// Logarithms, `log[exp[i]] == i`.
+    log: Vec<u16>,
+    /// First power of the generator that is a root of the code's generator polynomial.
+    base: usize,
+}
+
+impl GaloisField {
+    /// Creates the field of `size` elements reduced by the primitive polynomial `primitive`,
+    /// with error correction roots starting at 2^`base`.
+    pub fn new(size: usize, primitive: u32, base: usize) -> Self {
+        let mut exp = vec![0; size];
+        let mut log = vec![0; size];
+        let mut value: u32 = 1;
+        for (power, slot) in exp.iter_mut().enumerate() {
+            *slot = value as u16;
+            if power < size - 1 {
+                log[value as usize] = power as u16;
+            }
+            value <<= 1;
+            if value as usize >= size {
+                value ^= primitive;
+            }
+        }
+        GaloisField { exp, log, base }
+    }
+
+    pub fn multiply(&self, a: u16, b: u16) -> u16 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        let order = self.exp.len() - 1;
+        self.exp[(self.log[a as usize] as usize + self.log[b as usize] as usize) % order]
+    }
+
+    /// Coefficients of the generator polynomial for `degree` check words, highest term omitted
+    /// and the next highest first.
+    fn generator(&self, degree: usize) -> Vec<u16> {
+        // Product of (x - 2^i) for `degree` consecutive i, subtraction being xor
+        let mut generator = vec![1];
+        for i in 0..degree {
+            let root = self.exp[(self.base + i) % (self.exp.len() - 1)];
+            let mut next = vec![0; generator.len() + 1];
+            for (j, &coefficient) in generator.iter().enumerate() {
+                next[j] ^= coefficient;
+                next[j + 1] ^= self.multiply(coefficient, root);
+            }
+            generator = next;
+        }
+        generator.remove(0);
+        generator
+    }
+
+    /// Computes `count` check words for the data words, which must be smaller than the field.
+    pub fn check_words(&self, data: &[u16], count: usize) -> Vec<u16> {
+        let generator = self.generator(count);
+        let mut remainder = vec![0; count];
+        for &word in data {
+            let factor = word ^ remainder.remove(0);
+            remainder.push(0);
+            for (value, &coefficient) in remainder.iter_mut().zip(&generator) {
+                *value ^= self.multiply(coefficient, factor);
+            }
+        }
+        remainder
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt::Write;
+
+use image::RgbaImage;
+use rgb::Rgba;
+
+use super::linear::fill;
+
+/// Points per millimeter, the unit of PDF page coordinates.
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+
+/// A dark rectangle of a symbol, in modules from the top-left corner of its quiet zone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModuleRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// The dark parts of a symbol as rectangles in module units, quiet zones included, so any
+/// backend can draw it exactly at any resolution.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolGeometry {
+    pub width: f32,
+    pub height: f32,
+    pub rects: Vec<ModuleRect>,
+}
+
+impl SymbolGeometry {
+    /// Builds the geometry of a linear symbol from its modules (1 for bar, 0 for space), with
+    /// bars `height` modules high and quiet zones on the left and right.
+    pub fn from_modules(modules: &[u8], height: f32, (left, right): (u32, u32)) -> Self {
+        let mut geometry = SymbolGeometry {
+            width: (left as usize + modules.len() + right as usize) as f32,
+            height,
+            rects: Vec::new(),
+        };
+        for (start, run) in runs(modules.iter().map(|&module| module != 0)) {
+            geometry.rects.push(ModuleRect {
+                x: (left as usize + start) as f32,
+                y: 0.0,
+                width: run as f32,
+                height,
+            });
+        }
+        geometry
+    }
+
+    /// Builds the geometry of a 2D symbol of `columns` by `rows` modules, merging dark runs along
+    /// each row. Rows are `row_height` modules high, more than 1 for stacked symbologies.
+    pub fn from_grid(
+        columns: usize,
+        rows: usize,
+        quiet_zone: u32,
+        row_height: f32,
+        dark: impl Fn(usize, usize) -> bool,
+    ) -> Self {
+        let quiet = quiet_zone as f32;
+        let mut geometry = SymbolGeometry {
+            width: columns as f32 + quiet * 2.0,
+            height: rows as f32 * row_height + quiet * 2.0,
+            rects: Vec::new(),
+        };
+        for y in 0..rows {
+            for (start, run) in runs((0..columns).map(|x| dark(x, y))) {
+                geometry.rects.push(ModuleRect {
+                    x: quiet + start as f32,
+                    y: quiet + y as f32 * row_height,
+                    width: run as f32,
+                    height: row_height,
+                });
+            }
+        }
+        geometry
+    }
+
+    /// Draws the rectangles with whole-pixel modules, the quiet zone's corner at `x`, `y`.
+    pub fn draw(&self, image: &mut RgbaImage, x: u32, y: u32, module_size: u32, color: Rgba<u8>) {
+        let scale = module_size as f32;
+        for rect in &self.rects {
+            let (x1, y1) = (
+                x + (rect.x * scale).round() as u32,
+                y + (rect.y * scale).round() as u32,
+            );
+            let (x2, y2) = (
+                x + ((rect.x + rect.width) * scale).round() as u32,
+                y + ((rect.y + rect.height) * scale).round() as u32,
+            );
+            fill(image, x1, y1, x2, y2, color);
+        }
+    }
+
+    /// Writes the symbol as an SVG document with modules of `module_size` in `unit`, such as
+    /// `"mm"`, `"in"` or `"px"`. Dark areas are one path, with crisp edges.
+    pub fn to_svg(
+        &self,
+        module_size: f32,
+        unit: &str,
+        foreground: Rgba<u8>,
+        background: Option<Rgba<u8>>,
+    ) -> String {
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}{unit}\" height=\"{}{unit}\" \
+             viewBox=\"0 0 {} {}\" shape-rendering=\"crispEdges\">\n",
+            self.width * module_size,
+            self.height * module_size,
+            self.width,
+            self.height
+        );
+        if let Some(background) = background {
+            let _ = writeln!(
+                svg,
+                "<rect width=\"{}\" height=\"{}\" {}/>",
+                self.width,
+                self.height,
+                svg_fill(background)
+            );
+        }
+        let mut path = String::new();
+        for rect in &self.rects {
+            let _ = write!(
+                path,
+                "M{} {}h{}v{}h-{}z",
+                rect.x, rect.y, rect.width, rect.height, rect.width
+            );
+        }
+        let _ = writeln!(svg, "<path {} d=\"{path}\"/>", svg_fill(foreground));
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Writes PDF content stream operators that fill the symbol with its quiet zone's top-left
+    /// corner at `x`, `y` in points on a page `page_height` points high, for embedding in a page.
+    pub fn to_pdf_operators(
+        &self,
+        module_size: f32,
+        (x, y): (f32, f32),
+        page_height: f32,
+        foreground: Rgba<u8>,
+    ) -> String {
+        let channel = |value: u8| f32::from(value) / 255.0;
+        let mut operators = format!(
+            "{} {} {} rg\n",
+            channel(foreground.r),
+            channel(foreground.g),
+            channel(foreground.b)
+        );
+        // PDF measures upwards from the bottom of the page
+        for rect in &self.rects {
+            let _ = writeln!(
+                operators,
+                "{} {} {} {} re",
+                x + rect.x * module_size,
+                page_height - y - (rect.y + rect.height) * module_size,
+                rect.width * module_size,
+                rect.height * module_size
+            );
+        }
+        operators.push_str("f\n");
+        operators
+    }
+
+    /// Writes a single page PDF document exactly the size of the symbol, with modules
+    /// `module_size_mm` millimeters wide.
+    pub fn to_pdf(&self, module_size_mm: f32, foreground: Rgba<u8>) -> Vec<u8> {
+        let module_size = module_size_mm * POINTS_PER_MM;
+        let (width, height) = (self.width * module_size, self.height * module_size);
+        let content = self.to_pdf_operators(module_size, (0.0, 0.0), height, foreground);
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {width} {height}] /Contents 4 0 R >>"
+            ),
+            format!(
+                "<< /Length {} >>\nstream\n{content}endstream",
+                content.len()
+            ),
+        ];
+
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::new();
+        for (index, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            let _ = write!(pdf, "{} 0 obj\n{object}\nendobj\n", index + 1);
+        }
+        let xref = pdf.len();
+        let _ = write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(pdf, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            pdf,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        );
+        pdf.into_bytes()
+    }
+
+    /// Writes ZPL graphic boxes for a label printer, with modules `module_dots` printer dots wide
+    /// and the quiet zone's corner at `x`, `y` in dots. The fields go between `^XA` and `^XZ`.
+    pub fn to_zpl(&self, module_dots: u32, x: u32, y: u32) -> String {
+        let dots = |modules: f32| (modules * module_dots as f32).round() as u32;
+        let mut zpl = String::new();
+        for rect in &self.rects {
+            let (width, height) = (dots(rect.width).max(1), dots(rect.height).max(1));
+            let _ = writeln!(
+                zpl,
+                "^FO{},{}^GB{width},{height},{}^FS",
+                x + dots(rect.x),
+                y + dots(rect.y),
+                width.min(height)
+            );
+        }
+        zpl
+    }
+}
+
+/// Finds the runs of set values as start index and length.
+fn runs(values: impl Iterator<Item = bool>) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    let mut length = 0;
+    for (index, value) in values.enumerate() {
+        length = index + 1;
+        match (value, start) {
+            (true, None) => start = Some(index),
+            (false, Some(first)) => {
+                runs.push((first, index - first));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = start {
+        runs.push((first, length - first));
+    }
+    runs
+}
+
+/// SVG fill attributes for a color, with its opacity when not opaque.
+fn svg_fill(color: Rgba<u8>) -> String {
+    let fill = format!("fill=\"#{:02x}{:02x}{:02x}\"", color.r, color.g, color.b);
+    if color.a == 255 {
+        fill
+    } else {
+        format!("{fill} fill-opacity=\"{}\"", f32::from(color.a) / 255.0)
+    }
+}
@@ -0,0 +1,623 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use image::{DynamicImage, GrayImage};
+use rxing::{BarcodeFormat, DecodeHints, RXingResult};
+use tracing::debug;
+
+use crate::geometry::rectangle::Rectangle;
+
+use super::aztec::Aztec;
+use super::barcode::{Barcode, Symbology};
+use super::code128::{Code128Data, parse_gs1};
+use super::datamatrix::DataMatrix;
+use super::pdf417::Pdf417;
+use super::qr::QRCode;
+
+/// Scan lines per symbol, as ISO/IEC 15416 asks for.
+const SCAN_LINES: usize = 10;
+
+/// Symbologies the verifier can read back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolFormat {
+    Code128,
+    Ean13,
+    Ean8,
+    UpcA,
+    UpcE,
+    Code39,
+    Code93,
+    Itf,
+    Codabar,
+    Qr,
+    DataMatrix,
+    Pdf417,
+    Aztec,
+}
+
+impl SymbolFormat {
+    /// Whether the symbology is a 1D barcode, which can be graded.
+    pub fn is_linear(self) -> bool {
+        !matches!(
+            self,
+            SymbolFormat::Qr
+                | SymbolFormat::DataMatrix
+                | SymbolFormat::Pdf417
+                | SymbolFormat::Aztec
+        )
+    }
+
+    fn decoder_format(self) -> BarcodeFormat {
+        match self {
+            SymbolFormat::Code128 => BarcodeFormat::CODE_128,
+            SymbolFormat::Ean13 => BarcodeFormat::EAN_13,
+            SymbolFormat::Ean8 => BarcodeFormat::EAN_8,
+            SymbolFormat::UpcA => BarcodeFormat::UPC_A,
+            SymbolFormat::UpcE => BarcodeFormat::UPC_E,
+            SymbolFormat::Code39 => BarcodeFormat::CODE_39,
+            SymbolFormat::Code93 => BarcodeFormat::CODE_93,
+            SymbolFormat::Itf => BarcodeFormat::ITF,
+            SymbolFormat::Codabar => BarcodeFormat::CODABAR,
+            SymbolFormat::Qr => BarcodeFormat::QR_CODE,
+            SymbolFormat::DataMatrix => BarcodeFormat::DATA_MATRIX,
+            SymbolFormat::Pdf417 => BarcodeFormat::PDF_417,
+            SymbolFormat::Aztec => BarcodeFormat::AZTEC,
+        }
+    }
+}
+
+impl From<Symbology> for SymbolFormat {
+    fn from(symbology: Symbology) -> Self {
+        match symbology {
+            Symbology::Code128 | Symbology::Gs1Code128 => SymbolFormat::Code128,
+            Symbology::Ean13 => SymbolFormat::Ean13,
+            Symbology::Ean8 => SymbolFormat::Ean8,
+            Symbology::UpcA => SymbolFormat::UpcA,
+            Symbology::UpcE => SymbolFormat::UpcE,
+            Symbology::Code39 { .. } => SymbolFormat::Code39,
+            Symbology::Code93 => SymbolFormat::Code93,
+            Symbology::Itf14 { .. } => SymbolFormat::Itf,
+            Symbology::Codabar => SymbolFormat::Codabar,
+        }
+    }
+}
+
+/// A symbol that should be readable in a rendered image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expected {
+    pub format: SymbolFormat,
+    /// The data as a scanner reports it.
+    pub payload: String,
+}
+
+impl Expected {
+    pub fn new(format: SymbolFormat, payload: &str) -> Self {
+        Expected {
+            format,
+            payload: payload.to_string(),
+        }
+    }
+
+    /// Expects GS1 element strings such as `(01)09501101530003(10)ABC`, which scanners report
+    /// without parentheses and with a group separator after variable length fields.
+    pub fn gs1(format: SymbolFormat, input: &str) -> Result<Self, String> {
+        let payload = parse_gs1(input)?
+            .into_iter()
+            .skip(1)
+            .map(|data| match data {
+                Code128Data::Char(byte) => char::from(byte),
+                _ => '\x1D',
+            })
+            .collect();
+        Ok(Expected { format, payload })
+    }
+}
+
+impl From<&Barcode<'_>> for Expected {
+    fn from(barcode: &Barcode<'_>) -> Self {
+        let format = SymbolFormat::from(barcode.symbology);
+        match barcode.symbology {
+            Symbology::Gs1Code128 => Expected::gs1(format, &barcode.text)
+                .unwrap_or_else(|_| Expected::new(format, &barcode.text)),
+            _ => Expected::new(format, &barcode.payload()),
+        }
+    }
+}
+
+impl From<&QRCode> for Expected {
+    fn from(qr: &QRCode) -> Self {
+        Expected::new(SymbolFormat::Qr, &qr.content)
+    }
+}
+
+impl From<&DataMatrix> for Expected {
+    fn from(data_matrix: &DataMatrix) -> Self {
+        let format = SymbolFormat::DataMatrix;
+        if data_matrix.config.gs1 {
+            Expected::gs1(format, &data_matrix.content)
+                .unwrap_or_else(|_| Expected::new(format, &data_matrix.content))
+        } else {
+            Expected::new(format, &data_matrix.content)
+        }
+    }
+}
+
+impl From<&Pdf417> for Expected {
+    fn from(pdf417: &Pdf417) -> Self {
+        Expected::new(SymbolFormat::Pdf417, &pdf417.content)
+    }
+}
+
+impl From<&Aztec> for Expected {
+    fn from(aztec: &Aztec) -> Self {
+        Expected::new(SymbolFormat::Aztec, &aztec.content)
+    }
+}
+
+/// Quality grades from best to worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Grade {
+    A,
+    B,
+    C,
+    D,
+    F,
+}
+
+impl Grade {
+    /// The grade as a number from 4 for A to 0 for F.
+    pub fn value(self) -> f32 {
+        match self {
+            Grade::A => 4.0,
+            Grade::B => 3.0,
+            Grade::C => 2.0,
+            Grade::D => 1.0,
+            Grade::F => 0.0,
+        }
+    }
+
+    /// The letter grade of an average, rounding to the nearest grade.
+    pub fn from_value(value: f32) -> Self {
+        match value {
+            value if value >= 3.5 => Grade::A,
+            value if value >= 2.5 => Grade::B,
+            value if value >= 1.5 => Grade::C,
+            value if value >= 0.5 => Grade::D,
+            _ => Grade::F,
+        }
+    }
+
+    /// Grades a measurement against the lower limits of grades A to D.
+    fn at_least(value: f32, limits: [f32; 4]) -> Self {
+        [Grade::A, Grade::B, Grade::C, Grade::D]
+            .into_iter()
+            .zip(limits)
+            .find_map(|(grade, limit)| (value >= limit).then_some(grade))
+            .unwrap_or(Grade::F)
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Measurements of one scan line across a 1D symbol, with reflectances from 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanGrade {
+    pub min_reflectance: f32,
+    pub max_reflectance: f32,
+    /// Difference between the lightest and darkest reflectance.
+    pub symbol_contrast: f32,
+    /// Smallest difference between a space and the bar next to it.
+    pub edge_contrast: f32,
+    /// Edge contrast relative to symbol contrast.
+    pub modulation: f32,
+    /// Worst dip inside a bar or space relative to symbol contrast, from spots and voids.
+    pub defects: f32,
+    /// Whether the scan line alone decodes to the expected payload.
+    pub decoded: bool,
+    /// The worst grade of the measurements.
+    pub grade: Grade,
+}
+
+/// An estimate of the ISO/IEC 15416 print quality grade of a 1D symbol, from ten scan lines
+/// across the bars. Decodability is not measured and reflectances come from the image's
+/// luminance, so this is no substitute for a calibrated verifier.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolGrade {
+    pub scans: Vec<ScanGrade>,
+    /// Average of the scan grades from 4 to 0.
+    pub value: f32,
+    pub grade: Grade,
+}
+
+/// Settings for reading back symbols.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VerifyOptions {
+    /// Part of the image to search, `None` for all of it.
+    pub region: Option<Rectangle>,
+    /// Worst grade a 1D symbol may have, `None` to only check the payload.
+    pub minimum_grade: Option<Grade>,
+}
+
+/// A symbol found with the expected payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Verification {
+    pub format: SymbolFormat,
+    pub payload: String,
+    /// Print quality of 1D symbols.
+    pub grade: Option<SymbolGrade>,
+}
+
+/// Why a rendered symbol failed verification.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyError {
+    /// No symbol of the format could be read.
+    Unreadable { format: SymbolFormat },
+    /// Symbols were read, but none holds the expected payload.
+    Mismatch {
+        format: SymbolFormat,
+        expected: String,
+        decoded: Vec<String>,
+    },
+    /// The symbol reads correctly but its print quality is below the minimum.
+    LowGrade {
+        format: SymbolFormat,
+        grade: Grade,
+        minimum: Grade,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Unreadable { format } => write!(f, "no {format:?} symbol could be read"),
+            VerifyError::Mismatch {
+                format,
+                expected,
+                decoded,
+            } => write!(
+                f,
+                "{format:?} symbol should read {expected:?}, but read {}",
+                decoded
+                    .iter()
+                    .map(|payload| format!("{payload:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            VerifyError::LowGrade {
+                format,
+                grade,
+                minimum,
+            } => write!(
+                f,
+                "{format:?} symbol grades {grade}, the minimum is {minimum}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Reads the expected symbol back from the pixels of a rendered image, e.g. after compositing,
+/// rotating and scaling, and checks its payload. 1D symbols are graded as well.
+pub fn verify(
+    image: &DynamicImage,
+    expected: &Expected,
+    options: &VerifyOptions,
+) -> Result<Verification, VerifyError> {
+    let luma = match &options.region {
+        Some(region) => image
+            .crop_imm(region.x1, region.y1, region.width, region.height)
+            .to_luma8(),
+        None => image.to_luma8(),
+    };
+    let results = decode(&luma, expected.format);
+    let payloads: Vec<String> = results.iter().map(payload).collect();
+    let Some(index) = payloads
+        .iter()
+        .position(|payload| *payload == expected.payload)
+    else {
+        return Err(if payloads.is_empty() {
+            VerifyError::Unreadable {
+                format: expected.format,
+            }
+        } else {
+            VerifyError::Mismatch {
+                format: expected.format,
+                expected: expected.payload.clone(),
+                decoded: payloads,
+            }
+        });
+    };
+
+    let grade = expected
+        .format
+        .is_linear()
+        .then(|| grade(&luma, &results[index], expected));
+    if let (Some(grade), Some(minimum)) = (&grade, options.minimum_grade)
+        && grade.grade > minimum
+    {
+        return Err(VerifyError::LowGrade {
+            format: expected.format,
+            grade: grade.grade,
+            minimum,
+        });
+    }
+    debug!(
+        "Verified {:?} symbol reading {:?}, grade {:?}",
+        expected.format,
+        expected.payload,
+        grade.as_ref().map(|grade| grade.grade)
+    );
+
+    Ok(Verification {
+        format: expected.format,
+        payload: payloads[index].clone(),
+        grade,
+    })
+}
+
+/// Decodes every symbol of the format in the image.
+fn decode(luma: &GrayImage, format: SymbolFormat) -> Vec<RXingResult> {
+    let (width, height) = luma.dimensions();
+    let mut hints = DecodeHints {
+        TryHarder: Some(true),
+        PossibleFormats: Some(HashSet::from([format.decoder_format()])),
+        AssumeGs1: Some(true),
+        ..Default::default()
+    };
+    let found = rxing::helpers::detect_multiple_in_luma_with_hints(
+        luma.to_vec(),
+        width,
+        height,
+        &mut hints,
+    )
+    .unwrap_or_default();
+    if !found.is_empty() {
+        return found;
+    }
+    rxing::helpers::detect_in_luma_with_hints(luma.to_vec(), width, height, None, &mut hints)
+        .map(|result| vec![result])
+        .unwrap_or_default()
+}
+
+/// The payload of a result, without the GS1-128 symbology identifier.
+fn payload(result: &RXingResult) -> String {
+    let text = result.getText();
+    text.strip_prefix("]C1").unwrap_or(text).to_string()
+}
+
+/// Grades a decoded 1D symbol on scan lines parallel to where the decoder read it, spread over
+/// the middle 80% of the bar height.
+fn grade(luma: &GrayImage, result: &RXingResult, expected: &Expected) -> SymbolGrade {
+    let (width, height) = luma.dimensions();
+    let (start, end) = match result.getPoints() {
+        [first, .., last] if first != last => ((first.x, first.y), (last.x, last.y)),
+        _ => {
+            let middle = height as f32 / 2.0;
+            ((0.0, middle), (width as f32 - 1.0, middle))
+        }
+    };
+    let length = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+    let direction = ((end.0 - start.0) / length, (end.1 - start.1) / length);
+    // Scan across the whole image, the decoder's points do not always span the symbol
+    let reach = (width + height) as f32;
+    let line = ScanLine {
+        start: (start.0 - direction.0 * reach, start.1 - direction.1 * reach),
+        direction,
+        length: reach * 2.0,
+    };
+
+    // Find how far the bars reach across the scan line by the edges staying the same
+    let center = line.profile(luma, 0.0);
+    let edges = |offset: f32| {
+        let profile = line.profile(luma, offset);
+        let threshold = threshold(&center);
+        elements(&profile, threshold).len()
+    };
+    let expected_edges = edges(0.0);
+    let extent = |sign: f32| {
+        (1..reach as usize)
+            .map(|step| step as f32 * sign)
+            .take_while(|&offset| edges(offset) == expected_edges)
+            .last()
+            .unwrap_or(0.0)
+    };
+    let (low, high) = (extent(-1.0), extent(1.0));
+
+    let scans: Vec<ScanGrade> = (0..SCAN_LINES)
+        .map(|index| {
+            let fraction = 0.1 + 0.8 * index as f32 / (SCAN_LINES - 1) as f32;
+            let profile = line.profile(luma, low + (high - low) * fraction);
+            grade_scan(&profile, expected)
+        })
+        .collect();
+    let value = scans.iter().map(|scan| scan.grade.value()).sum::<f32>() / scans.len() as f32;
+    SymbolGrade {
+        scans,
+        value,
+        grade: Grade::from_value(value),
+    }
+}
+
+/// A straight line through the image that reflectance is sampled along.
+struct ScanLine {
+    start: (f32, f32),
+    direction: (f32, f32),
+    length: f32,
+}
+
+impl ScanLine {
+    /// Samples reflectance every pixel along the line shifted sideways by `offset`, stopping at
+    /// the image's edges.
+    fn profile(&self, luma: &GrayImage, offset: f32) -> Vec<f32> {
+        let normal = (-self.direction.1, self.direction.0);
+        (0..self.length as usize)
+            .map(|step| {
+                let along = step as f32;
+                (
+                    self.start.0 + self.direction.0 * along + normal.0 * offset,
+                    self.start.1 + self.direction.1 * along + normal.1 * offset,
+                )
+            })
+            .skip_while(|&(x, y)| sample(luma, x, y).is_none())
+            .map_while(|(x, y)| sample(luma, x, y))
+            .collect()
+    }
+}
+
+/// Reflectance at a point from 0 for black to 1 for white, interpolated between pixels.
+fn sample(luma: &GrayImage, x: f32, y: f32) -> Option<f32> {
+    let (width, height) = luma.dimensions();
+    if x < 0.0 || y < 0.0 || x > (width - 1) as f32 || y > (height - 1) as f32 {
+        return None;
+    }
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let pixel = |x, y| f32::from(luma.get_pixel(x, y).0[0]) / 255.0;
+    let top = pixel(x0, y0) * (1.0 - fx) + pixel(x1, y0) * fx;
+    let bottom = pixel(x0, y1) * (1.0 - fx) + pixel(x1, y1) * fx;
+    Some(top * (1.0 - fy) + bottom * fy)
+}
+
+/// The global threshold halfway between the darkest and lightest reflectance.
+fn threshold(profile: &[f32]) -> f32 {
+    let min = profile.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = profile.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    (min + max) / 2.0
+}
+
+/// Splits a profile into bars and spaces at the threshold, as sample ranges.
+fn elements(profile: &[f32], threshold: f32) -> Vec<std::ops::Range<usize>> {
+    let mut elements = Vec::new();
+    let mut start = 0;
+    for index in 1..=profile.len() {
+        if index == profile.len() || (profile[index] < threshold) != (profile[start] < threshold) {
+            elements.push(start..index);
+            start = index;
+        }
+    }
+    elements
+}
+
+/// Measures one scan profile against the ISO/IEC 15416 parameters.
+fn grade_scan(profile: &[f32], expected: &Expected) -> ScanGrade {
+    if profile.len() < 2 {
+        return ScanGrade {
+            min_reflectance: 0.0,
+            max_reflectance: 0.0,
+            symbol_contrast: 0.0,
+            edge_contrast: 0.0,
+            modulation: 0.0,
+            defects: 1.0,
+            decoded: false,
+            grade: Grade::F,
+        };
+    }
+    let min = profile.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = profile.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let contrast = max - min;
+    let threshold = (min + max) / 2.0;
+    let elements = elements(profile, threshold);
+
+    let darkest = |range: &std::ops::Range<usize>| {
+        profile[range.clone()]
+            .iter()
+            .copied()
+            .fold(f32::INFINITY, f32::min)
+    };
+    let lightest = |range: &std::ops::Range<usize>| {
+        profile[range.clone()]
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max)
+    };
+    let edge_contrast = elements
+        .windows(2)
+        .map(|pair| {
+            let (bar, space) = if profile[pair[0].start] < threshold {
+                (&pair[0], &pair[1])
+            } else {
+                (&pair[1], &pair[0])
+            };
+            lightest(space) - darkest(bar)
+        })
+        .fold(contrast, f32::min);
+
+    // Element reflectance non-uniformity: the deepest dip inside an element, between a peak
+    // and valley that are not at its edges
+    let non_uniformity = elements
+        .iter()
+        .map(|range| {
+            let values = &profile[range.clone()];
+            let bar = values[0] < threshold;
+            let interior = (1..values.len().saturating_sub(1)).filter(|&index| {
+                let (before, value, after) = (values[index - 1], values[index], values[index + 1]);
+                if bar {
+                    value > before && value >= after
+                } else {
+                    value < before && value <= after
+                }
+            });
+            interior
+                .map(|index| {
+                    if bar {
+                        values[index] - darkest(range)
+                    } else {
+                        lightest(range) - values[index]
+                    }
+                })
+                .fold(0.0, f32::max)
+        })
+        .fold(0.0, f32::max);
+
+    let (modulation, defects) = if contrast > 0.0 {
+        (edge_contrast / contrast, non_uniformity / contrast)
+    } else {
+        (0.0, 1.0)
+    };
+    let decoded = decode_profile(profile, expected);
+
+    let grade = [
+        if min <= max * 0.5 { Grade::A } else { Grade::F },
+        Grade::at_least(contrast, [0.70, 0.55, 0.40, 0.20]),
+        if edge_contrast >= 0.15 {
+            Grade::A
+        } else {
+            Grade::F
+        },
+        Grade::at_least(modulation, [0.70, 0.60, 0.50, 0.40]),
+        Grade::at_least(-defects, [-0.15, -0.20, -0.25, -0.30]),
+        if decoded { Grade::A } else { Grade::F },
+    ]
+    .into_iter()
+    .max()
+    .unwrap_or(Grade::F);
+
+    ScanGrade {
+        min_reflectance: min,
+        max_reflectance: max,
+        symbol_contrast: contrast,
+        edge_contrast,
+        modulation,
+        defects,
+        decoded,
+        grade,
+    }
+}
+
+/// Checks whether a single scan profile decodes to the expected payload.
+fn decode_profile(profile: &[f32], expected: &Expected) -> bool {
+    let row: Vec<u8> = profile
+        .iter()
+        .map(|reflectance| (reflectance * 255.0).round() as u8)
+        .collect();
+    let image = GrayImage::from_fn(row.len() as u32, 4, |x, _| image::Luma([row[x as usize]]));
+    decode(&image, expected.format)
+        .iter()
+        .any(|result| payload(result) == expected.payload)
+}
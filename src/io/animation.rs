@@ -0,0 +1,588 @@
+use std::io::{Cursor, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageError, ImageFormat, RgbaImage};
+use rayon::prelude::*;
+
+use super::encode::WebPMode;
+use super::images::{DecodeLimits, DecodeOptions, load_image_from_memory};
+use crate::error::ImageSlapperError;
+use crate::rendering::draw::Drawable;
+
+/// How many times an animation plays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopCount {
+    #[default]
+    Infinite,
+    /// Plays this many times, at least once.
+    Finite(u16),
+}
+
+/// What happens to a frame's area once its delay is over.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Disposal {
+    /// Cleared to transparent, so every frame is written whole. Right for any animation.
+    #[default]
+    Background,
+    /// Left for the next frame to draw over, so only the area that changed is written. Smaller,
+    /// but in GIF a pixel turning transparent shows the previous frame through.
+    Keep,
+    /// Restored to how it was before the frame.
+    Previous,
+}
+
+/// An animated output format with its encoder settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationFormat {
+    /// Every frame gets its own 256 color palette. Speed from 1 (best colors) to 30.
+    Gif {
+        quantization_speed: u8,
+    },
+    Apng,
+    /// libwebp picks the disposal and the changed area of every frame itself.
+    WebP(WebPMode),
+}
+
+/// Settings for encoding an animation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnimationEncodeOptions {
+    pub format: AnimationFormat,
+    pub disposal: Disposal,
+}
+
+impl AnimationEncodeOptions {
+    /// Default settings for a format, `None` when it cannot be animated.
+    pub fn for_format(format: ImageFormat) -> Option<Self> {
+        let format = match format {
+            ImageFormat::Gif => AnimationFormat::Gif {
+                quantization_speed: 10,
+            },
+            ImageFormat::Png => AnimationFormat::Apng,
+            ImageFormat::WebP => AnimationFormat::WebP(WebPMode::Lossless),
+            _ => return None,
+        };
+        Some(AnimationEncodeOptions {
+            format,
+            disposal: Disposal::default(),
+        })
+    }
+}
+
+/// One frame of an animation, covering the whole canvas.
+#[derive(Clone, Debug)]
+pub struct AnimationFrame {
+    pub image: DynamicImage,
+    /// How long the frame shows.
+    pub delay: Duration,
+}
+
+/// Frames shown one after another, all the same size.
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub frames: Vec<AnimationFrame>,
+    pub loop_count: LoopCount,
+}
+
+impl Animation {
+    pub fn new(frames: Vec<AnimationFrame>, loop_count: LoopCount) -> Self {
+        Animation { frames, loop_count }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frames.first().map_or(0, |frame| frame.image.width())
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frames.first().map_or(0, |frame| frame.image.height())
+    }
+
+    /// How long one play of the animation takes.
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|frame| frame.delay).sum()
+    }
+
+    /// Draws onto every frame in parallel, e.g. a watermark over a GIF. Drawing several
+    /// drawables in turn composites the same layer stack onto each frame.
+    pub fn draw<T: Drawable + Sync>(&mut self, drawable: &T) -> Result<(), ImageSlapperError> {
+        self.frames
+            .par_iter_mut()
+            .try_for_each(|frame| drawable.draw(&mut frame.image))
+    }
+
+    /// Encodes the animation into a writer.
+    pub fn encode(
+        &self,
+        options: &AnimationEncodeOptions,
+        writer: &mut impl Write,
+    ) -> Result<(), ImageSlapperError> {
+        encode_animation(self, options, writer)
+    }
+
+    /// Saves the animation as GIF, APNG or WebP by the extension and returns the elapsed time.
+    pub fn save(&self, output_path: &str) -> Result<Duration, ImageSlapperError> {
+        let start = Instant::now();
+        let path = Path::new(output_path);
+        let options = ImageFormat::from_path(path)
+            .ok()
+            .and_then(AnimationEncodeOptions::for_format)
+            .ok_or_else(|| ImageSlapperError::Encode {
+                path: path.to_path_buf(),
+                source: ImageError::Unsupported(ImageFormatHint::PathExtension(path.into()).into()),
+            })?;
+        let bytes = encode_animation_to_vec(self, &options)?;
+        std::fs::write(path, bytes)
+            .map_err(|error| ImageSlapperError::io(path, "write animation", error))?;
+        Ok(start.elapsed())
+    }
+}
+
+/// Loads the frames of a GIF, APNG or animated WebP. Still images load as a single frame.
+pub fn load_animation(
+    image_path: &str,
+    limits: &DecodeLimits,
+) -> Result<Animation, ImageSlapperError> {
+    let path = Path::new(image_path);
+    let bytes =
+        std::fs::read(path).map_err(|error| ImageSlapperError::io(path, "read image", error))?;
+    decode_animation(&bytes, path, limits)
+}
+
+/// Loads the frames of an animation held in memory.
+pub fn load_animation_from_memory(
+    bytes: &[u8],
+    limits: &DecodeLimits,
+) -> Result<Animation, ImageSlapperError> {
+    decode_animation(bytes, Path::new("<memory>"), limits)
+}
+
+fn decode_animation(
+    bytes: &[u8],
+    source: &Path,
+    limits: &DecodeLimits,
+) -> Result<Animation, ImageSlapperError> {
+    let decoded = |error| ImageSlapperError::Decode {
+        path: source.to_path_buf(),
+        source: error,
+    };
+    let format = image::guess_format(bytes).map_err(decoded)?;
+    // Frames are composited onto a canvas of the full logical screen, so its size is checked
+    // before any frame is decoded
+    let image_limits = limits.to_image_limits();
+    let animated = match format {
+        ImageFormat::Gif => {
+            let mut decoder = GifDecoder::new(Cursor::new(bytes)).map_err(decoded)?;
+            decoder.set_limits(image_limits).map_err(decoded)?;
+            // The image crate takes a GIF without a loop extension, which plays once, as
+            // looping forever, so the header is read again for the repeat count
+            let repeat = gif::DecodeOptions::new()
+                .read_info(Cursor::new(bytes))
+                .map_or(gif::Repeat::Infinite, |decoder| decoder.repeat());
+            // GIF counts the repeats after the first play, with 0 for no loop extension
+            let loop_count = match repeat {
+                gif::Repeat::Infinite => LoopCount::Infinite,
+                gif::Repeat::Finite(repeats) => LoopCount::Finite(repeats.saturating_add(1)),
+            };
+            Some((decoder.into_frames(), loop_count))
+        }
+        ImageFormat::Png => {
+            let mut decoder = PngDecoder::new(Cursor::new(bytes)).map_err(decoded)?;
+            decoder.set_limits(image_limits).map_err(decoded)?;
+            if decoder.is_apng().map_err(decoded)? {
+                let decoder = decoder.apng().map_err(decoded)?;
+                let loop_count = plays(decoder.loop_count());
+                Some((decoder.into_frames(), loop_count))
+            } else {
+                None
+            }
+        }
+        ImageFormat::WebP => {
+            let mut decoder = WebPDecoder::new(Cursor::new(bytes)).map_err(decoded)?;
+            decoder.set_limits(image_limits).map_err(decoded)?;
+            if decoder.has_animation() {
+                let loop_count = plays(decoder.loop_count());
+                Some((decoder.into_frames(), loop_count))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+
+    let Some((frames, loop_count)) = animated else {
+        let options = DecodeOptions::from(*limits);
+        let image = load_image_from_memory(bytes, &options)?;
+        return Ok(Animation::new(
+            vec![AnimationFrame {
+                image,
+                delay: Duration::ZERO,
+            }],
+            LoopCount::Infinite,
+        ));
+    };
+
+    // Frames are decoded whole, so the allocation limit covers all of them together
+    let mut allocated = 0u64;
+    let mut animation = Animation::new(Vec::new(), loop_count);
+    for frame in frames {
+        let frame = frame.map_err(decoded)?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let image = DynamicImage::ImageRgba8(frame.into_buffer());
+        limits.check(&image).map_err(decoded)?;
+        allocated += image.as_bytes().len() as u64;
+        if limits.max_alloc.is_some_and(|max| allocated > max) {
+            return Err(decoded(ImageError::Limits(
+                image::error::LimitError::from_kind(
+                    image::error::LimitErrorKind::InsufficientMemory,
+                ),
+            )));
+        }
+        animation.frames.push(AnimationFrame {
+            image,
+            delay: Duration::from_secs_f64(
+                f64::from(numerator) / f64::from(denominator.max(1)) / 1000.0,
+            ),
+        });
+    }
+    Ok(animation)
+}
+
+/// Converts a loop count of plays, as APNG and WebP store it.
+fn plays(loop_count: image::metadata::LoopCount) -> LoopCount {
+    match loop_count {
+        image::metadata::LoopCount::Infinite => LoopCount::Infinite,
+        image::metadata::LoopCount::Finite(plays) => {
+            LoopCount::Finite(u16::try_from(plays.get()).unwrap_or(u16::MAX))
+        }
+    }
+}
+
+/// Encodes an animation into a writer.
+pub fn encode_animation(
+    animation: &Animation,
+    options: &AnimationEncodeOptions,
+    writer: &mut impl Write,
+) -> Result<(), ImageSlapperError> {
+    let format = match options.format {
+        AnimationFormat::Gif { .. } => ImageFormat::Gif,
+        AnimationFormat::Apng => ImageFormat::Png,
+        AnimationFormat::WebP(_) => ImageFormat::WebP,
+    };
+    let failed = |error: Box<dyn std::error::Error + Send + Sync>| ImageSlapperError::Encode {
+        path: Path::new("<writer>").to_path_buf(),
+        source: ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), error)),
+    };
+    let (width, height) = (animation.width(), animation.height());
+    if animation.frames.is_empty() {
+        return Err(failed("an animation needs at least one frame".into()));
+    }
+    let frames: Vec<RgbaImage> = animation
+        .frames
+        .par_iter()
+        .map(|frame| frame.image.to_rgba8())
+        .collect();
+    if frames
+        .iter()
+        .any(|frame| frame.dimensions() != (width, height))
+    {
+        return Err(failed("every frame must be the size of the first".into()));
+    }
+    let areas = changed_areas(&frames, options.disposal);
+
+    match options.format {
+        AnimationFormat::Gif { quantization_speed } => {
+            let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height))
+            else {
+                return Err(failed("GIF is limited to 65535 pixels a side".into()));
+            };
+            let dispose = match options.disposal {
+                Disposal::Background => gif::DisposalMethod::Background,
+                Disposal::Keep => gif::DisposalMethod::Keep,
+                Disposal::Previous => gif::DisposalMethod::Previous,
+            };
+            let speed = i32::from(quantization_speed.clamp(1, 30));
+            // Quantizing is the slow part, so every frame gets its palette in parallel
+            let encoded: Vec<gif::Frame<'static>> = frames
+                .par_iter()
+                .zip(&animation.frames)
+                .zip(&areas)
+                .map(|((image, frame), &(x, y, w, h))| {
+                    let mut pixels = image::imageops::crop_imm(image, x, y, w, h)
+                        .to_image()
+                        .into_raw();
+                    let mut encoded =
+                        gif::Frame::from_rgba_speed(w as u16, h as u16, &mut pixels, speed);
+                    encoded.left = x as u16;
+                    encoded.top = y as u16;
+                    // GIF delays are in hundredths of a second
+                    encoded.delay =
+                        (frame.delay.as_millis().div_ceil(10)).min(u128::from(u16::MAX)) as u16;
+                    encoded.dispose = dispose;
+                    encoded
+                })
+                .collect();
+
+            let mut encoder = gif::Encoder::new(&mut *writer, gif_width, gif_height, &[])
+                .map_err(|error| failed(error.into()))?;
+            let repeat = match animation.loop_count {
+                LoopCount::Infinite => Some(gif::Repeat::Infinite),
+                // One play is a GIF without the looping extension
+                LoopCount::Finite(0 | 1) => None,
+                LoopCount::Finite(plays) => Some(gif::Repeat::Finite(plays - 1)),
+            };
+            if let Some(repeat) = repeat {
+                encoder
+                    .set_repeat(repeat)
+                    .map_err(|error| failed(error.into()))?;
+            }
+            for frame in &encoded {
+                encoder
+                    .write_frame(frame)
+                    .map_err(|error| failed(error.into()))?;
+            }
+            Ok(())
+        }
+        AnimationFormat::Apng => {
+            let mut encoder = png::Encoder::new(&mut *writer, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let plays = match animation.loop_count {
+                LoopCount::Infinite => 0,
+                LoopCount::Finite(plays) => u32::from(plays.max(1)),
+            };
+            encoder
+                .set_animated(frames.len() as u32, plays)
+                .map_err(|error| failed(error.into()))?;
+            let mut png = encoder
+                .write_header()
+                .map_err(|error| failed(error.into()))?;
+            let dispose = match options.disposal {
+                Disposal::Background => png::DisposeOp::Background,
+                Disposal::Keep => png::DisposeOp::None,
+                Disposal::Previous => png::DisposeOp::Previous,
+            };
+            for ((image, frame), &(x, y, w, h)) in frames.iter().zip(&animation.frames).zip(&areas)
+            {
+                let delay = frame.delay.as_millis().min(u128::from(u16::MAX)) as u16;
+                let pixels = image::imageops::crop_imm(image, x, y, w, h).to_image();
+                // Replacing rather than blending lets pixels turn transparent
+                png.set_blend_op(png::BlendOp::Source)
+                    .and_then(|()| png.set_dispose_op(dispose))
+                    .and_then(|()| png.set_frame_delay(delay, 1000))
+                    .and_then(|()| png.set_frame_position(0, 0))
+                    .and_then(|()| png.set_frame_dimension(w, h))
+                    .and_then(|()| png.set_frame_position(x, y))
+                    .and_then(|()| png.write_image_data(pixels.as_raw()))
+                    .map_err(|error| failed(error.into()))?;
+            }
+            png.finish().map_err(|error| failed(error.into()))
+        }
+        AnimationFormat::WebP(mode) => {
+            let mut config =
+                webp::WebPConfig::new().map_err(|()| failed("invalid WebP config".into()))?;
+            match mode {
+                WebPMode::Lossless => config.lossless = 1,
+                WebPMode::Lossy { quality } => config.quality = quality.clamp(0.0, 100.0),
+            }
+            let mut encoder = webp::AnimEncoder::new(width, height, &config);
+            encoder.set_loop_count(match animation.loop_count {
+                LoopCount::Infinite => 0,
+                LoopCount::Finite(plays) => i32::from(plays.max(1)),
+            });
+            let mut timestamp = 0;
+            for (image, frame) in frames.iter().zip(&animation.frames) {
+                encoder.add_frame(webp::AnimFrame::from_rgba(
+                    image.as_raw(),
+                    width,
+                    height,
+                    timestamp,
+                ));
+                timestamp += i32::try_from(frame.delay.as_millis()).unwrap_or(i32::MAX);
+            }
+            let memory = encoder
+                .try_encode()
+                .map_err(|error| failed(format!("{error:?}").into()))?;
+            let last = animation
+                .frames
+                .last()
+                .map_or(Duration::ZERO, |frame| frame.delay);
+            let bytes = with_last_duration(&memory, last);
+            writer
+                .write_all(&bytes)
+                .map_err(|error| failed(error.into()))
+        }
+    }
+}
+
+/// Encodes an animation into a new buffer.
+pub fn encode_animation_to_vec(
+    animation: &Animation,
+    options: &AnimationEncodeOptions,
+) -> Result<Vec<u8>, ImageSlapperError> {
+    let mut bytes = Vec::new();
+    encode_animation(animation, options, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// The area of each frame to write as (x, y, width, height). Frames kept for the next one to
+/// draw over only need the area that differs from the frame before.
+fn changed_areas(frames: &[RgbaImage], disposal: Disposal) -> Vec<(u32, u32, u32, u32)> {
+    let whole = |frame: &RgbaImage| (0, 0, frame.width(), frame.height());
+    if disposal != Disposal::Keep {
+        return frames.iter().map(whole).collect();
+    }
+    let mut areas = Vec::with_capacity(frames.len());
+    areas.extend(frames.first().map(whole));
+    areas.par_extend(frames.par_windows(2).map(|pair| {
+        let (previous, current) = (&pair[0], &pair[1]);
+        let (mut x1, mut y1, mut x2, mut y2) = (u32::MAX, u32::MAX, 0, 0);
+        for (x, y, pixel) in current.enumerate_pixels() {
+            if previous.get_pixel(x, y) != pixel {
+                x1 = x1.min(x);
+                y1 = y1.min(y);
+                x2 = x2.max(x + 1);
+                y2 = y2.max(y + 1);
+            }
+        }
+        // An unchanged frame still needs a pixel to carry its delay
+        if x1 > x2 {
+            (0, 0, 1, 1)
+        } else {
+            (x1, y1, x2 - x1, y2 - y1)
+        }
+    }));
+    areas
+}
+
+/// Sets the duration of the last frame of an animated WebP, which libwebp leaves unset.
+fn with_last_duration(webp: &[u8], delay: Duration) -> Vec<u8> {
+    let mut bytes = webp.to_vec();
+    let mut position = 12;
+    let mut last = None;
+    while position + 8 <= bytes.len() {
+        let size = u32::from_le_bytes([
+            bytes[position + 4],
+            bytes[position + 5],
+            bytes[position + 6],
+            bytes[position + 7],
+        ]) as usize;
+        if &bytes[position..position + 4] == b"ANMF" {
+            last = Some(position + 8);
+        }
+        position += 8 + size + size % 2;
+    }
+    // The duration is 24 bits after the frame's offset and size
+    if let Some(data) = last
+        && data + 15 <= bytes.len()
+    {
+        let milliseconds = delay.as_millis().min(0xFF_FFFF) as u32;
+        bytes[data + 12..data + 15].copy_from_slice(&milliseconds.to_le_bytes()[..3]);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(loop_count: LoopCount) -> Animation {
+        let frames = [[255, 0, 0, 255], [0, 0, 255, 255]]
+            .into_iter()
+            .map(|color| AnimationFrame {
+                image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, image::Rgba(color))),
+                delay: Duration::from_millis(100),
+            })
+            .collect();
+        Animation::new(frames, loop_count)
+    }
+
+    fn round_trip(loop_count: LoopCount, format: ImageFormat) -> Animation {
+        let options = AnimationEncodeOptions::for_format(format).unwrap();
+        let bytes = encode_animation_to_vec(&animation(loop_count), &options).unwrap();
+        load_animation_from_memory(&bytes, &DecodeLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn gif_loop_count_round_trips() {
+        for loop_count in [
+            LoopCount::Infinite,
+            LoopCount::Finite(1),
+            LoopCount::Finite(3),
+            LoopCount::Finite(u16::MAX),
+        ] {
+            let decoded = round_trip(loop_count, ImageFormat::Gif);
+            assert_eq!(decoded.loop_count, loop_count);
+            assert_eq!(decoded.frames.len(), 2);
+            assert_eq!(decoded.frames[1].delay, Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn apng_loop_count_round_trips() {
+        for loop_count in [
+            LoopCount::Infinite,
+            LoopCount::Finite(1),
+            LoopCount::Finite(3),
+            LoopCount::Finite(u16::MAX),
+        ] {
+            let decoded = round_trip(loop_count, ImageFormat::Png);
+            assert_eq!(decoded.loop_count, loop_count);
+            assert_eq!(decoded.frames.len(), 2);
+            assert_eq!(
+                decoded.frames[1].image,
+                animation(loop_count).frames[1].image
+            );
+        }
+    }
+
+    #[test]
+    fn gif_repeating_65535_times_saturates() {
+        let mut bytes = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut bytes, 1, 1, &[0, 0, 0]).unwrap();
+        encoder.set_repeat(gif::Repeat::Finite(u16::MAX)).unwrap();
+        encoder
+            .write_frame(&gif::Frame {
+                width: 1,
+                height: 1,
+                buffer: std::borrow::Cow::Borrowed(&[0]),
+                ..gif::Frame::default()
+            })
+            .unwrap();
+        drop(encoder);
+        let decoded = load_animation_from_memory(&bytes, &DecodeLimits::default()).unwrap();
+        assert_eq!(decoded.loop_count, LoopCount::Finite(u16::MAX));
+    }
+
+    #[test]
+    fn oversized_gif_screen_is_rejected_before_decoding() {
+        let mut bytes = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut bytes, 12000, 12000, &[0, 0, 0]).unwrap();
+        for _ in 0..2 {
+            encoder
+                .write_frame(&gif::Frame {
+                    width: 1,
+                    height: 1,
+                    buffer: std::borrow::Cow::Borrowed(&[0]),
+                    ..gif::Frame::default()
+                })
+                .unwrap();
+        }
+        drop(encoder);
+        let limits = DecodeLimits {
+            max_width: Some(1000),
+            max_height: Some(1000),
+            max_alloc: Some(10 << 20),
+        };
+        assert!(matches!(
+            load_animation_from_memory(&bytes, &limits),
+            Err(ImageSlapperError::Decode {
+                source: ImageError::Limits(_),
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,586 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use image::DynamicImage;
+use tracing::{debug, info, warn};
+
+use super::encode::{EncodeOptions, encode_to_vec};
+use super::images::{DecodeOptions, decode};
+use crate::error::ImageSlapperError;
+use crate::rendering::color::{self, ColorSpace, WorkingSpace};
+
+/// Counter that keeps temporary file names unique within the process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Hashes bytes into a hex key for content-addressed caching.
+pub fn content_hash(bytes: &[u8]) -> String {
+    blake3::hash(bytes).to_hex().to_string()
+}
+
+/// Which entry goes first when the cache is over its limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// The least recently used entry.
+    #[default]
+    Lru,
+    /// The least frequently used entry, the least recently used among equals.
+    Lfu,
+}
+
+/// Bounds on the files kept in the cache directory, `None` for unbounded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheLimits {
+    pub max_bytes: Option<u64>,
+    pub max_entries: Option<usize>,
+}
+
+/// Lookup counts since the cache was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub memory_hits: u64,
+    pub disk_hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Share of lookups served from either tier, 0 before the first lookup.
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.memory_hits + self.disk_hits;
+        let lookups = hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            hits as f64 / lookups as f64
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Tracked {
+    size: u64,
+    uses: u64,
+    last_use: u64,
+}
+
+/// Sizes and usage of cached entries, ordered for eviction.
+#[derive(Debug, Default)]
+struct Tracker {
+    policy: EvictionPolicy,
+    clock: u64,
+    total: u64,
+    entries: HashMap<String, Tracked>,
+    order: BTreeSet<(u64, u64, String)>,
+}
+
+impl Tracker {
+    fn new(policy: EvictionPolicy) -> Self {
+        Tracker {
+            policy,
+            ..Tracker::default()
+        }
+    }
+
+    fn rank(&self, key: &str, entry: &Tracked) -> (u64, u64, String) {
+        match self.policy {
+            EvictionPolicy::Lru => (entry.last_use, 0, key.to_string()),
+            EvictionPolicy::Lfu => (entry.uses, entry.last_use, key.to_string()),
+        }
+    }
+
+    /// Records a use of an entry, returning false when it is not tracked.
+    fn touch(&mut self, key: &str) -> bool {
+        let Some(&entry) = self.entries.get(key) else {
+            return false;
+        };
+        self.order.remove(&self.rank(key, &entry));
+        self.clock += 1;
+        let entry = Tracked {
+            uses: entry.uses + 1,
+            last_use: self.clock,
+            ..entry
+        };
+        self.order.insert(self.rank(key, &entry));
+        self.entries.insert(key.to_string(), entry);
+        true
+    }
+
+    /// Tracks an entry as just used, replacing any entry with the same key.
+    fn insert(&mut self, key: &str, size: u64) {
+        self.remove(key);
+        self.clock += 1;
+        let entry = Tracked {
+            size,
+            uses: 1,
+            last_use: self.clock,
+        };
+        self.order.insert(self.rank(key, &entry));
+        self.entries.insert(key.to_string(), entry);
+        self.total += size;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&self.rank(key, &entry));
+            self.total -= entry.size;
+        }
+    }
+
+    /// Removes and returns the next entry to evict while over the limits.
+    fn evict(&mut self, max_bytes: Option<u64>, max_entries: Option<usize>) -> Option<String> {
+        let over = max_bytes.is_some_and(|max| self.total > max)
+            || max_entries.is_some_and(|max| self.entries.len() > max);
+        if !over {
+            return None;
+        }
+        let (_, _, key) = self.order.pop_first()?;
+        if let Some(entry) = self.entries.remove(&key) {
+            self.total -= entry.size;
+        }
+        Some(key)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    /// Whether entries written before this cache was created have been indexed.
+    scanned: bool,
+    disk: Tracker,
+    memory: Tracker,
+    images: HashMap<String, Arc<DynamicImage>>,
+    stats: CacheStats,
+}
+
+/// A directory of decoded images stored as QOI, keyed by the hash of their source file so a
+/// changed source is never served stale. Clones share the memory tier and statistics.
+#[derive(Clone, Debug)]
+pub struct QoiCache {
+    pub dir: PathBuf,
+    pub limits: CacheLimits,
+    /// Largest total size of decoded images kept in memory, `None` for no memory tier.
+    pub memory_bytes: Option<u64>,
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl QoiCache {
+    /// Creates an unbounded cache without a memory tier.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        QoiCache {
+            dir: dir.into(),
+            limits: CacheLimits::default(),
+            memory_bytes: None,
+            state: Arc::new(Mutex::new(CacheState::default())),
+        }
+    }
+
+    /// Bounds the cache directory, evicting entries by the policy once over the limits. Entries
+    /// already on disk count towards the limits.
+    pub fn with_limits(mut self, limits: CacheLimits, policy: EvictionPolicy) -> Self {
+        self.limits = limits;
+        self.lock().disk = Tracker::new(policy);
+        self
+    }
+
+    /// Keeps up to `max_bytes` of decoded images in memory, evicting the least recently used.
+    pub fn with_memory(mut self, max_bytes: u64) -> Self {
+        self.memory_bytes = Some(max_bytes);
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        // Every update leaves the state consistent, so a panicked holder did no harm
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Path of the entry for a key, sharded by the key's first two characters to keep
+    /// directories small.
+    pub fn path(&self, key: &str) -> PathBuf {
+        let shard = key.get(..2).unwrap_or(key);
+        self.dir.join(shard).join(format!("{key}.qoi"))
+    }
+
+    /// Returns the lookup counts so far.
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    /// Logs the lookup counts and what each tier holds. Disk entries are only tracked with limits.
+    pub fn log_stats(&self) {
+        let state = self.lock();
+        let stats = state.stats;
+        info!(
+            "Image cache: {} memory hits, {} disk hits, {} misses ({:.1}% hit rate), {} evictions, \
+             {} entries of {} bytes on disk, {} images of {} bytes in memory",
+            stats.memory_hits,
+            stats.disk_hits,
+            stats.misses,
+            stats.hit_rate() * 100.0,
+            stats.evictions,
+            state.disk.entries.len(),
+            state.disk.total,
+            state.images.len(),
+            state.memory.total
+        );
+    }
+
+    /// Reads an entry, treating a missing or corrupt file as a miss. Corrupt files are removed.
+    pub fn get(&self, key: &str) -> Option<Arc<DynamicImage>> {
+        if let Some(image) = self.memory_get(key) {
+            self.lock().stats.memory_hits += 1;
+            debug!("Memory cache hit: {}", key);
+            return Some(image);
+        }
+
+        let path = self.path(key);
+        let image = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| match decode_qoi(&bytes) {
+                Ok(image) => Some((Arc::new(image), bytes.len() as u64)),
+                Err(error) => {
+                    warn!(
+                        "Discarding corrupt cache entry {}: {}",
+                        path.display(),
+                        error
+                    );
+                    let _ = std::fs::remove_file(&path);
+                    None
+                }
+            });
+
+        let mut state = self.lock();
+        let Some((image, size)) = image else {
+            state.disk.remove(key);
+            state.stats.misses += 1;
+            debug!("Cache miss: {}", key);
+            return None;
+        };
+        if self.track_disk(&mut state) && !state.disk.touch(key) {
+            // Another worker wrote the entry
+            state.disk.insert(key, size);
+        }
+        state.stats.disk_hits += 1;
+        debug!("Disk cache hit: {}", key);
+        drop(state);
+        self.memory_put(key, &image);
+        Some(image)
+    }
+
+    /// Writes an entry through a temporary file renamed into place, so readers never see a
+    /// partly written file, then evicts entries over the limits.
+    ///
+    /// QOI only holds 8-bit RGB and RGBA, so other images are kept in the memory tier alone
+    /// rather than coming back from disk as a different type.
+    pub fn put(&self, key: &str, image: &Arc<DynamicImage>) -> Result<(), ImageSlapperError> {
+        if !matches!(
+            **image,
+            DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)
+        ) {
+            debug!(
+                "Not writing {:?} image to disk cache: {}",
+                image.color(),
+                key
+            );
+            self.memory_put(key, image);
+            return Ok(());
+        }
+        let path = self.path(key);
+        let dir = path.parent().unwrap_or(&self.dir);
+        std::fs::create_dir_all(dir)
+            .map_err(|e| ImageSlapperError::io(dir, "create cache directory", e))?;
+
+        let bytes = encode_to_vec(image, &EncodeOptions::Qoi).map_err(|error| {
+            ImageSlapperError::Cache {
+                path: path.clone(),
+                context: error.to_string(),
+            }
+        })?;
+        let size = bytes.len() as u64;
+        let temp = dir.join(format!(
+            "{key}.{}.{}.tmp",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temp, bytes)
+            .and_then(|()| std::fs::rename(&temp, &path))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&temp);
+                ImageSlapperError::io(&path, "write cache entry", e)
+            })?;
+
+        self.memory_put(key, image);
+        let mut state = self.lock();
+        if !self.track_disk(&mut state) {
+            return Ok(());
+        }
+        state.disk.insert(key, size);
+        while let Some(evicted) = state
+            .disk
+            .evict(self.limits.max_bytes, self.limits.max_entries)
+        {
+            let _ = std::fs::remove_file(self.path(&evicted));
+            state.stats.evictions += 1;
+            debug!("Evicted cache entry: {}", evicted);
+        }
+        Ok(())
+    }
+
+    /// Loads an image file, from the cache when its content was decoded before.
+    pub fn load(&self, image_path: &Path) -> Result<DynamicImage, ImageSlapperError> {
+        self.load_shared(image_path).map(Arc::unwrap_or_clone)
+    }
+
+    /// Loads an image file like `load`, sharing the decoded image with the memory tier.
+    pub fn load_shared(&self, image_path: &Path) -> Result<Arc<DynamicImage>, ImageSlapperError> {
+        let bytes = std::fs::read(image_path)
+            .map_err(|e| ImageSlapperError::io(image_path, "read image", e))?;
+        self.load_keyed(&bytes, image_path, &DecodeOptions::default())
+    }
+
+    /// Loads an image held in memory, such as an upload, from the cache when its content was
+    /// decoded before.
+    pub fn load_from_memory(
+        &self,
+        bytes: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<Arc<DynamicImage>, ImageSlapperError> {
+        self.load_keyed(bytes, Path::new("<memory>"), options)
+    }
+
+    /// Loads an image from a reader, which is read to the end to hash its content.
+    pub fn load_from_reader(
+        &self,
+        mut reader: impl Read,
+        options: &DecodeOptions,
+    ) -> Result<Arc<DynamicImage>, ImageSlapperError> {
+        let source = Path::new("<reader>");
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|e| ImageSlapperError::io(source, "read image", e))?;
+        self.load_keyed(&bytes, source, options)
+    }
+
+    /// Loads an image from a shared buffer, such as an HTTP request body.
+    pub fn load_from_bytes(
+        &self,
+        bytes: &bytes::Bytes,
+        options: &DecodeOptions,
+    ) -> Result<Arc<DynamicImage>, ImageSlapperError> {
+        self.load_keyed(bytes, Path::new("<memory>"), options)
+    }
+
+    /// Loads encoded image data through the cache, keyed by its content hash.
+    fn load_keyed(
+        &self,
+        bytes: &[u8],
+        source: &Path,
+        options: &DecodeOptions,
+    ) -> Result<Arc<DynamicImage>, ImageSlapperError> {
+        // QOI holds 8-bit sRGB, so linear images are cached in sRGB and converted on the way out
+        let linear = options.working_space == Some(WorkingSpace::LinearSrgb);
+        let cached = DecodeOptions {
+            working_space: options.working_space.map(|_| WorkingSpace::Srgb),
+            ..*options
+        };
+        let image = self.load_cached(bytes, source, &cached)?;
+        if linear {
+            let image = color::convert(&image, ColorSpace::Srgb, ColorSpace::LinearSrgb)
+                .map_err(|error| error.with_path(source))?;
+            return Ok(Arc::new(image));
+        }
+        Ok(image)
+    }
+
+    fn load_cached(
+        &self,
+        bytes: &[u8],
+        source: &Path,
+        options: &DecodeOptions,
+    ) -> Result<Arc<DynamicImage>, ImageSlapperError> {
+        let mut key = content_hash(bytes);
+        // Entries written before orientation and color management were applied hold the
+        // pixels as decoded, so the other variants get their own keys
+        if options.apply_orientation {
+            key.push_str("-upright");
+        }
+        if options.working_space.is_some() {
+            key.push_str("-srgb");
+        }
+        if let Some(image) = self.get(&key) {
+            info!("Loading image from cache: {}", self.path(&key).display());
+            options
+                .limits
+                .check(&image)
+                .map_err(|error| ImageSlapperError::Decode {
+                    path: source.to_path_buf(),
+                    source: error,
+                })?;
+            return Ok(image);
+        }
+
+        info!("Loading image directly: {}", source.display());
+        let image = Arc::new(decode(Cursor::new(bytes), source, options)?);
+        match self.put(&key, &image) {
+            Ok(()) => debug!("Image saved to cache: {}", self.path(&key).display()),
+            Err(error) => warn!("{}", error),
+        }
+        Ok(image)
+    }
+
+    /// Whether the directory has limits to track entries for, indexing the entries written
+    /// before this cache was created on first use.
+    fn track_disk(&self, state: &mut CacheState) -> bool {
+        if self.limits == CacheLimits::default() {
+            return false;
+        }
+        if !state.scanned {
+            state.scanned = true;
+            self.scan(&mut state.disk);
+        }
+        true
+    }
+
+    fn memory_get(&self, key: &str) -> Option<Arc<DynamicImage>> {
+        let mut state = self.lock();
+        let image = state.images.get(key).cloned()?;
+        state.memory.touch(key);
+        Some(image)
+    }
+
+    fn memory_put(&self, key: &str, image: &Arc<DynamicImage>) {
+        let Some(max_bytes) = self.memory_bytes else {
+            return;
+        };
+        let size = image.as_bytes().len() as u64;
+        if size > max_bytes {
+            return;
+        }
+        let mut state = self.lock();
+        state.memory.insert(key, size);
+        state.images.insert(key.to_string(), Arc::clone(image));
+        while let Some(evicted) = state.memory.evict(Some(max_bytes), None) {
+            state.images.remove(&evicted);
+        }
+    }
+
+    /// Tracks the entries already in the directory, oldest modified first.
+    fn scan(&self, tracker: &mut Tracker) {
+        let Ok(shards) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut found = Vec::new();
+        for file in shards
+            .flatten()
+            .filter_map(|shard| std::fs::read_dir(shard.path()).ok())
+            .flatten()
+            .flatten()
+        {
+            let path = file.path();
+            let (Some(key), Ok(metadata)) = (
+                path.file_stem().and_then(std::ffi::OsStr::to_str),
+                file.metadata(),
+            ) else {
+                continue;
+            };
+            if path.extension().is_some_and(|extension| extension == "qoi") {
+                found.push((metadata.modified().ok(), key.to_string(), metadata.len()));
+            }
+        }
+        found.sort();
+        debug!(
+            "Found {} cache entries in {}",
+            found.len(),
+            self.dir.display()
+        );
+        for (_, key, size) in found {
+            tracker.insert(&key, size);
+        }
+    }
+}
+
+/// Decodes a QOI file, failing on a bad header, truncated data or a missing end marker.
+fn decode_qoi(bytes: &[u8]) -> Result<DynamicImage, String> {
+    let (header, pixels) = qoi::decode_to_vec(bytes).map_err(|e| e.to_string())?;
+    let image = match header.channels {
+        qoi::Channels::Rgb => image::RgbImage::from_raw(header.width, header.height, pixels)
+            .map(DynamicImage::ImageRgb8),
+        qoi::Channels::Rgba => image::RgbaImage::from_raw(header.width, header.height, pixels)
+            .map(DynamicImage::ImageRgba8),
+    };
+    image.ok_or_else(|| "pixel data does not match the header".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evicted(
+        tracker: &mut Tracker,
+        max_bytes: Option<u64>,
+        max_entries: Option<usize>,
+    ) -> Vec<String> {
+        std::iter::from_fn(|| tracker.evict(max_bytes, max_entries)).collect()
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used() {
+        let mut tracker = Tracker::new(EvictionPolicy::Lru);
+        for key in ["a", "b", "c"] {
+            tracker.insert(key, 1);
+        }
+        assert!(tracker.touch("a"));
+        assert_eq!(evicted(&mut tracker, None, Some(2)), ["b"]);
+        tracker.insert("d", 1);
+        assert_eq!(evicted(&mut tracker, None, Some(2)), ["c"]);
+    }
+
+    #[test]
+    fn lfu_evicts_the_least_frequently_used() {
+        let mut tracker = Tracker::new(EvictionPolicy::Lfu);
+        tracker.insert("a", 1);
+        tracker.insert("b", 1);
+        tracker.touch("a");
+        tracker.touch("a");
+        tracker.touch("b");
+        tracker.insert("c", 1);
+        assert_eq!(evicted(&mut tracker, None, Some(2)), ["c"]);
+        assert_eq!(evicted(&mut tracker, None, Some(1)), ["b"]);
+    }
+
+    #[test]
+    fn lfu_breaks_ties_by_recency() {
+        let mut tracker = Tracker::new(EvictionPolicy::Lfu);
+        for key in ["a", "b", "c"] {
+            tracker.insert(key, 1);
+        }
+        assert_eq!(evicted(&mut tracker, None, Some(1)), ["a", "b"]);
+    }
+
+    #[test]
+    fn evicts_until_under_the_byte_limit() {
+        let mut tracker = Tracker::new(EvictionPolicy::Lru);
+        for key in ["a", "b", "c"] {
+            tracker.insert(key, 5);
+        }
+        assert_eq!(evicted(&mut tracker, Some(6), None), ["a", "b"]);
+        assert_eq!(tracker.total, 5);
+        assert!(evicted(&mut tracker, Some(6), None).is_empty());
+    }
+
+    #[test]
+    fn reinserting_replaces_the_entry() {
+        let mut tracker = Tracker::new(EvictionPolicy::Lru);
+        tracker.insert("a", 5);
+        tracker.insert("a", 7);
+        assert_eq!(tracker.total, 7);
+        assert_eq!(tracker.entries.len(), 1);
+        tracker.remove("a");
+        assert_eq!(tracker.total, 0);
+        assert!(!tracker.touch("a"));
+    }
+}
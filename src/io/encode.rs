@@ -0,0 +1,274 @@
+use std::io::Write;
+use std::path::Path;
+
+use image::ImageEncoder;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::png::PngEncoder;
+pub use image::codecs::png::{CompressionType, FilterType};
+use image::error::{EncodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageFormat};
+use tracing::warn;
+
+use super::metadata::{self, ImageMetadata};
+use crate::error::ImageSlapperError;
+
+/// Signature starting a JPEG APP1 segment with EXIF.
+const JPEG_EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+/// Signature starting a JPEG APP1 segment with XMP.
+const JPEG_XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// How JPEG color channels are sampled relative to brightness.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// Full color resolution, for text and sharp edges.
+    Yuv444,
+    /// Half horizontal color resolution.
+    Yuv422,
+    /// Half color resolution both ways, the smallest files for photos.
+    #[default]
+    Yuv420,
+}
+
+/// WebP compression, lossless or lossy at a quality from 0 to 100.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WebPMode {
+    Lossless,
+    Lossy { quality: f32 },
+}
+
+/// An output format with its encoder settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncodeOptions {
+    Png {
+        compression: CompressionType,
+        filter: FilterType,
+    },
+    /// Quality from 1 to 100. JPEG has no alpha channel, so alpha is dropped.
+    Jpeg {
+        quality: u8,
+        subsampling: ChromaSubsampling,
+    },
+    WebP(WebPMode),
+    /// Speed from 1 (slowest, smallest) to 10 and quality from 1 to 100.
+    Avif {
+        speed: u8,
+        quality: u8,
+    },
+    Qoi,
+}
+
+impl EncodeOptions {
+    /// Default settings for a format, the ones `save_image` writes with, or `None` when it
+    /// cannot be encoded.
+    pub fn for_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(EncodeOptions::Png {
+                compression: CompressionType::default(),
+                filter: FilterType::default(),
+            }),
+            ImageFormat::Jpeg => Some(EncodeOptions::Jpeg {
+                quality: 75,
+                subsampling: ChromaSubsampling::default(),
+            }),
+            ImageFormat::WebP => Some(EncodeOptions::WebP(WebPMode::Lossless)),
+            ImageFormat::Avif => Some(EncodeOptions::Avif {
+                speed: 4,
+                quality: 80,
+            }),
+            ImageFormat::Qoi => Some(EncodeOptions::Qoi),
+            _ => None,
+        }
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        match self {
+            EncodeOptions::Png { .. } => ImageFormat::Png,
+            EncodeOptions::Jpeg { .. } => ImageFormat::Jpeg,
+            EncodeOptions::WebP(_) => ImageFormat::WebP,
+            EncodeOptions::Avif { .. } => ImageFormat::Avif,
+            EncodeOptions::Qoi => ImageFormat::Qoi,
+        }
+    }
+}
+
+/// Encodes an image into a writer, such as a file or an HTTP response body.
+pub fn encode(
+    image: &DynamicImage,
+    options: &EncodeOptions,
+    writer: &mut impl Write,
+) -> Result<(), ImageSlapperError> {
+    encode_with_metadata(image, options, &ImageMetadata::default(), writer)
+}
+
+/// Encodes an image like `encode`, embedding the given metadata. Blocks a format cannot hold,
+/// such as anything in QOI, are skipped with a warning.
+pub fn encode_with_metadata(
+    image: &DynamicImage,
+    options: &EncodeOptions,
+    metadata: &ImageMetadata,
+    writer: &mut impl Write,
+) -> Result<(), ImageSlapperError> {
+    let format = options.format();
+    let encoded = |result: image::ImageResult<()>| {
+        result.map_err(|source| ImageSlapperError::Encode {
+            path: Path::new("<writer>").to_path_buf(),
+            source,
+        })
+    };
+    let failed = |error: String| {
+        encoded(Err(ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(format),
+            error,
+        ))))
+    };
+    let alpha = image.color().has_alpha();
+
+    match *options {
+        EncodeOptions::Png {
+            compression,
+            filter,
+        } => {
+            // XMP goes in a text chunk the encoder cannot write, so it is added afterwards
+            let mut png = Vec::new();
+            let mut encoder = PngEncoder::new_with_quality(&mut png, compression, filter);
+            if let Some(icc_profile) = &metadata.icc_profile {
+                encoded(
+                    encoder
+                        .set_icc_profile(icc_profile.clone())
+                        .map_err(ImageError::Unsupported),
+                )?;
+            }
+            if let Some(exif) = &metadata.exif {
+                encoded(
+                    encoder
+                        .set_exif_metadata(exif.clone())
+                        .map_err(ImageError::Unsupported),
+                )?;
+            }
+            match image {
+                // PNG has no floating point samples
+                DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => encoded(
+                    DynamicImage::ImageRgba16(image.to_rgba16()).write_with_encoder(encoder),
+                ),
+                _ => encoded(image.write_with_encoder(encoder)),
+            }?;
+            if let Some(xmp) = &metadata.xmp {
+                png = metadata::png_with_xmp(&png, xmp);
+            }
+            encoded(writer.write_all(&png).map_err(ImageError::IoError))
+        }
+        EncodeOptions::Jpeg {
+            quality,
+            subsampling,
+        } => {
+            let (Ok(width), Ok(height)) =
+                (u16::try_from(image.width()), u16::try_from(image.height()))
+            else {
+                return failed("JPEG is limited to 65535 pixels a side".to_string());
+            };
+            let mut encoder = jpeg_encoder::Encoder::new(writer, quality.clamp(1, 100));
+            encoder.set_sampling_factor(match subsampling {
+                ChromaSubsampling::Yuv444 => jpeg_encoder::SamplingFactor::R_4_4_4,
+                ChromaSubsampling::Yuv422 => jpeg_encoder::SamplingFactor::R_4_2_2,
+                ChromaSubsampling::Yuv420 => jpeg_encoder::SamplingFactor::R_4_2_0,
+            });
+            if let Some(icc_profile) = &metadata.icc_profile
+                && let Err(error) = encoder.add_icc_profile(icc_profile)
+            {
+                return failed(error.to_string());
+            }
+            for (signature, data) in [
+                (JPEG_EXIF_SIGNATURE, &metadata.exif),
+                (JPEG_XMP_SIGNATURE, &metadata.xmp),
+            ] {
+                let Some(data) = data else { continue };
+                if let Err(error) = encoder.add_app_segment(1, [signature, data].concat()) {
+                    return failed(error.to_string());
+                }
+            }
+            let result = if image.color().has_color() {
+                let rgb = image.to_rgb8();
+                encoder.encode(rgb.as_raw(), width, height, jpeg_encoder::ColorType::Rgb)
+            } else {
+                let luma = image.to_luma8();
+                encoder.encode(luma.as_raw(), width, height, jpeg_encoder::ColorType::Luma)
+            };
+            result.or_else(|error| failed(error.to_string()))
+        }
+        EncodeOptions::WebP(mode) => {
+            let (width, height) = (image.width(), image.height());
+            let pixels = if alpha {
+                image.to_rgba8().into_raw()
+            } else {
+                image.to_rgb8().into_raw()
+            };
+            let encoder = if alpha {
+                webp::Encoder::from_rgba(&pixels, width, height)
+            } else {
+                webp::Encoder::from_rgb(&pixels, width, height)
+            };
+            let result = match mode {
+                WebPMode::Lossless => encoder.encode_simple(true, 100.0),
+                WebPMode::Lossy { quality } => {
+                    encoder.encode_simple(false, quality.clamp(0.0, 100.0))
+                }
+            };
+            match result {
+                Ok(memory) if metadata.is_empty() => {
+                    encoded(writer.write_all(&memory).map_err(ImageError::IoError))
+                }
+                Ok(memory) => {
+                    let webp = metadata::webp_with_metadata(&memory, metadata, (width, height));
+                    encoded(writer.write_all(&webp).map_err(ImageError::IoError))
+                }
+                Err(error) => failed(format!("{error:?}")),
+            }
+        }
+        EncodeOptions::Avif { speed, quality } => {
+            let mut encoder = AvifEncoder::new_with_speed_quality(
+                writer,
+                speed.clamp(1, 10),
+                quality.clamp(1, 100),
+            );
+            if metadata.icc_profile.is_some() || metadata.xmp.is_some() {
+                warn!("AVIF output only carries EXIF, skipping the ICC profile and XMP");
+            }
+            if let Some(exif) = &metadata.exif
+                && encoder.set_exif_metadata(exif.clone()).is_err()
+            {
+                warn!("AVIF encoder rejected the EXIF metadata");
+            }
+            if alpha {
+                encoded(DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder))
+            } else {
+                encoded(DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder))
+            }
+        }
+        EncodeOptions::Qoi => {
+            if !metadata.is_empty() {
+                warn!("QOI has no metadata, skipping EXIF, ICC profile and XMP");
+            }
+            let result = if alpha {
+                let rgba = image.to_rgba8();
+                qoi::encode_to_vec(rgba.as_raw(), rgba.width(), rgba.height())
+            } else {
+                let rgb = image.to_rgb8();
+                qoi::encode_to_vec(rgb.as_raw(), rgb.width(), rgb.height())
+            };
+            match result {
+                Ok(bytes) => encoded(writer.write_all(&bytes).map_err(ImageError::IoError)),
+                Err(error) => failed(error.to_string()),
+            }
+        }
+    }
+}
+
+/// Encodes an image into a new buffer.
+pub fn encode_to_vec(
+    image: &DynamicImage,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, ImageSlapperError> {
+    let mut bytes = Vec::new();
+    encode(image, options, &mut bytes)?;
+    Ok(bytes)
+}
@@ -1,16 +1,53 @@
-use tracing::info;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
 
-/// Loads an image from the specified path and returns it along with the elapsed time.
-pub fn load_image(image_path: &str) -> (image::DynamicImage, std::time::Duration) {
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use tracing::{info, warn};
+
+use super::cache::QoiCache;
+use super::metadata::ImageMetadata;
+use crate::error::ImageSlapperError;
+use crate::rendering::color::{self, ColorSpace, WorkingSpace};
+
+/// Loads an image from the specified path and returns it along with the elapsed time. The
+/// image is turned upright and converted to sRGB, see `load_image_with` to keep it as stored.
+pub fn load_image(
+    image_path: &str,
+) -> Result<(DynamicImage, std::time::Duration), ImageSlapperError> {
+    load_image_with(image_path, &DecodeOptions::default())
+}
+
+/// Loads an image from the specified path like `load_image`, decoded with the given options.
+pub fn load_image_with(
+    image_path: &str,
+    options: &DecodeOptions,
+) -> Result<(DynamicImage, std::time::Duration), ImageSlapperError> {
     let start = std::time::Instant::now();
-    let image = image::open(image_path).expect("Failed to load image");
-    (image, start.elapsed())
+    let image = open(Path::new(image_path), options)?;
+    Ok((image, start.elapsed()))
+}
+
+/// Loads an image along with its EXIF, ICC and XMP metadata, to write back when saving.
+pub fn load_image_with_metadata(
+    image_path: &str,
+    options: &DecodeOptions,
+) -> Result<(DynamicImage, ImageMetadata), ImageSlapperError> {
+    let path = Path::new(image_path);
+    let file = std::fs::File::open(path)
+        .map_err(|error| ImageSlapperError::io(path, "read image", error))?;
+    decode_with_metadata(BufReader::new(file), path, options)
 }
 
-/// Loads an image, using `qoi` if the extension is `.qoi`, or falls back to the default loader.
-pub fn load_image_with_qoi(image_path: &str) -> (image::DynamicImage, std::time::Duration) {
+/// Loads an image, using `qoi` if the extension is `.qoi`, or decoding it once and reading it
+/// back from the QOI cache afterwards.
+pub fn load_image_with_qoi(
+    image_path: &str,
+    cache: &QoiCache,
+) -> Result<(DynamicImage, std::time::Duration), ImageSlapperError> {
     let start = std::time::Instant::now();
-    let extension = std::path::Path::new(image_path)
+    let path = Path::new(image_path);
+    let extension = path
         .extension()
         .and_then(std::ffi::OsStr::to_str)
         .unwrap_or("");
@@ -20,26 +57,241 @@ pub fn load_image_with_qoi(image_path: &str) -> (image::DynamicImage, std::time:
 
     let image = if extension.eq_ignore_ascii_case("qoi") {
         info!("Using QOI loader for image: {}", image_path);
-        image::open(image_path).expect("Failed to load QOI image")
+        open(path, &DecodeOptions::default())?
     } else {
-        let cache_path = format!("./.cache/{image_path}.qoi");
-        if std::path::Path::new(&cache_path).exists() {
-            info!("Loading image from cache: {}", cache_path);
-            image::open(&cache_path).expect("Failed to load cached image")
-        } else {
-            info!("Loading image directly: {}", image_path);
-            let image = image::open(image_path).expect("Failed to load image");
-            let cache_dir = std::path::Path::new(&cache_path)
-                .parent()
-                .expect("Failed to get cache directory");
-            std::fs::create_dir_all(cache_dir).expect("Failed to create cache directory");
-            image
-                .save(&cache_path)
-                .expect("Failed to save image to cache");
-            info!("Image saved to cache: {}", cache_path);
-            image
+        cache.load(path)?
+    };
+
+    Ok((image, start.elapsed()))
+}
+
+/// Bounds on decoded images, guarding against decompression bombs in untrusted uploads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Largest allocation in bytes a decoder may make, `None` for unbounded.
+    pub max_alloc: Option<u64>,
+}
+
+impl Default for DecodeLimits {
+    /// No dimension limits and the `image` crate's 512 MiB allocation limit.
+    fn default() -> Self {
+        let limits = image::Limits::default();
+        DecodeLimits {
+            max_width: limits.max_image_width,
+            max_height: limits.max_image_height,
+            max_alloc: limits.max_alloc,
+        }
+    }
+}
+
+/// How images are decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub limits: DecodeLimits,
+    /// Rotates and flips the pixels as the EXIF orientation says, so phone photos come out
+    /// upright. The orientation tag is reset once applied.
+    pub apply_orientation: bool,
+    /// Converts the pixels from their embedded ICC profile into this space, treating untagged
+    /// images as sRGB. `None` keeps the decoded values and the profile as they are.
+    pub working_space: Option<WorkingSpace>,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            limits: DecodeLimits::default(),
+            apply_orientation: true,
+            working_space: Some(WorkingSpace::Srgb),
         }
+    }
+}
+
+impl From<DecodeLimits> for DecodeOptions {
+    fn from(limits: DecodeLimits) -> Self {
+        DecodeOptions {
+            limits,
+            ..DecodeOptions::default()
+        }
+    }
+}
+
+impl DecodeLimits {
+    pub(crate) fn to_image_limits(self) -> image::Limits {
+        let mut limits = image::Limits::no_limits();
+        limits.max_image_width = self.max_width;
+        limits.max_image_height = self.max_height;
+        limits.max_alloc = self.max_alloc;
+        limits
+    }
+
+    /// Checks an image decoded earlier, such as a cache entry, against the dimension limits.
+    pub fn check(&self, image: &DynamicImage) -> Result<(), image::ImageError> {
+        let too_wide = self.max_width.is_some_and(|max| image.width() > max);
+        let too_tall = self.max_height.is_some_and(|max| image.height() > max);
+        if too_wide || too_tall {
+            return Err(image::ImageError::Limits(
+                image::error::LimitError::from_kind(image::error::LimitErrorKind::DimensionError),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Decodes an image held in memory, detecting its format from its magic number.
+pub fn load_image_from_memory(
+    bytes: &[u8],
+    options: &DecodeOptions,
+) -> Result<DynamicImage, ImageSlapperError> {
+    decode(Cursor::new(bytes), Path::new("<memory>"), options)
+}
+
+/// Decodes an image held in memory along with its metadata.
+pub fn load_image_from_memory_with_metadata(
+    bytes: &[u8],
+    options: &DecodeOptions,
+) -> Result<(DynamicImage, ImageMetadata), ImageSlapperError> {
+    decode_with_metadata(Cursor::new(bytes), Path::new("<memory>"), options)
+}
+
+/// Decodes an image from a reader, detecting its format from its magic number.
+pub fn load_image_from_reader(
+    reader: impl Read + Seek,
+    options: &DecodeOptions,
+) -> Result<DynamicImage, ImageSlapperError> {
+    decode(BufReader::new(reader), Path::new("<reader>"), options)
+}
+
+/// Decodes an image from a shared buffer, such as an HTTP request body.
+pub fn load_image_from_bytes(
+    bytes: &bytes::Bytes,
+    options: &DecodeOptions,
+) -> Result<DynamicImage, ImageSlapperError> {
+    load_image_from_memory(bytes, options)
+}
+
+/// Decodes an image with its format detected from its magic number rather than an extension.
+/// `source` names where it came from in errors.
+pub fn decode(
+    reader: impl BufRead + Seek,
+    source: &Path,
+    options: &DecodeOptions,
+) -> Result<DynamicImage, ImageSlapperError> {
+    decode_with_metadata(reader, source, options).map(|(image, _)| image)
+}
+
+/// Decodes an image like `decode`, also returning the metadata found in its headers.
+pub fn decode_with_metadata(
+    mut reader: impl BufRead + Seek,
+    source: &Path,
+    options: &DecodeOptions,
+) -> Result<(DynamicImage, ImageMetadata), ImageSlapperError> {
+    let decoded = |error| match error {
+        image::ImageError::IoError(error) => ImageSlapperError::io(source, "read image", error),
+        error => ImageSlapperError::Decode {
+            path: source.to_path_buf(),
+            source: error,
+        },
     };
+    let read = |error| ImageSlapperError::io(source, "read image", error);
+    let start = reader.stream_position().map_err(read)?;
+    let mut image_reader = ImageReader::new(&mut reader)
+        .with_guessed_format()
+        .map_err(read)?;
+    let format = image_reader.format();
+    image_reader.limits(options.limits.to_image_limits());
+    let mut decoder = image_reader.into_decoder().map_err(decoded)?;
+    let mut metadata = ImageMetadata::read(&mut decoder).map_err(decoded)?;
+    let orientation = decoder.orientation().map_err(decoded)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(decoded)?;
+
+    // Untagged pixels are sRGB already
+    let untagged_srgb =
+        metadata.icc_profile.is_none() && options.working_space == Some(WorkingSpace::Srgb);
+    if let Some(working) = options.working_space
+        && !untagged_srgb
+    {
+        let converted = match &metadata.icc_profile {
+            // The decoder blends CMYK to RGB without the profile, so the inks are read again
+            Some(icc_profile)
+                if format == Some(ImageFormat::Jpeg) && color::is_cmyk_profile(icc_profile) =>
+            {
+                reader.seek(SeekFrom::Start(start)).map_err(read)?;
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes).map_err(read)?;
+                decode_cmyk_jpeg(&bytes, source, &options.limits).and_then(|cmyk| {
+                    color::cmyk_to_working_space(
+                        &cmyk,
+                        (image.width(), image.height()),
+                        icc_profile,
+                        working,
+                    )
+                })
+            }
+            Some(icc_profile) => color::to_working_space(&image, icc_profile, working),
+            None => color::convert(&image, ColorSpace::Srgb, working.color_space()),
+        };
+        match converted {
+            Ok(converted) => {
+                image = converted;
+                // sRGB is what untagged pixels are taken to be
+                metadata.icc_profile = match working {
+                    WorkingSpace::Srgb => None,
+                    WorkingSpace::LinearSrgb => Some(ColorSpace::LinearSrgb.icc_profile()?),
+                };
+            }
+            Err(error) => warn!(
+                "Keeping the colors of {} as decoded: {}",
+                source.display(),
+                error
+            ),
+        }
+    }
+
+    if options.apply_orientation && orientation != Orientation::NoTransforms {
+        image.apply_orientation(orientation);
+        // The pixels are upright now, so the tag must not rotate them again
+        if let Some(exif) = &mut metadata.exif {
+            let _ = Orientation::remove_from_exif_chunk(exif);
+        }
+    }
+    Ok((image, metadata))
+}
+
+/// Decodes a CMYK JPEG to its stored ink values rather than blended RGB, within `limits`.
+fn decode_cmyk_jpeg(
+    bytes: &[u8],
+    source: &Path,
+    limits: &DecodeLimits,
+) -> Result<Vec<u8>, ImageSlapperError> {
+    let limit = |max: Option<u32>| max.map_or(usize::MAX, |max| max as usize);
+    let options = zune_core::options::DecoderOptions::default()
+        .set_strict_mode(false)
+        .set_max_width(limit(limits.max_width))
+        .set_max_height(limit(limits.max_height))
+        .jpeg_set_out_colorspace(zune_core::colorspace::ColorSpace::CMYK);
+    let mut decoder = zune_jpeg::JpegDecoder::new_with_options(
+        zune_core::bytestream::ZCursor::new(bytes),
+        options,
+    );
+    let failed = |error| ImageSlapperError::color("embedded", format!("CMYK JPEG: {error:?}"));
+    decoder.decode_headers().map_err(failed)?;
+    let size = decoder.output_buffer_size().unwrap_or(usize::MAX) as u64;
+    if limits.max_alloc.is_some_and(|max| size > max) {
+        return Err(ImageSlapperError::Decode {
+            path: source.to_path_buf(),
+            source: image::ImageError::Limits(image::error::LimitError::from_kind(
+                image::error::LimitErrorKind::InsufficientMemory,
+            )),
+        });
+    }
+    decoder.decode().map_err(failed)
+}
 
-    (image, start.elapsed())
+/// Opens an image file, telling a missing or unreadable file apart from one that fails to decode.
+fn open(path: &Path, options: &DecodeOptions) -> Result<DynamicImage, ImageSlapperError> {
+    let file = std::fs::File::open(path)
+        .map_err(|error| ImageSlapperError::io(path, "read image", error))?;
+    decode(BufReader::new(file), path, options)
 }
@@ -0,0 +1,189 @@
+use image::{ImageDecoder, ImageResult};
+
+/// Keyword of the PNG text chunk holding XMP.
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Metadata blocks carried alongside the pixels of an image.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageMetadata {
+    /// A raw TIFF structure, starting with its `II` or `MM` byte order mark.
+    pub exif: Option<Vec<u8>>,
+    pub icc_profile: Option<Vec<u8>>,
+    /// An XMP packet in UTF-8.
+    pub xmp: Option<Vec<u8>>,
+}
+
+/// Which metadata to write when saving.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetadataOptions {
+    /// EXIF holds camera settings, but also GPS positions and serial numbers.
+    pub exif: bool,
+    /// The ICC profile tells viewers how to show the colors.
+    pub icc_profile: bool,
+    pub xmp: bool,
+}
+
+impl Default for MetadataOptions {
+    /// Keeps the ICC profile so colors survive, strips EXIF and XMP for privacy.
+    fn default() -> Self {
+        MetadataOptions {
+            exif: false,
+            icc_profile: true,
+            xmp: false,
+        }
+    }
+}
+
+impl MetadataOptions {
+    pub fn keep_all() -> Self {
+        MetadataOptions {
+            exif: true,
+            icc_profile: true,
+            xmp: true,
+        }
+    }
+
+    pub fn strip_all() -> Self {
+        MetadataOptions {
+            exif: false,
+            icc_profile: false,
+            xmp: false,
+        }
+    }
+}
+
+impl ImageMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.icc_profile.is_none() && self.xmp.is_none()
+    }
+
+    /// Returns the blocks the options keep.
+    pub fn filtered(&self, options: &MetadataOptions) -> Self {
+        ImageMetadata {
+            exif: self.exif.clone().filter(|_| options.exif),
+            icc_profile: self.icc_profile.clone().filter(|_| options.icc_profile),
+            xmp: self.xmp.clone().filter(|_| options.xmp),
+        }
+    }
+
+    /// Reads the metadata a decoder found in its headers.
+    pub(crate) fn read(decoder: &mut impl ImageDecoder) -> ImageResult<Self> {
+        Ok(ImageMetadata {
+            exif: decoder.exif_metadata()?,
+            icc_profile: decoder.icc_profile()?,
+            xmp: decoder.xmp_metadata()?,
+        })
+    }
+}
+
+/// Inserts an XMP text chunk before the image data of a PNG file, where readers look for it.
+pub(crate) fn png_with_xmp(png: &[u8], xmp: &[u8]) -> Vec<u8> {
+    // Skip the signature, then chunks of length, type, data and CRC up to the first IDAT
+    let mut end = 8;
+    while end + 8 <= png.len() && &png[end + 4..end + 8] != b"IDAT" {
+        let size = u32::from_be_bytes([png[end], png[end + 1], png[end + 2], png[end + 3]]);
+        end += 12 + size as usize;
+    }
+    let end = end.min(png.len());
+    let mut data = PNG_XMP_KEYWORD.to_vec();
+    // Null separator, no compression, then empty language and translated keyword
+    data.extend([0, 0, 0, 0, 0]);
+    data.extend(xmp);
+
+    let mut output = png[..end].to_vec();
+    output.extend((data.len() as u32).to_be_bytes());
+    let start = output.len();
+    output.extend(b"iTXt");
+    output.extend(&data);
+    let crc = crc32(&output[start..]);
+    output.extend(crc.to_be_bytes());
+    output.extend(&png[end..]);
+    output
+}
+
+/// Adds metadata chunks to a simple WebP file, converting it to the extended format.
+pub(crate) fn webp_with_metadata(
+    webp: &[u8],
+    metadata: &ImageMetadata,
+    (width, height): (u32, u32),
+) -> Vec<u8> {
+    // Split the file into its chunks after the RIFF header
+    let mut chunks = Vec::new();
+    let mut position = 12;
+    while position + 8 <= webp.len() {
+        let size = u32::from_le_bytes([
+            webp[position + 4],
+            webp[position + 5],
+            webp[position + 6],
+            webp[position + 7],
+        ]) as usize;
+        let end = (position + 8 + size).min(webp.len());
+        chunks.push((&webp[position..position + 4], &webp[position + 8..end]));
+        position = end + size % 2;
+    }
+    chunks.retain(|(fourcc, _)| !matches!(*fourcc, b"VP8X" | b"ICCP" | b"EXIF" | b"XMP "));
+    // Lossy images carry alpha in their own chunk, lossless ones flag it in the header
+    let alpha = chunks.iter().any(|(fourcc, data)| {
+        *fourcc == b"ALPH"
+            || (*fourcc == b"VP8L"
+                && data.len() >= 5
+                && u32::from_le_bytes([data[1], data[2], data[3], data[4]]) >> 28 & 1 == 1)
+    });
+
+    let mut flags = 0u8;
+    if metadata.icc_profile.is_some() {
+        flags |= 0x20;
+    }
+    if alpha {
+        flags |= 0x10;
+    }
+    if metadata.exif.is_some() {
+        flags |= 0x08;
+    }
+    if metadata.xmp.is_some() {
+        flags |= 0x04;
+    }
+    let mut header = vec![flags, 0, 0, 0];
+    header.extend(&(width - 1).to_le_bytes()[..3]);
+    header.extend(&(height - 1).to_le_bytes()[..3]);
+
+    let mut body = b"WEBP".to_vec();
+    let mut push = |fourcc: &[u8], data: &[u8]| {
+        body.extend(fourcc);
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(data);
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    };
+    push(b"VP8X", &header);
+    if let Some(icc_profile) = &metadata.icc_profile {
+        push(b"ICCP", icc_profile);
+    }
+    for (fourcc, data) in chunks {
+        push(fourcc, data);
+    }
+    if let Some(exif) = &metadata.exif {
+        push(b"EXIF", exif);
+    }
+    if let Some(xmp) = &metadata.xmp {
+        push(b"XMP ", xmp);
+    }
+
+    let mut output = b"RIFF".to_vec();
+    output.extend((body.len() as u32).to_le_bytes());
+    output.extend(body);
+    output
+}
+
+/// The CRC-32 of PNG chunks.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
@@ -1 +1,6 @@
+pub mod animation;
+pub mod cache;
+pub mod encode;
 pub mod images;
+pub mod metadata;
+pub mod tiled;
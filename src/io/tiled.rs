@@ -0,0 +1,429 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageFormat, RgbaImage};
+
+use super::images::{DecodeOptions, decode};
+use crate::error::ImageSlapperError;
+
+/// Rows of an image decoded on demand, so only a few of them are in memory at once.
+///
+/// Pixels come as stored, without color management or EXIF orientation.
+pub trait RowSource {
+    fn dimensions(&self) -> (u32, u32);
+
+    /// Copies row `y` into `row` as RGBA. Rows are read top to bottom: rows skipped over are
+    /// decoded and dropped, and a row above the last one read may no longer be available.
+    fn read_row(&mut self, y: u32, row: &mut [u8]) -> Result<(), ImageSlapperError>;
+
+    /// Bytes the source holds between reads.
+    fn resident_bytes(&self) -> u64;
+}
+
+/// Opens an image as rows: non-interlaced PNGs row by row, TIFFs a strip or row of tiles at a
+/// time, and anything else decoded whole. Every format is read as stored, so an image looks the
+/// same whichever way it is decoded.
+pub fn open_rows(image_path: &str) -> Result<Box<dyn RowSource + Send>, ImageSlapperError> {
+    let path = Path::new(image_path);
+    let open = || {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|error| ImageSlapperError::io(path, "open image", error))
+    };
+    let mut reader = open()?;
+    let mut magic = [0u8; 16];
+    let read = reader
+        .read(&mut magic)
+        .map_err(|error| ImageSlapperError::io(path, "read image", error))?;
+    match image::guess_format(&magic[..read]) {
+        Ok(ImageFormat::Png) => {
+            let rows = PngRows::new(open()?, path)?;
+            if !rows.interlaced {
+                return Ok(Box::new(rows));
+            }
+        }
+        Ok(ImageFormat::Tiff) => return Ok(Box::new(TiffRows::new(open()?, path)?)),
+        _ => {}
+    }
+    let as_stored = DecodeOptions {
+        apply_orientation: false,
+        working_space: None,
+        ..DecodeOptions::default()
+    };
+    let image = decode(open()?, path, &as_stored)?;
+    Ok(Box::new(ImageRows::new(&image)))
+}
+
+/// Wraps an error from a row decoder.
+fn decoding(path: &Path, format: ImageFormat, error: impl std::fmt::Display) -> ImageSlapperError {
+    ImageSlapperError::Decode {
+        path: path.to_path_buf(),
+        source: ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Exact(format),
+            error.to_string(),
+        )),
+    }
+}
+
+/// An image already in memory, shared so several readers cost nothing extra.
+#[derive(Debug, Clone)]
+pub struct ImageRows {
+    image: Arc<RgbaImage>,
+}
+
+impl ImageRows {
+    pub fn new(image: &DynamicImage) -> Self {
+        ImageRows {
+            image: Arc::new(image.to_rgba8()),
+        }
+    }
+}
+
+impl From<Arc<RgbaImage>> for ImageRows {
+    fn from(image: Arc<RgbaImage>) -> Self {
+        ImageRows { image }
+    }
+}
+
+impl RowSource for ImageRows {
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    fn read_row(&mut self, y: u32, row: &mut [u8]) -> Result<(), ImageSlapperError> {
+        let length = self.image.width() as usize * 4;
+        let start = y as usize * length;
+        row.copy_from_slice(&self.image.as_raw()[start..start + length]);
+        Ok(())
+    }
+
+    fn resident_bytes(&self) -> u64 {
+        self.image.as_raw().len() as u64
+    }
+}
+
+/// A PNG decoded one row at a time.
+pub struct PngRows<R: BufRead + Seek> {
+    reader: png::Reader<R>,
+    path: PathBuf,
+    interlaced: bool,
+    /// Index of the row the decoder returns next.
+    next: u32,
+    /// The last row read, as RGBA.
+    last: Vec<u8>,
+}
+
+impl<R: BufRead + Seek> PngRows<R> {
+    pub fn new(reader: R, path: &Path) -> Result<Self, ImageSlapperError> {
+        let failed = |error| decoding(path, ImageFormat::Png, error);
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let reader = decoder.read_info().map_err(failed)?;
+        let interlaced = reader.info().interlaced;
+        let width = reader.info().width;
+        Ok(PngRows {
+            reader,
+            path: path.to_path_buf(),
+            interlaced,
+            next: 0,
+            last: vec![0; width as usize * 4],
+        })
+    }
+}
+
+impl<R: BufRead + Seek> RowSource for PngRows<R> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.reader.info().width, self.reader.info().height)
+    }
+
+    fn read_row(&mut self, y: u32, row: &mut [u8]) -> Result<(), ImageSlapperError> {
+        let failed = |error: &dyn std::fmt::Display| decoding(&self.path, ImageFormat::Png, error);
+        if y + 1 < self.next {
+            return Err(failed(&format!("row {y} was already passed")));
+        }
+        let (color, _) = self.reader.output_color_type();
+        while self.next <= y {
+            let decoded = self
+                .reader
+                .next_row()
+                .map_err(|error| failed(&error))?
+                .ok_or_else(|| failed(&format!("row {y} is past the end")))?;
+            for (pixel, target) in decoded
+                .data()
+                .chunks_exact(color.samples())
+                .zip(self.last.chunks_exact_mut(4))
+            {
+                target.copy_from_slice(&match *pixel {
+                    [gray] => [gray, gray, gray, 255],
+                    [gray, alpha] => [gray, gray, gray, alpha],
+                    [r, g, b] => [r, g, b, 255],
+                    [r, g, b, a] => [r, g, b, a],
+                    _ => [0, 0, 0, 0],
+                });
+            }
+            self.next += 1;
+        }
+        row.copy_from_slice(&self.last);
+        Ok(())
+    }
+
+    fn resident_bytes(&self) -> u64 {
+        // The decoder keeps the previous raw row to undo filters
+        self.last.len() as u64 * 2
+    }
+}
+
+/// A TIFF decoded one strip or row of tiles at a time.
+pub struct TiffRows<R: Read + Seek> {
+    decoder: tiff::decoder::Decoder<R>,
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    /// Size of a full strip or tile.
+    chunk: (u32, u32),
+    /// Index of the band of chunks held, and its pixels as RGBA.
+    band: Option<(u32, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> TiffRows<R> {
+    pub fn new(reader: R, path: &Path) -> Result<Self, ImageSlapperError> {
+        let failed = |error| decoding(path, ImageFormat::Tiff, error);
+        let mut decoder = tiff::decoder::Decoder::new(reader).map_err(failed)?;
+        let (width, height) = decoder.dimensions().map_err(failed)?;
+        let planar = decoder
+            .find_tag_unsigned::<u16>(tiff::tags::Tag::PlanarConfiguration)
+            .map_err(failed)?;
+        if planar == Some(2) {
+            return Err(decoding(
+                path,
+                ImageFormat::Tiff,
+                "planar TIFFs cannot be read in strips",
+            ));
+        }
+        let chunk = decoder.chunk_dimensions();
+        Ok(TiffRows {
+            decoder,
+            path: path.to_path_buf(),
+            width,
+            height,
+            chunk,
+            band: None,
+        })
+    }
+
+    /// Decodes every chunk of a band and converts it to RGBA.
+    fn read_band(&mut self, band: u32) -> Result<Vec<u8>, ImageSlapperError> {
+        let path = self.path.clone();
+        let failed = |error: &dyn std::fmt::Display| decoding(&path, ImageFormat::Tiff, error);
+        let color = self.decoder.colortype().map_err(|error| failed(&error))?;
+        let channels = match color {
+            tiff::ColorType::Gray(8 | 16) => 1,
+            tiff::ColorType::GrayA(8 | 16) => 2,
+            tiff::ColorType::RGB(8 | 16) => 3,
+            tiff::ColorType::RGBA(8 | 16) => 4,
+            other => return Err(failed(&format!("cannot read {other:?} pixels in strips"))),
+        };
+        let (chunk_width, chunk_height) = self.chunk;
+        let across = self.width.div_ceil(chunk_width);
+        let rows = chunk_height.min(self.height - band * chunk_height);
+        let mut pixels = vec![0u8; self.width as usize * rows as usize * 4];
+        for column in 0..across {
+            let index = band * across + column;
+            let (data_width, data_height) = self.decoder.chunk_data_dimensions(index);
+            let samples: Vec<u8> = match self
+                .decoder
+                .read_chunk(index)
+                .map_err(|error| failed(&error))?
+            {
+                tiff::decoder::DecodingResult::U8(samples) => samples,
+                tiff::decoder::DecodingResult::U16(samples) => samples
+                    .into_iter()
+                    .map(|sample| (sample >> 8) as u8)
+                    .collect(),
+                _ => return Err(failed(&"unsupported sample format")),
+            };
+            // Edge tiles are padded to the full tile width
+            let stride = samples.len() / data_height.max(1) as usize;
+            for y in 0..data_height.min(rows) as usize {
+                let source = &samples[y * stride..];
+                let start = (y * self.width as usize + (column * chunk_width) as usize) * 4;
+                let target = &mut pixels[start..start + data_width as usize * 4];
+                for (pixel, target) in source
+                    .chunks_exact(channels)
+                    .zip(target.chunks_exact_mut(4))
+                {
+                    target.copy_from_slice(&match *pixel {
+                        [gray] => [gray, gray, gray, 255],
+                        [gray, alpha] => [gray, gray, gray, alpha],
+                        [r, g, b] => [r, g, b, 255],
+                        [r, g, b, a] => [r, g, b, a],
+                        _ => [0, 0, 0, 0],
+                    });
+                }
+            }
+        }
+        Ok(pixels)
+    }
+}
+
+impl<R: Read + Seek> RowSource for TiffRows<R> {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn read_row(&mut self, y: u32, row: &mut [u8]) -> Result<(), ImageSlapperError> {
+        let band = y / self.chunk.1;
+        // A band other than the one asked for is dropped before decoding the next
+        let held = self.band.take().filter(|(held, _)| *held == band);
+        let pixels = match held {
+            Some((_, pixels)) => pixels,
+            None => self.read_band(band)?,
+        };
+        let length = self.width as usize * 4;
+        let start = (y % self.chunk.1) as usize * length;
+        row.copy_from_slice(&pixels[start..start + length]);
+        self.band = Some((band, pixels));
+        Ok(())
+    }
+
+    fn resident_bytes(&self) -> u64 {
+        // The band and the raw chunks it was converted from
+        u64::from(self.width) * u64::from(self.chunk.1.min(self.height)) * 4 * 2
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use image::RgbImage;
+
+    use super::*;
+
+    /// A gradient with no two neighbouring pixels alike.
+    pub(crate) fn source() -> RgbImage {
+        RgbImage::from_fn(37, 23, |x, y| {
+            image::Rgb([(x * 7) as u8, (y * 11) as u8, (x * y) as u8])
+        })
+    }
+
+    /// An uncompressed TIFF with `rows` rows per strip.
+    pub(crate) fn striped_tiff(image: &RgbImage, rows: u32) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        let mut encoder = tiff::encoder::TiffEncoder::new(&mut bytes).unwrap();
+        let mut tiff = encoder
+            .new_image::<tiff::encoder::colortype::RGB8>(image.width(), image.height())
+            .unwrap();
+        tiff.rows_per_strip(rows).unwrap();
+        tiff.write_data(image.as_raw()).unwrap();
+        bytes.into_inner()
+    }
+
+    /// An uncompressed TIFF in tiles of `tile` pixels, with the edge tiles padded.
+    pub(crate) fn tiled_tiff(image: &RgbImage, tile: (u32, u32)) -> Vec<u8> {
+        let (width, height) = image.dimensions();
+        let (across, down) = (width.div_ceil(tile.0), height.div_ceil(tile.1));
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        for tile_y in 0..down {
+            for tile_x in 0..across {
+                offsets.push(8 + data.len() as u32);
+                for y in tile_y * tile.1..(tile_y + 1) * tile.1 {
+                    for x in tile_x * tile.0..(tile_x + 1) * tile.0 {
+                        let pixel = image
+                            .get_pixel_checked(x, y)
+                            .map_or([0; 3], |pixel| pixel.0);
+                        data.extend(pixel);
+                    }
+                }
+            }
+        }
+        let bits = 8 + data.len() as u32;
+        let offsets_at = bits + 6;
+        let counts_at = offsets_at + 4 * offsets.len() as u32;
+        let directory = counts_at + 4 * offsets.len() as u32;
+
+        let mut bytes = b"II*\0".to_vec();
+        bytes.extend(directory.to_le_bytes());
+        bytes.extend(&data);
+        bytes.extend([8u16; 3].iter().flat_map(|bits| bits.to_le_bytes()));
+        bytes.extend(offsets.iter().flat_map(|offset| offset.to_le_bytes()));
+        let count = tile.0 * tile.1 * 3;
+        bytes.extend(offsets.iter().flat_map(|_| count.to_le_bytes()));
+        // Tag, type (3 short, 4 long), count and value or offset
+        let entries: [(u16, u16, u32, u32); 11] = [
+            (256, 4, 1, width),
+            (257, 4, 1, height),
+            (258, 3, 3, bits),
+            (259, 3, 1, 1),
+            (262, 3, 1, 2),
+            (277, 3, 1, 3),
+            (284, 3, 1, 1),
+            (322, 4, 1, tile.0),
+            (323, 4, 1, tile.1),
+            (324, 4, offsets.len() as u32, offsets_at),
+            (325, 4, offsets.len() as u32, counts_at),
+        ];
+        bytes.extend((entries.len() as u16).to_le_bytes());
+        for (tag, kind, count, value) in entries {
+            bytes.extend(tag.to_le_bytes());
+            bytes.extend(kind.to_le_bytes());
+            bytes.extend(count.to_le_bytes());
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(0u32.to_le_bytes());
+        bytes
+    }
+
+    fn png(image: &RgbImage) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    /// Reads every row and checks it against the source.
+    fn assert_reads_source(mut rows: impl RowSource) {
+        let source = DynamicImage::ImageRgb8(source()).to_rgba8();
+        assert_eq!(rows.dimensions(), source.dimensions());
+        let mut row = vec![0; source.width() as usize * 4];
+        for (y, expected) in source.as_raw().chunks_exact(row.len()).enumerate() {
+            rows.read_row(y as u32, &mut row).unwrap();
+            assert_eq!(row, expected, "row {y}");
+        }
+    }
+
+    #[test]
+    fn png_rows_match_the_source() {
+        let rows = PngRows::new(Cursor::new(png(&source())), Path::new("<test>")).unwrap();
+        assert!(!rows.interlaced);
+        assert_reads_source(rows);
+    }
+
+    #[test]
+    fn striped_tiff_rows_match_the_source() {
+        let bytes = striped_tiff(&source(), 4);
+        let rows = TiffRows::new(Cursor::new(bytes), Path::new("<test>")).unwrap();
+        assert_eq!(rows.chunk, (37, 4));
+        assert_reads_source(rows);
+    }
+
+    #[test]
+    fn tiled_tiff_rows_match_the_source() {
+        let bytes = tiled_tiff(&source(), (16, 16));
+        let rows = TiffRows::new(Cursor::new(bytes), Path::new("<test>")).unwrap();
+        assert_eq!(rows.chunk, (16, 16));
+        assert_reads_source(rows);
+    }
+
+    #[test]
+    fn rows_already_passed_are_an_error() {
+        let mut rows = PngRows::new(Cursor::new(png(&source())), Path::new("<test>")).unwrap();
+        let mut row = vec![0; 37 * 4];
+        rows.read_row(10, &mut row).unwrap();
+        assert!(rows.read_row(10, &mut row).is_ok());
+        assert!(rows.read_row(9, &mut row).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod builders;
+pub mod error;
 pub mod geometry;
 pub mod helpers;
 pub mod io;
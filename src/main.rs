@@ -18,10 +18,17 @@ use std::time::{Duration, Instant};
 
 use image::DynamicImage;
 use imageslapper::{
-    builders::ImageBuilder, geometry::rectangle::Rectangle, helpers,
-    io::images::load_image_with_qoi, rendering::draw::ImageWrapper, text,
+    builders::ImageBuilder,
+    error::ImageSlapperError,
+    geometry::rectangle::Rectangle,
+    helpers,
+    io::cache::{CacheLimits, EvictionPolicy, QoiCache},
+    io::images::load_image_with_qoi,
+    rendering::draw::ImageWrapper,
+    text,
 };
-use tracing::info;
+use rgb::Rgba;
+use tracing::{info, warn};
 
 /// Removes the testing-outputs directory if it exists.
 fn remove_testing_outputs() -> Duration {
@@ -58,7 +65,7 @@ pub fn create_image_wrapper(image: DynamicImage) -> (ImageWrapper, std::time::Du
 }
 
 /// Placeholder for barcode demo.
-fn barcode_demo() -> Duration {
+fn barcode_demo() -> Result<Duration, ImageSlapperError> {
     let start = Instant::now();
 
     // Create a barcode
@@ -67,8 +74,36 @@ fn barcode_demo() -> Duration {
         helpers::code128::from(content, 500, 500, 25).expect("Failed to build code128 barcode");
 
     // Write the image to a file
-    ImageWrapper::new(image).save_image("testing-outputs/Code128.png");
-    start.elapsed()
+    ImageWrapper::new(image).save_image("testing-outputs/Code128.png")?;
+
+    // Create a retail barcode with its digits, the check digit is computed
+    let font = text::helpers::open_sans().expect("Failed to load font");
+    let image = helpers::barcode::from_with_text(
+        helpers::barcode::Symbology::Ean13,
+        "400638133393",
+        500,
+        300,
+        25,
+        helpers::linear::HumanReadable::new(font, 40.0),
+    )
+    .expect("Failed to build EAN-13 barcode");
+
+    // Read it back from the pixels and grade the print quality
+    let expected =
+        helpers::verify::Expected::new(helpers::verify::SymbolFormat::Ean13, "4006381333931");
+    match helpers::verify::verify(
+        &image,
+        &expected,
+        &helpers::verify::VerifyOptions::default(),
+    ) {
+        Ok(verification) => info!(
+            "EAN-13 verified with grade {:?}",
+            verification.grade.map(|grade| grade.grade)
+        ),
+        Err(error) => warn!("EAN-13 failed verification: {}", error),
+    }
+    ImageWrapper::new(image).save_image("testing-outputs/EAN13.png")?;
+    Ok(start.elapsed())
 }
 
 /// Placeholder for coordinates demo.
@@ -85,18 +120,43 @@ fn fields_demo() -> Duration {
     start.elapsed()
 }
 
-/// Placeholder for inverted multiline demo.
-fn inverted_multiline_demo() -> Duration {
+/// Draws multiline text upside down, like the back of a folded table card.
+fn inverted_multiline_demo() -> Result<Duration, ImageSlapperError> {
     let start = Instant::now();
-    // ...logic to be implemented...
-    start.elapsed()
+
+    let field = Rectangle::from_xywh(25, 25, 450, 450);
+    let text = text::helpers::simple_open_sans("Upside down\nacross several\nlines", field)
+        .expect("Failed to create text")
+        .inverted();
+    let image = ImageBuilder::new(500, 500)
+        .add_rectangle(Rectangle::from_xywh(0, 0, 500, 500).filled(Rgba::new(30, 30, 30, 255)))?
+        .add_text(text)?
+        .get_image();
+
+    ImageWrapper::new(image).save_image("testing-outputs/inverted_multiline.png")?;
+    Ok(start.elapsed())
 }
 
-/// Placeholder for QR demo.
-fn qr_demo() -> Duration {
+/// Generates a QR code with high error correction.
+fn qr_demo() -> Result<Duration, ImageSlapperError> {
     let start = Instant::now();
-    // ...logic to be implemented...
-    start.elapsed()
+
+    // Create a QR code with high error correction
+    let config = helpers::qr::QRCodeConfig {
+        error_correction: helpers::qr::ErrorCorrection::H,
+        ..Default::default()
+    };
+    let image = helpers::qr::from(
+        "https://github.com/ofluffydev/imageslapper",
+        500,
+        500,
+        config,
+    )
+    .expect("Failed to build QR code");
+
+    // Write the image to a file
+    ImageWrapper::new(image).save_image("testing-outputs/qr.png")?;
+    Ok(start.elapsed())
 }
 
 /// Placeholder for single line demo.
@@ -114,7 +174,7 @@ fn text_anchors_demo() -> Duration {
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), ImageSlapperError> {
     let start = Instant::now();
 
     // Tracing subscriber is goated
@@ -126,7 +186,15 @@ async fn main() {
     let image_path = "testing-inputs/image.avif";
 
     // Use qoi
-    let (image, time_load_image) = load_image_with_qoi(image_path);
+    let cache = QoiCache::new("./.cache").with_limits(
+        CacheLimits {
+            max_bytes: Some(2 * 1024 * 1024 * 1024),
+            max_entries: None,
+        },
+        EvictionPolicy::Lru,
+    );
+    let (image, time_load_image) = load_image_with_qoi(image_path, &cache)?;
+    cache.log_stats();
 
     let (_, time_read_metadata) = read_image_metadata(image_path);
 
@@ -134,24 +202,24 @@ async fn main() {
     let rectangle_width = image.width();
     let rectangle = Rectangle::new(0, 0, rectangle_width, rectangle_height);
     let image = ImageBuilder::from_image(image)
-        .add_rectangle(rectangle)
+        .add_rectangle(rectangle)?
         .add_text(
             text::helpers::simple_open_sans("Hello, world!", rectangle)
                 .expect("Failed to create text"),
-        )
+        )?
         .get_image();
     let time_render_text_and_rectangle = start.elapsed();
 
     let time_create_output_dir = create_output_directory();
 
-    ImageWrapper::new(image).save_image("testing-outputs/image.png");
-    let time_save_image = start.elapsed() - time_create_output_dir;
+    ImageWrapper::new(image).save_image("testing-outputs/image.png")?;
+    let time_save_image = start.elapsed().saturating_sub(time_create_output_dir);
 
-    let time_barcode_demo = barcode_demo();
+    let time_barcode_demo = barcode_demo()?;
     let time_coordinates_demo = coordinates_demo();
     let time_fields_demo = fields_demo();
-    let time_inverted_multiline_demo = inverted_multiline_demo();
-    let time_qr_demo = qr_demo();
+    let time_inverted_multiline_demo = inverted_multiline_demo()?;
+    let time_qr_demo = qr_demo()?;
     let time_single_line_demo = single_line_demo();
     let time_text_anchors_demo = text_anchors_demo();
 
@@ -194,4 +262,5 @@ async fn main() {
     info!("QR demo: {:?}", time_qr_demo);
     info!("Single line demo: {:?}", time_single_line_demo);
     info!("Text anchors demo: {:?}", time_text_anchors_demo);
+    Ok(())
 }
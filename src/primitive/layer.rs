@@ -1,6 +1,7 @@
 use tracing::debug;
 
 use crate::geometry::rectangle::Rectangle;
+use crate::rendering::color::ColorSpace;
 
 use super::delta_buffer::{DeltaBuffer, PixelDelta};
 
@@ -28,7 +29,7 @@ pub struct Layer<T: PixelProvider> {
 }
 
 /// Blend modes for rendering layers.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlendMode {
     Normal,
     Multiply,
@@ -39,6 +40,21 @@ pub enum BlendMode {
 }
 
 impl<T: PixelProvider> Layer<T> {
+    /// Creates a visible, opaque layer at the origin with no transformations.
+    pub fn new(content: T) -> Self {
+        Layer {
+            content,
+            position: (0.0, 0.0),
+            z_index: 0,
+            opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+            transform: Transform::identity(),
+            visible: true,
+            clip_mask: None,
+            last_render_hash: 0,
+        }
+    }
+
     /// Instead of rendering directly, collect pixel changes
     pub fn collect_changes(&self, prev_state: Option<&LayerState>) -> DeltaBuffer {
         debug!(
@@ -149,6 +165,7 @@ impl<T: PixelProvider> Layer<T> {
                 border: None,
                 filled: false,
                 fill_color: None,
+                color_space: ColorSpace::Srgb,
             }
         } else {
             // Use the current layer's bounds if no previous state exists.
@@ -166,6 +183,7 @@ impl<T: PixelProvider> Layer<T> {
                 border: None,
                 filled: false,
                 fill_color: None,
+                color_space: ColorSpace::Srgb,
             }
         }
     }
@@ -241,7 +259,7 @@ impl ClipMask {
 }
 
 /// Represents transformations applied to a layer.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Transform {
     /// Horizontal translation (in pixels).
     pub translate_x: f64,
@@ -279,3 +297,33 @@ pub trait PixelProvider {
     /// Retrieves the height of the content.
     fn height(&self) -> u32;
 }
+
+impl<T: PixelProvider + ?Sized> PixelProvider for Box<T> {
+    fn pixel_at(&self, x: u32, y: u32) -> rgb::Rgba<u8> {
+        (**self).pixel_at(x, y)
+    }
+
+    fn width(&self) -> u32 {
+        (**self).width()
+    }
+
+    fn height(&self) -> u32 {
+        (**self).height()
+    }
+}
+
+/// Images as layer content, e.g. a logo or pre-rendered text.
+impl PixelProvider for image::RgbaImage {
+    fn pixel_at(&self, x: u32, y: u32) -> rgb::Rgba<u8> {
+        let [r, g, b, a] = self.get_pixel(x, y).0;
+        rgb::Rgba { r, g, b, a }
+    }
+
+    fn width(&self) -> u32 {
+        self.dimensions().0
+    }
+
+    fn height(&self) -> u32 {
+        self.dimensions().1
+    }
+}
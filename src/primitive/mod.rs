@@ -1,2 +1,3 @@
 pub mod delta_buffer;
 pub mod layer;
+pub mod timeline;
@@ -0,0 +1,686 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use image::{DynamicImage, RgbaImage};
+use rayon::prelude::*;
+use tracing::debug;
+
+use crate::error::ImageSlapperError;
+use crate::io::animation::{Animation, AnimationFrame, LoopCount};
+use crate::rendering::blend::blend_pixel_with;
+
+use super::layer::{Layer, PixelProvider, Transform};
+
+/// Where the jumps of a stepped easing fall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepPosition {
+    /// The first jump happens right away, like CSS `jump-start`.
+    Start,
+    /// The last jump happens at the end, like CSS `jump-end`.
+    End,
+}
+
+/// How a value moves from one keyframe to the next, with the same meaning as in CSS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    /// A curve through (0, 0), (x1, y1), (x2, y2) and (1, 1).
+    CubicBezier(f64, f64, f64, f64),
+    /// Holds the value in `count` equal steps.
+    Steps(u32, StepPosition),
+}
+
+impl Easing {
+    pub const EASE: Easing = Easing::CubicBezier(0.25, 0.1, 0.25, 1.0);
+    pub const EASE_IN: Easing = Easing::CubicBezier(0.42, 0.0, 1.0, 1.0);
+    pub const EASE_OUT: Easing = Easing::CubicBezier(0.0, 0.0, 0.58, 1.0);
+    pub const EASE_IN_OUT: Easing = Easing::CubicBezier(0.42, 0.0, 0.58, 1.0);
+
+    /// Maps the progress between two keyframes, from 0.0 to 1.0, to the eased progress.
+    pub fn apply(&self, progress: f64) -> f64 {
+        let progress = progress.clamp(0.0, 1.0);
+        match *self {
+            Easing::Linear => progress,
+            Easing::CubicBezier(x1, y1, x2, y2) => {
+                let x1 = x1.clamp(0.0, 1.0);
+                let x2 = x2.clamp(0.0, 1.0);
+                let bezier = |a: f64, b: f64, s: f64| {
+                    3.0 * a * s * (1.0 - s).powi(2) + 3.0 * b * s * s * (1.0 - s) + s.powi(3)
+                };
+                // Find the curve parameter whose x is the progress, by Newton's method with
+                // bisection where the slope is too flat
+                let (mut low, mut high, mut s) = (0.0, 1.0, progress);
+                for _ in 0..32 {
+                    let error = bezier(x1, x2, s) - progress;
+                    if error.abs() < 1e-7 {
+                        break;
+                    }
+                    if error > 0.0 {
+                        high = s;
+                    } else {
+                        low = s;
+                    }
+                    let slope = 3.0 * x1 * (1.0 - s).powi(2)
+                        + 6.0 * (x2 - x1) * s * (1.0 - s)
+                        + 3.0 * (1.0 - x2) * s * s;
+                    let next = s - error / slope;
+                    s = if slope.abs() > 1e-6 && next > low && next < high {
+                        next
+                    } else {
+                        (low + high) / 2.0
+                    };
+                }
+                bezier(y1, y2, s)
+            }
+            Easing::Steps(count, position) => {
+                let count = f64::from(count.max(1));
+                let step = (progress * count).floor()
+                    + match position {
+                        StepPosition::Start => 1.0,
+                        StepPosition::End => 0.0,
+                    };
+                (step / count).min(1.0)
+            }
+        }
+    }
+}
+
+/// Values that can move between keyframes.
+pub trait Animatable: Clone {
+    /// Returns the value `progress` of the way from this one to `other`.
+    fn interpolate(&self, other: &Self, progress: f64) -> Self;
+}
+
+impl Animatable for f64 {
+    fn interpolate(&self, other: &Self, progress: f64) -> Self {
+        self + (other - self) * progress
+    }
+}
+
+impl Animatable for f32 {
+    fn interpolate(&self, other: &Self, progress: f64) -> Self {
+        self + (other - self) * progress as f32
+    }
+}
+
+impl Animatable for (f64, f64) {
+    fn interpolate(&self, other: &Self, progress: f64) -> Self {
+        (
+            self.0.interpolate(&other.0, progress),
+            self.1.interpolate(&other.1, progress),
+        )
+    }
+}
+
+/// Switches to the next value once the eased progress reaches it.
+impl Animatable for bool {
+    fn interpolate(&self, other: &Self, progress: f64) -> Self {
+        if progress >= 1.0 { *other } else { *self }
+    }
+}
+
+impl Animatable for Transform {
+    fn interpolate(&self, other: &Self, progress: f64) -> Self {
+        Transform {
+            translate_x: self.translate_x.interpolate(&other.translate_x, progress),
+            translate_y: self.translate_y.interpolate(&other.translate_y, progress),
+            scale_x: self.scale_x.interpolate(&other.scale_x, progress),
+            scale_y: self.scale_y.interpolate(&other.scale_y, progress),
+            rotation: self.rotation.interpolate(&other.rotation, progress),
+        }
+    }
+}
+
+/// A value at a point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframe<V> {
+    pub time: Duration,
+    pub value: V,
+    /// How the value moves from this keyframe to the next.
+    pub easing: Easing,
+}
+
+impl<V> Keyframe<V> {
+    pub fn new(time: Duration, value: V) -> Self {
+        Keyframe {
+            time,
+            value,
+            easing: Easing::Linear,
+        }
+    }
+
+    pub fn eased(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+}
+
+/// Keyframes of one property, holding the first value before them and the last after.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<V> {
+    keyframes: Vec<Keyframe<V>>,
+}
+
+impl<V: Animatable> Track<V> {
+    pub fn new(mut keyframes: Vec<Keyframe<V>>) -> Self {
+        keyframes.sort_by_key(|keyframe| keyframe.time);
+        Track { keyframes }
+    }
+
+    /// Returns the value at a time, `None` without keyframes.
+    pub fn value_at(&self, time: Duration) -> Option<V> {
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        let Some(next_keyframe) = self.keyframes.get(next) else {
+            return self.keyframes.last().map(|keyframe| keyframe.value.clone());
+        };
+        let Some(previous) = next.checked_sub(1).map(|index| &self.keyframes[index]) else {
+            return Some(next_keyframe.value.clone());
+        };
+        let span = (next_keyframe.time - previous.time).as_secs_f64();
+        let progress = (time - previous.time).as_secs_f64() / span;
+        Some(
+            previous
+                .value
+                .interpolate(&next_keyframe.value, previous.easing.apply(progress)),
+        )
+    }
+}
+
+/// A layer with keyframed properties. Properties without a track keep the layer's value.
+#[derive(Debug, Clone)]
+pub struct AnimatedLayer<T: PixelProvider> {
+    pub layer: Layer<T>,
+    pub position: Option<Track<(f64, f64)>>,
+    pub opacity: Option<Track<f32>>,
+    pub transform: Option<Track<Transform>>,
+    pub visible: Option<Track<bool>>,
+}
+
+impl<T: PixelProvider> AnimatedLayer<T> {
+    pub fn new(layer: Layer<T>) -> Self {
+        AnimatedLayer {
+            layer,
+            position: None,
+            opacity: None,
+            transform: None,
+            visible: None,
+        }
+    }
+
+    pub fn with_position(mut self, keyframes: Vec<Keyframe<(f64, f64)>>) -> Self {
+        self.position = Some(Track::new(keyframes));
+        self
+    }
+
+    pub fn with_opacity(mut self, keyframes: Vec<Keyframe<f32>>) -> Self {
+        self.opacity = Some(Track::new(keyframes));
+        self
+    }
+
+    pub fn with_transform(mut self, keyframes: Vec<Keyframe<Transform>>) -> Self {
+        self.transform = Some(Track::new(keyframes));
+        self
+    }
+
+    pub fn with_visibility(mut self, keyframes: Vec<Keyframe<bool>>) -> Self {
+        self.visible = Some(Track::new(keyframes));
+        self
+    }
+
+    /// Returns the animated properties at a time.
+    fn pose_at(&self, time: Duration) -> Pose {
+        fn value<V: Animatable>(track: &Option<Track<V>>, time: Duration, fallback: V) -> V {
+            track
+                .as_ref()
+                .and_then(|track| track.value_at(time))
+                .unwrap_or(fallback)
+        }
+        Pose {
+            position: value(&self.position, time, self.layer.position),
+            opacity: value(&self.opacity, time, self.layer.opacity).clamp(0.0, 1.0),
+            transform: value(&self.transform, time, self.layer.transform),
+            visible: value(&self.visible, time, self.layer.visible),
+        }
+    }
+}
+
+/// The animated properties of a layer in one frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pose {
+    position: (f64, f64),
+    opacity: f32,
+    transform: Transform,
+    visible: bool,
+}
+
+/// A canvas area as (x1, y1, x2, y2), exclusive at the end.
+type Area = (u32, u32, u32, u32);
+
+impl Pose {
+    fn shows(&self) -> bool {
+        self.visible
+            && self.opacity > 0.0
+            && self.transform.scale_x != 0.0
+            && self.transform.scale_y != 0.0
+    }
+
+    /// Half the scaled content size, the pivot of the rotation.
+    fn center(&self, (width, height): (u32, u32)) -> (f64, f64) {
+        (
+            f64::from(width) * self.transform.scale_x / 2.0,
+            f64::from(height) * self.transform.scale_y / 2.0,
+        )
+    }
+
+    /// Where the top-left corner of the unrotated, scaled content sits on the canvas.
+    fn origin(&self) -> (f64, f64) {
+        (
+            self.position.0 + self.transform.translate_x,
+            self.position.1 + self.transform.translate_y,
+        )
+    }
+
+    /// The canvas area the layer covers, `None` when it shows nothing on the canvas.
+    fn area(&self, size: (u32, u32), canvas: (u32, u32)) -> Option<Area> {
+        if !self.shows() {
+            return None;
+        }
+        let (center_x, center_y) = self.center(size);
+        let (origin_x, origin_y) = self.origin();
+        let (sin, cos) = self.transform.rotation.to_radians().sin_cos();
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(u, v)| {
+            let (dx, dy) = (u * center_x, v * center_y);
+            (
+                origin_x + center_x + dx * cos - dy * sin,
+                origin_y + center_y + dx * sin + dy * cos,
+            )
+        });
+        let clamp = |value: f64, max: u32| value.clamp(0.0, f64::from(max)) as u32;
+        let x1 = clamp(
+            corners.iter().map(|c| c.0).fold(f64::MAX, f64::min).floor(),
+            canvas.0,
+        );
+        let y1 = clamp(
+            corners.iter().map(|c| c.1).fold(f64::MAX, f64::min).floor(),
+            canvas.1,
+        );
+        let x2 = clamp(
+            corners.iter().map(|c| c.0).fold(f64::MIN, f64::max).ceil(),
+            canvas.0,
+        );
+        let y2 = clamp(
+            corners.iter().map(|c| c.1).fold(f64::MIN, f64::max).ceil(),
+            canvas.1,
+        );
+        (x1 < x2 && y1 < y2).then_some((x1, y1, x2, y2))
+    }
+
+    /// Maps a canvas pixel back onto the content, `None` outside it.
+    fn content_pixel(&self, x: u32, y: u32, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+        let (center_x, center_y) = self.center((width, height));
+        let (origin_x, origin_y) = self.origin();
+        let (sin, cos) = self.transform.rotation.to_radians().sin_cos();
+        let dx = f64::from(x) + 0.5 - origin_x - center_x;
+        let dy = f64::from(y) + 0.5 - origin_y - center_y;
+        let u = (dx * cos + dy * sin + center_x) / self.transform.scale_x;
+        let v = (-dx * sin + dy * cos + center_y) / self.transform.scale_y;
+        (u >= 0.0 && v >= 0.0 && u < f64::from(width) && v < f64::from(height))
+            .then_some((u as u32, v as u32))
+    }
+}
+
+/// Layers over a background whose properties change over time, rendered as frames.
+///
+/// Between frames only the areas of layers that moved or changed are composited again.
+#[derive(Debug, Clone)]
+pub struct Timeline<T: PixelProvider> {
+    pub background: DynamicImage,
+    pub duration: Duration,
+    pub layers: Vec<AnimatedLayer<T>>,
+    pub loop_count: LoopCount,
+}
+
+impl<T: PixelProvider + Sync> Timeline<T> {
+    pub fn new(background: DynamicImage, duration: Duration) -> Self {
+        Timeline {
+            background,
+            duration,
+            layers: Vec::new(),
+            loop_count: LoopCount::default(),
+        }
+    }
+
+    pub fn add_layer(&mut self, layer: AnimatedLayer<T>) -> &mut Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Renders the single frame at a time.
+    pub fn frame_at(&self, time: Duration) -> RgbaImage {
+        let background = self.background.to_rgba8();
+        let mut canvas = background.clone();
+        let poses: Vec<Pose> = self
+            .layers
+            .iter()
+            .map(|layer| layer.pose_at(time))
+            .collect();
+        let area = (0, 0, canvas.width(), canvas.height());
+        self.composite(
+            &mut canvas,
+            &background,
+            &[area],
+            &poses,
+            &self.stacking_order(),
+        );
+        canvas
+    }
+
+    /// Renders the frames at a frame rate as an animation, merging frames that do not change
+    /// into the delay of the one before.
+    pub fn render(&self, fps: u32) -> Result<Animation, ImageSlapperError> {
+        let mut animation = Animation::new(Vec::new(), self.loop_count);
+        let delay = Duration::from_secs(1) / fps.max(1);
+        self.render_frames(fps, |canvas, changed| {
+            match animation.frames.last_mut() {
+                Some(frame) if !changed => frame.delay += delay,
+                _ => animation.frames.push(AnimationFrame {
+                    image: DynamicImage::ImageRgba8(canvas.clone()),
+                    delay,
+                }),
+            }
+            Ok(())
+        })?;
+        Ok(animation)
+    }
+
+    /// Saves every frame at a frame rate as `frame_00000.png` and onwards in a directory and
+    /// returns the number of frames.
+    pub fn save_png_sequence(&self, fps: u32, directory: &str) -> Result<usize, ImageSlapperError> {
+        check_fps(fps)?;
+        let directory = Path::new(directory);
+        std::fs::create_dir_all(directory)
+            .map_err(|error| ImageSlapperError::io(directory, "create directory", error))?;
+        let mut count = 0;
+        self.render_frames(fps, |canvas, _| {
+            let path = directory.join(format!("frame_{count:05}.png"));
+            canvas
+                .save(&path)
+                .map_err(|source| ImageSlapperError::Encode { path, source })?;
+            count += 1;
+            Ok(())
+        })?;
+        Ok(count)
+    }
+
+    /// Writes every frame at a frame rate as raw YUV 4:4:4 video in the Y4M format, e.g. to
+    /// pipe into `ffmpeg -i -`. Transparent pixels come out black.
+    pub fn write_y4m(&self, fps: u32, writer: &mut impl Write) -> Result<(), ImageSlapperError> {
+        let path = Path::new("<writer>");
+        let failed = |error| ImageSlapperError::io(path, "write Y4M", error);
+        check_fps(fps)?;
+        let (width, height) = (self.background.width(), self.background.height());
+        writeln!(writer, "YUV4MPEG2 W{width} H{height} F{fps}:1 Ip A1:1 C444").map_err(failed)?;
+        let mut planes = vec![0u8; (width * height) as usize * 3];
+        self.render_frames(fps, |canvas, _| {
+            let (luma, chroma) = planes.split_at_mut((width * height) as usize);
+            let (blue, red) = chroma.split_at_mut((width * height) as usize);
+            for (index, pixel) in canvas.pixels().enumerate() {
+                let [r, g, b, a] = pixel.0.map(|channel| f32::from(channel) / 255.0);
+                let (r, g, b) = (r * a, g * a, b * a);
+                // BT.601 in limited range, what players assume for untagged Y4M
+                luma[index] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+                blue[index] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+                red[index] = (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+            }
+            writer.write_all(b"FRAME\n").map_err(failed)?;
+            writer.write_all(&planes).map_err(failed)
+        })
+    }
+
+    /// Renders every frame in turn into one canvas, telling the callback whether it changed
+    /// since the frame before.
+    fn render_frames(
+        &self,
+        fps: u32,
+        mut frame: impl FnMut(&RgbaImage, bool) -> Result<(), ImageSlapperError>,
+    ) -> Result<(), ImageSlapperError> {
+        check_fps(fps)?;
+        let count = (self.duration.as_secs_f64() * f64::from(fps))
+            .ceil()
+            .max(1.0) as u32;
+        let order = self.stacking_order();
+        let background = self.background.to_rgba8();
+        let mut canvas = background.clone();
+        let canvas_size = canvas.dimensions();
+        let mut previous: Option<Vec<Pose>> = None;
+
+        for index in 0..count {
+            let time = Duration::from_nanos(u64::from(index) * 1_000_000_000 / u64::from(fps));
+            let poses: Vec<Pose> = self
+                .layers
+                .iter()
+                .map(|layer| layer.pose_at(time))
+                .collect();
+            let dirty: Vec<Area> = match &previous {
+                None => vec![(0, 0, canvas_size.0, canvas_size.1)],
+                Some(previous) => {
+                    self.layers
+                        .iter()
+                        .zip(previous.iter().zip(&poses))
+                        .filter(|(_, (before, after))| before != after)
+                        .filter_map(|(layer, (before, after))| {
+                            let size = (layer.layer.content.width(), layer.layer.content.height());
+                            let areas = [
+                                before.area(size, canvas_size),
+                                after.area(size, canvas_size),
+                            ];
+                            areas.into_iter().flatten().reduce(|a, b| {
+                                (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))
+                            })
+                        })
+                        .collect()
+                }
+            };
+            debug!("Frame {index}: {} dirty areas", dirty.len());
+            self.composite(&mut canvas, &background, &dirty, &poses, &order);
+            frame(&canvas, !dirty.is_empty())?;
+            previous = Some(poses);
+        }
+        Ok(())
+    }
+
+    /// Layer indices from bottom to top.
+    fn stacking_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.layers.len()).collect();
+        order.sort_by_key(|&index| self.layers[index].layer.z_index);
+        order
+    }
+
+    /// Composites the background and every layer again inside the areas, row by row in parallel.
+    fn composite(
+        &self,
+        canvas: &mut RgbaImage,
+        background: &RgbaImage,
+        areas: &[Area],
+        poses: &[Pose],
+        order: &[usize],
+    ) {
+        let canvas_size = canvas.dimensions();
+        let row_length = canvas_size.0 as usize * 4;
+        let layers: Vec<(&Layer<T>, Pose, Area)> = order
+            .iter()
+            .filter_map(|&index| {
+                let layer = &self.layers[index].layer;
+                let size = (layer.content.width(), layer.content.height());
+                let pose = poses[index];
+                pose.area(size, canvas_size).map(|area| (layer, pose, area))
+            })
+            .collect();
+
+        for &(x1, y1, x2, y2) in areas {
+            canvas
+                .par_chunks_mut(row_length)
+                .enumerate()
+                .skip(y1 as usize)
+                .take((y2 - y1) as usize)
+                .for_each(|(y, row)| {
+                    let y = y as u32;
+                    for x in x1..x2 {
+                        let mut pixel = *background.get_pixel(x, y);
+                        for (layer, pose, area) in &layers {
+                            if x < area.0 || x >= area.2 || y < area.1 || y >= area.3 {
+                                continue;
+                            }
+                            let size = (layer.content.width(), layer.content.height());
+                            let Some((u, v)) = pose.content_pixel(x, y, size) else {
+                                continue;
+                            };
+                            let clip = match &layer.clip_mask {
+                                Some(mask) => mask.value_at(x, y).map_or(0.0, f32::from) / 255.0,
+                                None => 1.0,
+                            };
+                            let color = layer.content.pixel_at(u, v);
+                            blend_pixel_with(
+                                &mut pixel,
+                                color,
+                                pose.opacity * clip,
+                                layer.blend_mode,
+                            );
+                        }
+                        let index = x as usize * 4;
+                        row[index..index + 4].copy_from_slice(&pixel.0);
+                    }
+                });
+        }
+    }
+}
+
+/// Rejects a frame rate of zero before anything is written.
+fn check_fps(fps: u32) -> Result<(), ImageSlapperError> {
+    if fps == 0 {
+        return Err(ImageSlapperError::layout(
+            "timeline",
+            "needs a frame rate of at least one frame per second",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: f64) -> Duration {
+        Duration::from_secs_f64(seconds)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn linear_and_bezier_endpoints() {
+        for easing in [
+            Easing::Linear,
+            Easing::EASE,
+            Easing::EASE_IN,
+            Easing::EASE_OUT,
+            Easing::EASE_IN_OUT,
+        ] {
+            assert!(close(easing.apply(0.0), 0.0));
+            assert!(close(easing.apply(1.0), 1.0));
+        }
+        assert!(close(Easing::Linear.apply(0.3), 0.3));
+        assert!(close(
+            Easing::CubicBezier(0.0, 0.0, 1.0, 1.0).apply(0.3),
+            0.3
+        ));
+    }
+
+    #[test]
+    fn bezier_matches_css() {
+        assert!(close(Easing::EASE.apply(0.5), 0.8024));
+        assert!(close(Easing::EASE_IN_OUT.apply(0.5), 0.5));
+        assert!(Easing::EASE_IN.apply(0.5) < 0.5);
+        assert!(Easing::EASE_OUT.apply(0.5) > 0.5);
+    }
+
+    #[test]
+    fn steps_jump_at_the_start_or_end() {
+        let end = Easing::Steps(4, StepPosition::End);
+        let start = Easing::Steps(4, StepPosition::Start);
+        assert_eq!(end.apply(0.0), 0.0);
+        assert_eq!(end.apply(0.3), 0.25);
+        assert_eq!(end.apply(1.0), 1.0);
+        assert_eq!(start.apply(0.0), 0.25);
+        assert_eq!(start.apply(0.3), 0.5);
+        assert_eq!(start.apply(1.0), 1.0);
+    }
+
+    #[test]
+    fn progress_is_clamped() {
+        assert_eq!(Easing::Linear.apply(-1.0), 0.0);
+        assert_eq!(Easing::Linear.apply(2.0), 1.0);
+    }
+
+    #[test]
+    fn empty_track_has_no_value() {
+        assert_eq!(Track::<f64>::new(Vec::new()).value_at(seconds(1.0)), None);
+    }
+
+    #[test]
+    fn track_holds_its_ends() {
+        let track = Track::new(vec![
+            Keyframe::new(seconds(1.0), 2.0),
+            Keyframe::new(seconds(3.0), 6.0),
+        ]);
+        assert_eq!(track.value_at(seconds(0.0)), Some(2.0));
+        assert_eq!(track.value_at(seconds(1.0)), Some(2.0));
+        assert_eq!(track.value_at(seconds(3.0)), Some(6.0));
+        assert_eq!(track.value_at(seconds(9.0)), Some(6.0));
+    }
+
+    #[test]
+    fn track_interpolates_between_sorted_keyframes() {
+        let track = Track::new(vec![
+            Keyframe::new(seconds(2.0), (10.0, 0.0)),
+            Keyframe::new(seconds(0.0), (0.0, 4.0)),
+        ]);
+        assert_eq!(track.value_at(seconds(1.0)), Some((5.0, 2.0)));
+        assert_eq!(track.value_at(seconds(0.5)), Some((2.5, 3.0)));
+    }
+
+    #[test]
+    fn track_uses_the_easing_of_the_earlier_keyframe() {
+        let track = Track::new(vec![
+            Keyframe::new(seconds(0.0), 0.0).eased(Easing::EASE_IN),
+            Keyframe::new(seconds(1.0), 1.0),
+        ]);
+        let value = track.value_at(seconds(0.5)).unwrap();
+        assert!(close(value, Easing::EASE_IN.apply(0.5)));
+    }
+
+    #[test]
+    fn visibility_switches_at_the_keyframe() {
+        let track = Track::new(vec![
+            Keyframe::new(seconds(0.0), true),
+            Keyframe::new(seconds(1.0), false),
+        ]);
+        assert_eq!(track.value_at(seconds(0.999)), Some(true));
+        assert_eq!(track.value_at(seconds(1.0)), Some(false));
+    }
+
+    #[test]
+    fn zero_fps_y4m_writes_nothing() {
+        let background = DynamicImage::new_rgba8(4, 4);
+        let timeline = Timeline::<RgbaImage>::new(background, seconds(1.0));
+        let mut bytes = Vec::new();
+        assert!(timeline.write_y4m(0, &mut bytes).is_err());
+        assert!(bytes.is_empty());
+        timeline.write_y4m(2, &mut bytes).unwrap();
+        assert!(bytes.starts_with(b"YUV4MPEG2 W4 H4 F2:1 "));
+    }
+}
@@ -0,0 +1,178 @@
+use image::RgbaImage;
+use rgb::Rgba;
+
+use crate::primitive::layer::BlendMode;
+
+/// Blends `color` over `pixel` with the given coverage (0.0 to 1.0) using straight alpha.
+pub fn blend_pixel(pixel: &mut image::Rgba<u8>, color: Rgba<u8>, coverage: f32) {
+    let src_alpha = coverage.clamp(0.0, 1.0) * color.a as f32 / 255.0;
+    if src_alpha <= 0.0 {
+        return;
+    }
+
+    let bg = pixel.0;
+    let dst_alpha = bg[3] as f32 / 255.0;
+    let out_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+
+    let mix = |src: u8, dst: u8| {
+        ((src as f32 * src_alpha + dst as f32 * dst_alpha * (1.0 - src_alpha)) / out_alpha).round()
+            as u8
+    };
+
+    *pixel = image::Rgba([
+        mix(color.r, bg[0]),
+        mix(color.g, bg[1]),
+        mix(color.b, bg[2]),
+        (out_alpha * 255.0).round() as u8,
+    ]);
+}
+
+/// Blends `color` over `pixel` with a blend mode. The mode mixes the two colors where the
+/// pixel is opaque, and the result is composited like [`blend_pixel`].
+pub fn blend_pixel_with(
+    pixel: &mut image::Rgba<u8>,
+    color: Rgba<u8>,
+    coverage: f32,
+    mode: BlendMode,
+) {
+    if mode == BlendMode::Normal {
+        return blend_pixel(pixel, color, coverage);
+    }
+    let backdrop_alpha = pixel.0[3] as f32 / 255.0;
+    let mix = |source: u8, backdrop: u8| {
+        let (s, b) = (source as f32 / 255.0, backdrop as f32 / 255.0);
+        let blended = match mode {
+            BlendMode::Normal => s,
+            BlendMode::Multiply => b * s,
+            BlendMode::Screen => b + s - b * s,
+            BlendMode::Overlay if b <= 0.5 => 2.0 * b * s,
+            BlendMode::Overlay => 1.0 - 2.0 * (1.0 - b) * (1.0 - s),
+            BlendMode::Darken => b.min(s),
+            BlendMode::Lighten => b.max(s),
+        };
+        (((1.0 - backdrop_alpha) * s + backdrop_alpha * blended) * 255.0).round() as u8
+    };
+    let [r, g, b, _] = pixel.0;
+    let mixed = Rgba::new(mix(color.r, r), mix(color.g, g), mix(color.b, b), color.a);
+    blend_pixel(pixel, mixed, coverage);
+}
+
+/// Blends `color` into the pixel at (x, y), ignoring coordinates outside the image.
+pub fn blend_at(image: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>, coverage: f32) {
+    if x < 0 || y < 0 || x as u32 >= image.width() || y as u32 >= image.height() {
+        return;
+    }
+    blend_pixel(image.get_pixel_mut(x as u32, y as u32), color, coverage);
+}
+
+/// Fills the axis-aligned area between (x1, y1) and (x2, y2) with `color`, clipped to the image.
+pub fn fill_area(image: &mut RgbaImage, x1: f32, y1: f32, x2: f32, y2: f32, color: Rgba<u8>) {
+    let x_start = x1.round().max(0.0) as u32;
+    let y_start = y1.round().max(0.0) as u32;
+    let x_end = (x2.round().max(0.0) as u32).min(image.width());
+    let y_end = (y2.round().max(0.0) as u32).min(image.height());
+
+    for y in y_start..y_end {
+        for x in x_start..x_end {
+            blend_pixel(image.get_pixel_mut(x, y), color, 1.0);
+        }
+    }
+}
+
+/// Composites `source` over `target`, rotated clockwise by `degrees` so that `source_pivot`
+/// lands on `target_pivot`. Pixels are sampled bilinearly with premultiplied alpha.
+pub fn composite_rotated(
+    target: &mut RgbaImage,
+    source: &RgbaImage,
+    source_pivot: (f32, f32),
+    target_pivot: (f32, f32),
+    degrees: f32,
+) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (source_width, source_height) = (source.width() as f32, source.height() as f32);
+
+    // Bounds of the rotated source on the target
+    let corners = [
+        (0.0, 0.0),
+        (source_width, 0.0),
+        (0.0, source_height),
+        (source_width, source_height),
+    ]
+    .map(|(x, y)| {
+        let (dx, dy) = (x - source_pivot.0, y - source_pivot.1);
+        (
+            target_pivot.0 + dx * cos - dy * sin,
+            target_pivot.1 + dx * sin + dy * cos,
+        )
+    });
+    let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min);
+    let max_x = corners
+        .iter()
+        .map(|c| c.0)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min);
+    let max_y = corners
+        .iter()
+        .map(|c| c.1)
+        .fold(f32::NEG_INFINITY, f32::max);
+
+    let x_start = min_x.floor().max(0.0) as u32;
+    let y_start = min_y.floor().max(0.0) as u32;
+    let x_end = (max_x.ceil().max(0.0) as u32).min(target.width());
+    let y_end = (max_y.ceil().max(0.0) as u32).min(target.height());
+
+    for y in y_start..y_end {
+        for x in x_start..x_end {
+            let (dx, dy) = (
+                x as f32 + 0.5 - target_pivot.0,
+                y as f32 + 0.5 - target_pivot.1,
+            );
+            let sx = dx * cos + dy * sin + source_pivot.0 - 0.5;
+            let sy = -dx * sin + dy * cos + source_pivot.1 - 0.5;
+
+            let [r, g, b, a] = sample_premultiplied(source, sx, sy);
+            if a > 0.0 {
+                let color = Rgba::new(
+                    (r / a).round().min(255.0) as u8,
+                    (g / a).round().min(255.0) as u8,
+                    (b / a).round().min(255.0) as u8,
+                    255,
+                );
+                blend_pixel(target.get_pixel_mut(x, y), color, a / 255.0);
+            }
+        }
+    }
+}
+
+/// Samples the image bilinearly at a fractional position, treating outside pixels as transparent.
+/// Returns red, green and blue premultiplied by the returned alpha (0 to 255).
+fn sample_premultiplied(image: &RgbaImage, x: f32, y: f32) -> [f32; 4] {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let mut result = [0.0; 4];
+
+    for (ox, oy, weight) in [
+        (0, 0, (1.0 - fx) * (1.0 - fy)),
+        (1, 0, fx * (1.0 - fy)),
+        (0, 1, (1.0 - fx) * fy),
+        (1, 1, fx * fy),
+    ] {
+        let (px, py) = (x0 as i64 + ox, y0 as i64 + oy);
+        if weight <= 0.0
+            || px < 0
+            || py < 0
+            || px >= image.width() as i64
+            || py >= image.height() as i64
+        {
+            continue;
+        }
+        let pixel = image.get_pixel(px as u32, py as u32).0;
+        let alpha = pixel[3] as f32 * weight;
+        result[0] += pixel[0] as f32 * alpha;
+        result[1] += pixel[1] as f32 * alpha;
+        result[2] += pixel[2] as f32 * alpha;
+        result[3] += alpha;
+    }
+
+    result
+}
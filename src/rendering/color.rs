@@ -0,0 +1,216 @@
+use image::{DynamicImage, ImageBuffer};
+use moxcms::{ColorProfile, DataColorSpace, Layout, ToneReprCurve, TransformOptions};
+use rgb::Rgba;
+
+use crate::error::ImageSlapperError;
+
+/// RGB color spaces that colors and images can be described in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// The web default, assumed for anything untagged.
+    #[default]
+    Srgb,
+    /// sRGB primaries with linear light values, for blending and resampling math.
+    LinearSrgb,
+    /// The wide gamut of recent phones and displays.
+    DisplayP3,
+    /// A wide gamut common in photography and print.
+    AdobeRgb,
+}
+
+/// The space pixels are held in between loading and saving.
+///
+/// sRGB images are stored with 8 or 16 bits per channel. Linear images are stored as 32-bit
+/// floats so dark tones keep their precision. Drawing happens in 8-bit sRGB, so only the pixels
+/// a drawable changes are quantized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WorkingSpace {
+    #[default]
+    Srgb,
+    LinearSrgb,
+}
+
+impl WorkingSpace {
+    pub fn color_space(&self) -> ColorSpace {
+        match self {
+            WorkingSpace::Srgb => ColorSpace::Srgb,
+            WorkingSpace::LinearSrgb => ColorSpace::LinearSrgb,
+        }
+    }
+}
+
+impl ColorSpace {
+    pub fn profile(&self) -> ColorProfile {
+        match self {
+            ColorSpace::Srgb => ColorProfile::new_srgb(),
+            ColorSpace::LinearSrgb => {
+                let mut profile = ColorProfile::new_srgb();
+                let linear = ToneReprCurve::Parametric(vec![1.0]);
+                profile.red_trc = Some(linear.clone());
+                profile.green_trc = Some(linear.clone());
+                profile.blue_trc = Some(linear);
+                // The CICP tag would take precedence with its sRGB transfer curve
+                profile.cicp = None;
+                profile.description = None;
+                profile
+            }
+            ColorSpace::DisplayP3 => ColorProfile::new_display_p3(),
+            ColorSpace::AdobeRgb => ColorProfile::new_adobe_rgb(),
+        }
+    }
+
+    /// Returns the ICC profile to embed in files saved in this space.
+    pub fn icc_profile(&self) -> Result<Vec<u8>, ImageSlapperError> {
+        self.profile()
+            .encode()
+            .map_err(|error| ImageSlapperError::color(format!("{self:?}"), error.to_string()))
+    }
+
+    /// Converts a color in this space to sRGB for drawing, clipping colors outside its gamut.
+    pub fn to_srgb(&self, color: Rgba<u8>) -> Rgba<u8> {
+        if *self == ColorSpace::Srgb {
+            return color;
+        }
+        let pixel =
+            image::RgbaImage::from_pixel(1, 1, image::Rgba([color.r, color.g, color.b, color.a]));
+        match convert(&DynamicImage::ImageRgba8(pixel), *self, ColorSpace::Srgb) {
+            Ok(converted) => {
+                let [r, g, b, a] = converted.to_rgba8().get_pixel(0, 0).0;
+                Rgba::new(r, g, b, a)
+            }
+            Err(_) => color,
+        }
+    }
+}
+
+/// Converts an image between two color spaces.
+///
+/// Linear output is stored as 32-bit floats, other output keeps 8-bit images at 8 bits and
+/// stores deeper ones at 16 bits.
+pub fn convert(
+    image: &DynamicImage,
+    from: ColorSpace,
+    to: ColorSpace,
+) -> Result<DynamicImage, ImageSlapperError> {
+    if from == to {
+        return Ok(image.clone());
+    }
+    transform(image, &from.profile(), to, &format!("{from:?}"))
+}
+
+/// Converts an image described by an embedded ICC profile into the working space.
+pub fn to_working_space(
+    image: &DynamicImage,
+    icc_profile: &[u8],
+    working: WorkingSpace,
+) -> Result<DynamicImage, ImageSlapperError> {
+    let profile = ColorProfile::new_from_slice(icc_profile)
+        .map_err(|error| ImageSlapperError::color("embedded", error.to_string()))?;
+    transform(image, &profile, working.color_space(), "embedded")
+}
+
+/// Whether an ICC profile describes CMYK, read from its header without parsing the rest.
+pub(crate) fn is_cmyk_profile(icc_profile: &[u8]) -> bool {
+    icc_profile.get(16..20) == Some(b"CMYK")
+}
+
+/// Converts CMYK pixels as stored in Adobe JPEGs, with inverted ink values, into the working
+/// space through their ICC profile.
+pub(crate) fn cmyk_to_working_space(
+    cmyk: &[u8],
+    (width, height): (u32, u32),
+    icc_profile: &[u8],
+    working: WorkingSpace,
+) -> Result<DynamicImage, ImageSlapperError> {
+    let failed = |error: moxcms::CmsError| ImageSlapperError::color("embedded", error.to_string());
+    let profile = ColorProfile::new_from_slice(icc_profile).map_err(failed)?;
+    if profile.color_space != DataColorSpace::Cmyk {
+        return Err(ImageSlapperError::color(
+            "embedded",
+            format!("expected a CMYK profile, found {:?}", profile.color_space),
+        ));
+    }
+    let ink: Vec<f32> = cmyk
+        .iter()
+        .map(|&value| f32::from(255 - value) / 255.0)
+        .collect();
+    let target = working.color_space().profile();
+    let transform = profile
+        .create_transform_f32(
+            Layout::Rgba,
+            &target,
+            Layout::Rgb,
+            TransformOptions::default(),
+        )
+        .map_err(failed)?;
+    let mut rgb = vec![0.0; ink.len() / 4 * 3];
+    transform.transform(&ink, &mut rgb).map_err(failed)?;
+    let image = image::Rgb32FImage::from_raw(width, height, rgb)
+        .map(DynamicImage::ImageRgb32F)
+        .ok_or_else(|| ImageSlapperError::color("embedded", "CMYK data is truncated"))?;
+    Ok(match working {
+        WorkingSpace::Srgb => DynamicImage::ImageRgb8(image.to_rgb8()),
+        WorkingSpace::LinearSrgb => image,
+    })
+}
+
+/// Runs a color transform over every pixel, keeping the alpha channel.
+fn transform(
+    image: &DynamicImage,
+    source: &ColorProfile,
+    to: ColorSpace,
+    name: &str,
+) -> Result<DynamicImage, ImageSlapperError> {
+    let failed = |error: moxcms::CmsError| ImageSlapperError::color(name, error.to_string());
+    let (width, height) = (image.width(), image.height());
+    let alpha = image.color().has_alpha();
+    let (pixels, source_layout) = match (source.color_space, alpha) {
+        (DataColorSpace::Gray, false) => (image.to_luma32f().into_raw(), Layout::Gray),
+        (DataColorSpace::Gray, true) => (image.to_luma_alpha32f().into_raw(), Layout::GrayAlpha),
+        (DataColorSpace::Rgb, false) => (image.to_rgb32f().into_raw(), Layout::Rgb),
+        (DataColorSpace::Rgb, true) => (image.to_rgba32f().into_raw(), Layout::Rgba),
+        (color_space, _) => {
+            return Err(ImageSlapperError::color(
+                name,
+                format!("cannot convert {color_space:?} pixels"),
+            ));
+        }
+    };
+    let target_layout = if alpha { Layout::Rgba } else { Layout::Rgb };
+    let transform = source
+        .create_transform_f32(
+            source_layout,
+            &to.profile(),
+            target_layout,
+            TransformOptions::default(),
+        )
+        .map_err(failed)?;
+    let channels = if alpha { 4 } else { 3 };
+    let mut output = vec![0.0; (width * height) as usize * channels];
+    transform.transform(&pixels, &mut output).map_err(failed)?;
+
+    let truncated = || ImageSlapperError::color(name, "pixel data is truncated");
+    let converted = if alpha {
+        ImageBuffer::from_raw(width, height, output)
+            .map(DynamicImage::ImageRgba32F)
+            .ok_or_else(truncated)?
+    } else {
+        ImageBuffer::from_raw(width, height, output)
+            .map(DynamicImage::ImageRgb32F)
+            .ok_or_else(truncated)?
+    };
+    let eight_bit = matches!(
+        image,
+        DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_)
+    );
+    Ok(match (to, eight_bit, alpha) {
+        (ColorSpace::LinearSrgb, _, _) => converted,
+        (_, true, true) => DynamicImage::ImageRgba8(converted.to_rgba8()),
+        (_, true, false) => DynamicImage::ImageRgb8(converted.to_rgb8()),
+        (_, false, true) => DynamicImage::ImageRgba16(converted.to_rgba16()),
+        (_, false, false) => DynamicImage::ImageRgb16(converted.to_rgb16()),
+    })
+}
@@ -1,27 +1,128 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
-use image::DynamicImage;
+use image::{DynamicImage, ImageFormat};
+
+use crate::error::ImageSlapperError;
+use crate::io::encode::{self, EncodeOptions};
+use crate::io::metadata::{ImageMetadata, MetadataOptions};
+use crate::rendering::color::{self, ColorSpace, WorkingSpace};
 
 /// A trait that defines a drawable object.
 pub trait Drawable {
-    fn draw(&self, image: &mut DynamicImage);
+    /// Draws onto the image, leaving it unchanged on error.
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError>;
 }
 
 /// A struct that wraps around an image and provides drawing functionality.
 pub struct ImageWrapper {
     image: DynamicImage,
+    /// Metadata of the source image, written back on save as `keep` allows.
+    metadata: ImageMetadata,
+    keep: MetadataOptions,
+    /// The space the pixels are in.
+    working_space: WorkingSpace,
+    /// The space the pixels are converted to when saving.
+    output_space: ColorSpace,
+    /// The file the image came from, named in errors.
+    source: Option<PathBuf>,
 }
 
 /// A wrapper around the image to provide drawing functionality.
 impl ImageWrapper {
     /// Creates a new ImageWrapper with the given image.
     pub fn new(image: DynamicImage) -> Self {
-        ImageWrapper { image }
+        ImageWrapper {
+            image,
+            metadata: ImageMetadata::default(),
+            keep: MetadataOptions::default(),
+            working_space: WorkingSpace::default(),
+            output_space: ColorSpace::default(),
+            source: None,
+        }
+    }
+
+    /// Names the file the image came from in font, barcode, color and layout errors, so a
+    /// failing record in a batch can be traced back to it.
+    pub fn with_source(mut self, path: impl Into<PathBuf>) -> Self {
+        self.source = Some(path.into());
+        self
+    }
+
+    /// Sets the space the pixels are in and the one to save them in. Saving in any space but
+    /// sRGB embeds its ICC profile, even when profiles are otherwise stripped.
+    pub fn with_color_spaces(mut self, working: WorkingSpace, output: ColorSpace) -> Self {
+        self.working_space = working;
+        self.output_space = output;
+        self
+    }
+
+    /// Sets the metadata to save along with the image and which of it to keep.
+    pub fn with_metadata(mut self, metadata: ImageMetadata, keep: MetadataOptions) -> Self {
+        self.metadata = metadata;
+        self.keep = keep;
+        self
+    }
+
+    /// Returns the metadata kept for saving.
+    pub fn metadata(&self) -> ImageMetadata {
+        self.metadata.filtered(&self.keep)
     }
 
     /// Draws the given drawable on the image.
-    pub fn draw<T: Drawable>(&mut self, drawable: &T) {
-        drawable.draw(&mut self.image);
+    ///
+    /// Drawables work on 8-bit sRGB pixels, so a linear image is drawn on as an sRGB copy and
+    /// only the pixels the drawable changed are written back, leaving the rest at full precision.
+    pub fn draw<T: Drawable>(&mut self, drawable: &T) -> Result<(), ImageSlapperError> {
+        self.draw_in_working_space(drawable)
+            .map_err(|error| self.traced(error))
+    }
+
+    fn draw_in_working_space<T: Drawable>(
+        &mut self,
+        drawable: &T,
+    ) -> Result<(), ImageSlapperError> {
+        match self.working_space {
+            WorkingSpace::Srgb => drawable.draw(&mut self.image),
+            WorkingSpace::LinearSrgb => {
+                let before = color::convert(&self.image, ColorSpace::LinearSrgb, ColorSpace::Srgb)?
+                    .to_rgba8();
+                let mut drawn = DynamicImage::ImageRgba8(before.clone());
+                drawable.draw(&mut drawn)?;
+                let drawn = drawn.to_rgba8();
+
+                let changed: Vec<(u32, u32)> = before
+                    .enumerate_pixels()
+                    .filter(|(x, y, pixel)| drawn.get_pixel(*x, *y) != *pixel)
+                    .map(|(x, y, _)| (x, y))
+                    .collect();
+                let Some(&(first_x, first_y)) = changed.first() else {
+                    return Ok(());
+                };
+                // Convert back only the area around the changed pixels
+                let (mut x1, mut y1, mut x2, mut y2) = (first_x, first_y, first_x, first_y);
+                for &(x, y) in &changed {
+                    x1 = x1.min(x);
+                    y1 = y1.min(y);
+                    x2 = x2.max(x);
+                    y2 = y2.max(y);
+                }
+                let area = image::imageops::crop_imm(&drawn, x1, y1, x2 - x1 + 1, y2 - y1 + 1);
+                let linear = color::convert(
+                    &DynamicImage::ImageRgba8(area.to_image()),
+                    ColorSpace::Srgb,
+                    ColorSpace::LinearSrgb,
+                )?
+                .to_rgba32f();
+                let mut image = self.image.to_rgba32f();
+                for (x, y) in changed {
+                    image.put_pixel(x, y, *linear.get_pixel(x - x1, y - y1));
+                }
+                self.image = DynamicImage::ImageRgba32F(image);
+                Ok(())
+            }
+        }
     }
 
     /// Returns a reference to the image.
@@ -29,10 +130,81 @@ impl ImageWrapper {
         &self.image
     }
 
-    /// Saves the image to the specified output path and returns the elapsed time.
-    pub fn save_image(&self, output_path: &str) -> Duration {
+    /// Encodes the image into a writer with explicit format settings, e.g. straight into an
+    /// HTTP response.
+    pub fn encode(
+        &self,
+        options: &EncodeOptions,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), ImageSlapperError> {
+        let (image, metadata) = self.output().map_err(|error| self.traced(error))?;
+        encode::encode_with_metadata(&image, options, &metadata, writer)
+    }
+
+    /// Encodes the image into a new buffer with explicit format settings.
+    pub fn encode_to_vec(&self, options: &EncodeOptions) -> Result<Vec<u8>, ImageSlapperError> {
+        let mut bytes = Vec::new();
+        self.encode(options, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Saves the image to the specified output path and returns the elapsed time. The format
+    /// comes from the extension, with the kept metadata embedded where the format allows.
+    pub fn save_image(&self, output_path: &str) -> Result<Duration, ImageSlapperError> {
         let start = Instant::now();
-        self.image.save(output_path).expect("Failed to save image");
-        start.elapsed()
+        if let Some(options) = ImageFormat::from_path(output_path)
+            .ok()
+            .and_then(EncodeOptions::for_format)
+        {
+            let path = Path::new(output_path);
+            let file = std::fs::File::create(path)
+                .map_err(|error| ImageSlapperError::io(path, "create image", error))?;
+            let mut writer = std::io::BufWriter::new(file);
+            self.encode(&options, &mut writer)?;
+            std::io::Write::flush(&mut writer)
+                .map_err(|error| ImageSlapperError::io(path, "write image", error))?;
+            return Ok(start.elapsed());
+        }
+        self.output()
+            .map_err(|error| self.traced(error))?
+            .0
+            .save(output_path)
+            .map_err(|source| ImageSlapperError::Encode {
+                path: output_path.into(),
+                source,
+            })?;
+        Ok(start.elapsed())
+    }
+
+    /// Adds the source file to an error, if known.
+    fn traced(&self, error: ImageSlapperError) -> ImageSlapperError {
+        match &self.source {
+            Some(path) => error.with_path(path),
+            None => error,
+        }
+    }
+
+    /// Whether the pixels are converted to another space when saving.
+    fn converts_on_save(&self) -> bool {
+        self.working_space.color_space() != self.output_space
+    }
+
+    /// Returns the pixels and metadata to save, converted to the output space.
+    fn output(&self) -> Result<(Cow<'_, DynamicImage>, ImageMetadata), ImageSlapperError> {
+        let mut metadata = self.metadata();
+        // The source profile no longer describes converted pixels. Untagged pixels are taken
+        // as sRGB, anything else needs its profile to be shown right
+        if self.converts_on_save() {
+            metadata.icc_profile = None;
+        }
+        if self.output_space != ColorSpace::Srgb {
+            metadata.icc_profile = Some(self.output_space.icc_profile()?);
+        }
+        if !self.converts_on_save() {
+            return Ok((Cow::Borrowed(&self.image), metadata));
+        }
+        let from = self.working_space.color_space();
+        let image = color::convert(&self.image, from, self.output_space)?;
+        Ok((Cow::Owned(image), metadata))
     }
 }
@@ -1 +1,4 @@
+pub mod blend;
+pub mod color;
 pub mod draw;
+pub mod strip;
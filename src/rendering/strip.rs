@@ -0,0 +1,650 @@
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use image::error::{EncodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageFormat, RgbaImage};
+use rgb::Rgba;
+use tracing::debug;
+
+use crate::error::ImageSlapperError;
+use crate::io::tiled::{ImageRows, RowSource, open_rows};
+use crate::rendering::blend::blend_pixel;
+use crate::rendering::draw::Drawable;
+
+/// Memory a canvas may use for its strips by default, 256 MiB.
+pub const DEFAULT_MEMORY_BUDGET: u64 = 256 * 1024 * 1024;
+
+/// Something that can draw itself one horizontal strip of the canvas at a time.
+pub trait StripDrawable {
+    /// The canvas rows it draws on, from the first to one past the last.
+    fn rows(&self) -> (u32, u32);
+
+    /// Bytes it holds while the strips pass over it, counted against the memory budget.
+    fn resident_bytes(&self) -> u64 {
+        0
+    }
+
+    /// Draws onto a full-width strip whose first row is row `top` of the canvas. Strips come
+    /// top to bottom.
+    fn draw_strip(&mut self, strip: &mut RgbaImage, top: u32) -> Result<(), ImageSlapperError>;
+}
+
+/// Any drawable confined to an area of the canvas, drawn in coordinates relative to the area.
+///
+/// The area is rendered when the first strip reaches it and freed after the last, so text
+/// and barcodes work on canvases far too large to hold.
+pub struct Patch<D: Drawable> {
+    drawable: D,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    rendered: Option<RgbaImage>,
+}
+
+impl<D: Drawable> Patch<D> {
+    pub fn new(drawable: D, x: u32, y: u32, width: u32, height: u32) -> Self {
+        Patch {
+            drawable,
+            x,
+            y,
+            width,
+            height,
+            rendered: None,
+        }
+    }
+
+    /// One past the last row of the area, which may reach past the end of any canvas.
+    fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+}
+
+impl<D: Drawable> StripDrawable for Patch<D> {
+    fn rows(&self) -> (u32, u32) {
+        (self.y, self.bottom())
+    }
+
+    fn resident_bytes(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height) * 4
+    }
+
+    fn draw_strip(&mut self, strip: &mut RgbaImage, top: u32) -> Result<(), ImageSlapperError> {
+        let bottom = top + strip.height();
+        if bottom <= self.y || top >= self.bottom() {
+            return Ok(());
+        }
+        if self.rendered.is_none() {
+            let mut image = DynamicImage::new_rgba8(self.width, self.height);
+            self.drawable.draw(&mut image)?;
+            self.rendered = Some(image.to_rgba8());
+        }
+        if let Some(rendered) = &self.rendered {
+            for y in top.max(self.y)..bottom.min(self.bottom()) {
+                for x in 0..self.width.min(strip.width().saturating_sub(self.x)) {
+                    let [r, g, b, a] = rendered.get_pixel(x, y - self.y).0;
+                    blend_pixel(
+                        strip.get_pixel_mut(self.x + x, y - top),
+                        Rgba::new(r, g, b, a),
+                        1.0,
+                    );
+                }
+            }
+        }
+        if bottom >= self.bottom() {
+            self.rendered = None;
+        }
+        Ok(())
+    }
+}
+
+/// Where a placed image reads its pixels from.
+enum Origin {
+    Path(PathBuf),
+    Image(Arc<RgbaImage>),
+}
+
+/// An image scaled into an area of the canvas, decoded lazily row by row as the strips pass.
+///
+/// Pixels are sampled bilinearly, which suits photos placed near their size or enlarged.
+pub struct PlacedImage {
+    origin: Origin,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    source_size: (u32, u32),
+    source_bytes: u64,
+    /// The open source while strips pass over the image.
+    reading: Option<Reading>,
+}
+
+/// A source being read, with up to two of its rows and their indices.
+struct Reading {
+    source: Box<dyn RowSource + Send>,
+    held: Vec<(u32, Vec<u8>)>,
+}
+
+impl PlacedImage {
+    /// Places an image file. PNG and TIFF files are read in rows or tiles as the strips reach
+    /// them, other formats are decoded whole.
+    pub fn open(
+        image_path: &str,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, ImageSlapperError> {
+        let source = open_rows(image_path)?;
+        Ok(PlacedImage {
+            origin: Origin::Path(image_path.into()),
+            x,
+            y,
+            width,
+            height,
+            source_size: source.dimensions(),
+            source_bytes: source.resident_bytes(),
+            reading: Some(Reading {
+                source,
+                held: Vec::new(),
+            }),
+        })
+    }
+
+    /// Places an image already in memory.
+    pub fn from_image(image: &DynamicImage, x: u32, y: u32, width: u32, height: u32) -> Self {
+        let image = Arc::new(image.to_rgba8());
+        PlacedImage {
+            source_size: image.dimensions(),
+            source_bytes: image.as_raw().len() as u64,
+            origin: Origin::Image(image),
+            x,
+            y,
+            width,
+            height,
+            reading: None,
+        }
+    }
+
+    /// One past the last row of the area, which may reach past the end of any canvas.
+    fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+
+    /// Source row `y` as RGBA, read from the source unless it is held already.
+    fn source_row(&mut self, y: u32) -> Result<&[u8], ImageSlapperError> {
+        if self.reading.is_none() {
+            let source: Box<dyn RowSource + Send> = match &self.origin {
+                Origin::Path(path) => open_rows(&path.to_string_lossy())?,
+                Origin::Image(image) => Box::new(ImageRows::from(Arc::clone(image))),
+            };
+            self.reading = Some(Reading {
+                source,
+                held: Vec::new(),
+            });
+        }
+        let Some(Reading { source, held }) = &mut self.reading else {
+            return Ok(&[]);
+        };
+        // Rows only move down, so anything above the pair being sampled is done with
+        held.retain(|(index, _)| *index + 1 >= y);
+        if !held.iter().any(|(index, _)| *index == y) {
+            let mut row = vec![0u8; self.source_size.0 as usize * 4];
+            source.read_row(y, &mut row)?;
+            held.push((y, row));
+        }
+        Ok(held
+            .iter()
+            .find(|(index, _)| *index == y)
+            .map_or(&[][..], |(_, row)| row.as_slice()))
+    }
+}
+
+impl StripDrawable for PlacedImage {
+    fn rows(&self) -> (u32, u32) {
+        (self.y, self.bottom())
+    }
+
+    fn resident_bytes(&self) -> u64 {
+        self.source_bytes + u64::from(self.source_size.0) * 4 * 2
+    }
+
+    fn draw_strip(&mut self, strip: &mut RgbaImage, top: u32) -> Result<(), ImageSlapperError> {
+        let bottom = top + strip.height();
+        let (source_width, source_height) = self.source_size;
+        if bottom <= self.y || top >= self.bottom() || source_width == 0 || source_height == 0 {
+            return Ok(());
+        }
+        // Source position of a target pixel center, as the two neighbours and the weight
+        let sample = |target: u32, target_size: u32, source_size: u32| {
+            let position =
+                ((f64::from(target) + 0.5) * f64::from(source_size) / f64::from(target_size) - 0.5)
+                    .clamp(0.0, f64::from(source_size - 1));
+            let first = position.floor() as u32;
+            (
+                first,
+                (first + 1).min(source_size - 1),
+                (position - position.floor()) as f32,
+            )
+        };
+        let columns: Vec<(u32, u32, f32)> =
+            (0..self.width.min(strip.width().saturating_sub(self.x)))
+                .map(|x| sample(x, self.width, source_width))
+                .collect();
+
+        let left_edge = self.x;
+        for y in top.max(self.y)..bottom.min(self.bottom()) {
+            let (first, second, weight) = sample(y - self.y, self.height, source_height);
+            let upper = self.source_row(first)?.to_vec();
+            let lower = self.source_row(second)?;
+            for (x, &(left, right, horizontal)) in columns.iter().enumerate() {
+                // Premultiplied so transparent pixels do not bleed their color
+                let mut color = [0.0f32; 4];
+                for (row, row_weight) in [(&upper[..], 1.0 - weight), (lower, weight)] {
+                    for (column, column_weight) in [(left, 1.0 - horizontal), (right, horizontal)] {
+                        let index = column as usize * 4;
+                        let [r, g, b, a] =
+                            [0, 1, 2, 3].map(|channel| f32::from(row[index + channel]));
+                        let factor = row_weight * column_weight * a / 255.0;
+                        color[0] += r * factor;
+                        color[1] += g * factor;
+                        color[2] += b * factor;
+                        color[3] += a * row_weight * column_weight;
+                    }
+                }
+                if color[3] <= 0.0 {
+                    continue;
+                }
+                let alpha = color[3] / 255.0;
+                let channel = |value: f32| (value / alpha).round().clamp(0.0, 255.0) as u8;
+                blend_pixel(
+                    strip.get_pixel_mut(left_edge + x as u32, y - top),
+                    Rgba::new(
+                        channel(color[0]),
+                        channel(color[1]),
+                        channel(color[2]),
+                        color[3].round() as u8,
+                    ),
+                    1.0,
+                );
+            }
+        }
+        if bottom >= self.bottom() {
+            self.reading = None;
+        }
+        Ok(())
+    }
+}
+
+/// A canvas rendered in horizontal strips and streamed to the encoder, for images too large
+/// to hold in memory, e.g. a 2 m wide banner at 300 dpi.
+///
+/// Peak memory stays within the budget: the strip height is what is left after what every
+/// drawable holds while the strips pass over it.
+pub struct StripCanvas<'a> {
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+    dpi: Option<f64>,
+    memory_budget: u64,
+    drawables: Vec<Box<dyn StripDrawable + 'a>>,
+}
+
+impl<'a> StripCanvas<'a> {
+    /// Creates a white canvas.
+    pub fn new(width: u32, height: u32) -> Self {
+        StripCanvas {
+            width,
+            height,
+            background: Rgba::new(255, 255, 255, 255),
+            dpi: None,
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            drawables: Vec::new(),
+        }
+    }
+
+    /// Creates a white canvas of a printed size in millimetres at a resolution.
+    pub fn for_print(width_mm: f64, height_mm: f64, dpi: f64) -> Self {
+        let pixels = |mm: f64| (mm / 25.4 * dpi).round() as u32;
+        StripCanvas::new(pixels(width_mm), pixels(height_mm)).with_dpi(dpi)
+    }
+
+    pub fn with_background(mut self, background: Rgba<u8>) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Sets the resolution written to the file, so print software picks the right size.
+    pub fn with_dpi(mut self, dpi: f64) -> Self {
+        self.dpi = Some(dpi);
+        self
+    }
+
+    /// Sets the most memory in bytes the strips and drawables may use together.
+    pub fn with_memory_budget(mut self, bytes: u64) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Adds a drawable on top of the ones before.
+    pub fn add(&mut self, drawable: impl StripDrawable + 'a) -> &mut Self {
+        self.drawables.push(Box::new(drawable));
+        self
+    }
+
+    /// The number of rows rendered at once within the memory budget.
+    pub fn strip_height(&self) -> Result<u32, ImageSlapperError> {
+        let held: u64 = self
+            .drawables
+            .iter()
+            .map(|drawable| drawable.resident_bytes())
+            .sum();
+        // The strip itself and the encoder's copy of it
+        let row_bytes = u64::from(self.width) * 4 * 2;
+        let rows = self.memory_budget.saturating_sub(held) / row_bytes.max(1);
+        if rows == 0 {
+            return Err(ImageSlapperError::layout(
+                "strip canvas",
+                format!(
+                    "a memory budget of {} bytes leaves no room for a row of {} bytes after {held} bytes held by drawables",
+                    self.memory_budget, row_bytes
+                ),
+            ));
+        }
+        Ok(rows.min(u64::from(self.height)).max(1) as u32)
+    }
+
+    /// Renders the canvas strip by strip from the top, handing each strip and the canvas row
+    /// it starts at to `strip`.
+    pub fn render_strips(
+        &mut self,
+        mut strip: impl FnMut(u32, &RgbaImage) -> Result<(), ImageSlapperError>,
+    ) -> Result<(), ImageSlapperError> {
+        let strip_height = self.strip_height()?;
+        debug!(
+            "Rendering {}x{} canvas in strips of {strip_height} rows",
+            self.width, self.height
+        );
+        let background = image::Rgba([
+            self.background.r,
+            self.background.g,
+            self.background.b,
+            self.background.a,
+        ]);
+        let mut top = 0;
+        while top < self.height {
+            let rows = strip_height.min(self.height - top);
+            let mut pixels = RgbaImage::from_pixel(self.width, rows, background);
+            for drawable in &mut self.drawables {
+                let (first, last) = drawable.rows();
+                if first < top + rows && last > top {
+                    drawable.draw_strip(&mut pixels, top)?;
+                }
+            }
+            strip(top, &pixels)?;
+            top += rows;
+        }
+        Ok(())
+    }
+
+    /// Streams the canvas as a PNG. Opaque backgrounds are written without alpha.
+    pub fn write_png(&mut self, writer: impl Write) -> Result<(), ImageSlapperError> {
+        let failed = |error: png::EncodingError| encoding(ImageFormat::Png, error);
+        let alpha = self.background.a < 255;
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(if alpha {
+            png::ColorType::Rgba
+        } else {
+            png::ColorType::Rgb
+        });
+        encoder.set_depth(png::BitDepth::Eight);
+        if let Some(dpi) = self.dpi {
+            let per_metre = (dpi / 0.0254).round() as u32;
+            encoder.set_pixel_dims(Some(png::PixelDimensions {
+                xppu: per_metre,
+                yppu: per_metre,
+                unit: png::Unit::Meter,
+            }));
+        }
+        let mut png = encoder.write_header().map_err(failed)?;
+        let mut stream = png.stream_writer().map_err(failed)?;
+        self.render_strips(|_, strip| {
+            let written = if alpha {
+                stream.write_all(strip.as_raw())
+            } else {
+                stream.write_all(&without_alpha(strip))
+            };
+            written.map_err(|error| encoding(ImageFormat::Png, error))
+        })?;
+        stream.finish().map_err(failed)?;
+        png.finish().map_err(failed)
+    }
+
+    /// Streams the canvas as an uncompressed TIFF, or BigTIFF past 4 GiB. Opaque backgrounds
+    /// are written without alpha.
+    pub fn write_tiff(&mut self, writer: impl Write + Seek) -> Result<(), ImageSlapperError> {
+        let channels = if self.background.a < 255 { 4 } else { 3 };
+        let bytes = u64::from(self.width) * u64::from(self.height) * channels;
+        // Leave room for the directory and strip offsets
+        if bytes < u64::from(u32::MAX) - (64 << 20) {
+            let encoder = tiff::encoder::TiffEncoder::new(writer)
+                .map_err(|error| encoding(ImageFormat::Tiff, error))?;
+            self.write_tiff_with(encoder)
+        } else {
+            let encoder = tiff::encoder::TiffEncoder::new_big(writer)
+                .map_err(|error| encoding(ImageFormat::Tiff, error))?;
+            self.write_tiff_with(encoder)
+        }
+    }
+
+    fn write_tiff_with<W: Write + Seek, K: tiff::encoder::TiffKind>(
+        &mut self,
+        mut encoder: tiff::encoder::TiffEncoder<W, K>,
+    ) -> Result<(), ImageSlapperError> {
+        use tiff::encoder::colortype::{RGB8, RGBA8};
+
+        let failed = |error: tiff::TiffError| encoding(ImageFormat::Tiff, error);
+        let strip_height = self.strip_height()?;
+        let (width, height, dpi) = (self.width, self.height, self.dpi);
+        let resolution =
+            |unit: &mut dyn FnMut(tiff::tags::ResolutionUnit, tiff::encoder::Rational)| {
+                if let Some(dpi) = dpi {
+                    let value = tiff::encoder::Rational {
+                        n: (dpi * 100.0).round() as u32,
+                        d: 100,
+                    };
+                    unit(tiff::tags::ResolutionUnit::Inch, value);
+                }
+            };
+        if self.background.a < 255 {
+            let mut image = encoder.new_image::<RGBA8>(width, height).map_err(failed)?;
+            image.rows_per_strip(strip_height).map_err(failed)?;
+            // Unassociated alpha, as the pixels are not premultiplied
+            image
+                .encoder()
+                .write_tag(tiff::tags::Tag::ExtraSamples, &[2u16][..])
+                .map_err(failed)?;
+            resolution(&mut |unit, value| image.resolution(unit, value));
+            self.render_strips(|_, strip| image.write_strip(strip.as_raw()).map_err(failed))?;
+            image.finish().map_err(failed)
+        } else {
+            let mut image = encoder.new_image::<RGB8>(width, height).map_err(failed)?;
+            image.rows_per_strip(strip_height).map_err(failed)?;
+            resolution(&mut |unit, value| image.resolution(unit, value));
+            self.render_strips(|_, strip| {
+                image.write_strip(&without_alpha(strip)).map_err(failed)
+            })?;
+            image.finish().map_err(failed)
+        }
+    }
+
+    /// Saves the canvas as PNG or TIFF by the extension and returns the elapsed time.
+    pub fn save(&mut self, output_path: &str) -> Result<Duration, ImageSlapperError> {
+        let start = Instant::now();
+        let path = Path::new(output_path);
+        let format = ImageFormat::from_path(path).ok();
+        if !matches!(format, Some(ImageFormat::Png | ImageFormat::Tiff)) {
+            return Err(ImageSlapperError::Encode {
+                path: path.to_path_buf(),
+                source: ImageError::Unsupported(ImageFormatHint::PathExtension(path.into()).into()),
+            });
+        }
+        let file = std::fs::File::create(path)
+            .map_err(|error| ImageSlapperError::io(path, "create image", error))?;
+        let mut writer = std::io::BufWriter::new(file);
+        match format {
+            Some(ImageFormat::Png) => self.write_png(&mut writer)?,
+            _ => self.write_tiff(&mut writer)?,
+        }
+        writer
+            .flush()
+            .map_err(|error| ImageSlapperError::io(path, "write image", error))?;
+        Ok(start.elapsed())
+    }
+}
+
+/// Wraps an error from a streaming encoder.
+fn encoding(
+    format: ImageFormat,
+    error: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> ImageSlapperError {
+    ImageSlapperError::Encode {
+        path: Path::new("<writer>").to_path_buf(),
+        source: ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), error)),
+    }
+}
+
+/// The RGB samples of a strip.
+fn without_alpha(strip: &RgbaImage) -> Vec<u8> {
+    strip
+        .as_raw()
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::geometry::rectangle::Rectangle;
+    use crate::io::tiled::tests::{source, striped_tiff, tiled_tiff};
+
+    /// A canvas with a rectangle, a patch and an image crossing each other.
+    fn canvas<'a>(budget: u64) -> StripCanvas<'a> {
+        let mut canvas = StripCanvas::new(60, 40).with_memory_budget(budget);
+        canvas.add(Rectangle::new(4, 3, 40, 30).filled(Rgba::new(200, 30, 30, 255)));
+        canvas.add(Patch::new(
+            Rectangle::new(0, 0, 15, 8).filled(Rgba::new(0, 0, 255, 255)),
+            35,
+            20,
+            20,
+            10,
+        ));
+        canvas.add(PlacedImage::from_image(
+            &DynamicImage::ImageRgb8(source()),
+            10,
+            9,
+            45,
+            29,
+        ));
+        canvas
+    }
+
+    /// What the canvas drawables hold: the image and two of its rows, and the patch.
+    const HELD: u64 = 37 * 23 * 4 + 37 * 4 * 2 + 20 * 10 * 4;
+
+    /// Room for six rows of 60 pixels, strip and encoder copy.
+    const SMALL_BUDGET: u64 = HELD + 6 * 60 * 4 * 2;
+
+    fn rendered(canvas: &mut StripCanvas) -> RgbaImage {
+        let mut pixels = Vec::new();
+        canvas
+            .render_strips(|top, strip| {
+                assert_eq!(top as usize, pixels.len() / (60 * 4));
+                pixels.extend_from_slice(strip.as_raw());
+                Ok(())
+            })
+            .unwrap();
+        RgbaImage::from_raw(canvas.width(), canvas.height(), pixels).unwrap()
+    }
+
+    #[test]
+    fn strips_render_the_same_as_one() {
+        let (mut whole, mut strips) = (canvas(DEFAULT_MEMORY_BUDGET), canvas(SMALL_BUDGET));
+        assert_eq!(whole.strip_height().unwrap(), 40);
+        assert_eq!(strips.strip_height().unwrap(), 6);
+        assert_eq!(rendered(&mut strips), rendered(&mut whole));
+    }
+
+    #[test]
+    fn strip_png_and_tiff_decode_the_same_as_one_strip() {
+        let expected = DynamicImage::ImageRgba8(rendered(&mut canvas(DEFAULT_MEMORY_BUDGET)));
+
+        let mut png = Vec::new();
+        canvas(SMALL_BUDGET).write_png(&mut png).unwrap();
+        let png = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+        assert_eq!(png.to_rgb8(), expected.to_rgb8());
+
+        let mut tiff = Cursor::new(Vec::new());
+        canvas(SMALL_BUDGET).write_tiff(&mut tiff).unwrap();
+        let tiff = image::load_from_memory_with_format(tiff.get_ref(), ImageFormat::Tiff).unwrap();
+        assert_eq!(tiff.to_rgb8(), expected.to_rgb8());
+    }
+
+    #[test]
+    fn placed_images_read_the_same_pixels_from_every_source() {
+        let source = source();
+        let mut png = Cursor::new(Vec::new());
+        source.write_to(&mut png, ImageFormat::Png).unwrap();
+        let files = [
+            ("png", png.into_inner()),
+            ("strip.tif", striped_tiff(&source, 4)),
+            ("tiled.tif", tiled_tiff(&source, (16, 16))),
+        ];
+
+        let render = |placed: PlacedImage| {
+            let mut canvas = StripCanvas::new(60, 40).with_memory_budget(HELD + 5 * 60 * 4 * 2);
+            canvas.add(placed);
+            rendered(&mut canvas)
+        };
+        let expected = render(PlacedImage::from_image(
+            &DynamicImage::ImageRgb8(source.clone()),
+            3,
+            2,
+            55,
+            33,
+        ));
+        for (extension, bytes) in files {
+            let path = std::env::temp_dir().join(format!(
+                "imageslapper-placed-{}.{extension}",
+                std::process::id()
+            ));
+            std::fs::write(&path, bytes).unwrap();
+            let placed = PlacedImage::open(&path.to_string_lossy(), 3, 2, 55, 33);
+            let actual = placed.map(render);
+            std::fs::remove_file(&path).unwrap();
+            assert!(actual.unwrap() == expected, "{extension} differs");
+        }
+    }
+
+    #[test]
+    fn drawables_over_the_budget_are_an_error() {
+        assert!(matches!(
+            canvas(HELD).strip_height(),
+            Err(ImageSlapperError::Layout { .. })
+        ));
+        assert_eq!(canvas(HELD + 60 * 4 * 2).strip_height().unwrap(), 1);
+    }
+}
@@ -2,14 +2,24 @@ use rgb::Rgba;
 use rusttype::Font;
 
 use crate::geometry::rectangle::Rectangle;
+use crate::rendering::color::ColorSpace;
 
-use super::{Text, TextAlignment};
+use super::hyphenate::Hyphenation;
+use super::overflow::Overflow;
+use super::paragraph::ParagraphStyle;
+use super::rich::{FontFamily, RichText, SpanStyle};
+use super::{Text, TextAlignment, WritingMode};
 
-/// Creates a simple text object with the Open Sans font.
-pub fn simple_open_sans(content: &str, bounding_box: Rectangle) -> Result<Text, String> {
+/// Loads the bundled Open Sans font.
+pub fn open_sans() -> Result<Font<'static>, String> {
     // From: https://www.1001fonts.com/open-sans-font.html "Free for commercial use"
     let font_data: &[u8] = include_bytes!("../../fonts/OpenSans-Regular.ttf");
-    let font: Font<'static> = Font::try_from_bytes(font_data).unwrap();
+    Font::try_from_bytes(font_data).ok_or_else(|| "Failed to parse Open Sans".to_string())
+}
+
+/// Creates a simple text object with the Open Sans font.
+pub fn simple_open_sans(content: &str, bounding_box: Rectangle) -> Result<Text<'static>, String> {
+    let font = open_sans()?;
     let color = Some(Rgba::new_alpha(255, 255, 255, 255));
     let field = Some(bounding_box);
     let alignment = TextAlignment::Left;
@@ -22,13 +32,33 @@ pub fn simple_open_sans(content: &str, bounding_box: Rectangle) -> Result<Text,
         content: content.to_string(),
         font: Some(font),
         font_size: 12.0,
+        min_font_size: None,
         color,
+        color_space: ColorSpace::Srgb,
         field,
         alignment,
         max_width,
+        max_lines: None,
         line_height,
         rotation_deg,
         word_wrap,
         anchor: None,
+        writing_mode: WritingMode::Horizontal,
+        overflow: Overflow::Visible,
+        letter_spacing: 0.0,
+        word_spacing: 0.0,
+        paragraph: ParagraphStyle::default(),
+        hyphenation: Hyphenation::default(),
     })
 }
+
+/// Creates white rich text in Open Sans from markup, e.g. `"**Price:** $19.99"`.
+pub fn rich_open_sans(
+    markup: &str,
+    font_size: f32,
+    bounding_box: Rectangle,
+) -> Result<RichText<'static>, String> {
+    let mut base = SpanStyle::new(FontFamily::new(open_sans()?), font_size);
+    base.color = Rgba::new(255, 255, 255, 255);
+    RichText::from_markup(markup, base, Some(bounding_box))
+}
@@ -0,0 +1,115 @@
+use std::sync::OnceLock;
+
+use hyphenation::{Hyphenator, Language, Load, Standard};
+
+/// The soft hyphen, an invisible mark for where a word may be hyphenated.
+pub const SOFT_HYPHEN: char = '\u{ad}';
+
+/// Languages with bundled Knuth-Liang hyphenation patterns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HyphenLanguage {
+    English,
+    German,
+    French,
+    Spanish,
+}
+
+impl HyphenLanguage {
+    /// Returns the patterns for the language, loaded once and shared.
+    fn dictionary(self) -> Option<&'static Standard> {
+        static ENGLISH: OnceLock<Option<Standard>> = OnceLock::new();
+        static GERMAN: OnceLock<Option<Standard>> = OnceLock::new();
+        static FRENCH: OnceLock<Option<Standard>> = OnceLock::new();
+        static SPANISH: OnceLock<Option<Standard>> = OnceLock::new();
+
+        let (cell, language) = match self {
+            HyphenLanguage::English => (&ENGLISH, Language::EnglishUS),
+            HyphenLanguage::German => (&GERMAN, Language::German1996),
+            HyphenLanguage::French => (&FRENCH, Language::French),
+            HyphenLanguage::Spanish => (&SPANISH, Language::Spanish),
+        };
+        cell.get_or_init(|| Standard::from_embedded(language).ok())
+            .as_ref()
+    }
+}
+
+/// Settings for breaking words across lines with a hyphen.
+///
+/// Soft hyphens in the text are always honored and take priority over the patterns, so
+/// hyphenation can be controlled by hand even without a language.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hyphenation {
+    /// Language whose patterns find break points, `None` to only break at soft hyphens.
+    pub language: Option<HyphenLanguage>,
+    /// Fewest characters left on either side of a pattern break, on top of the language's own minimum.
+    pub min_fragment: usize,
+}
+
+impl Default for Hyphenation {
+    fn default() -> Self {
+        Hyphenation {
+            language: None,
+            min_fragment: 2,
+        }
+    }
+}
+
+impl Hyphenation {
+    /// Hyphenates with the patterns of the given language.
+    pub fn new(language: HyphenLanguage) -> Self {
+        Hyphenation {
+            language: Some(language),
+            ..Hyphenation::default()
+        }
+    }
+
+    /// Returns the byte offsets in `text` where a line may break with a hyphen.
+    ///
+    /// Each offset is the start of the fragment that moves to the next line.
+    pub fn opportunities(&self, text: &str) -> Vec<usize> {
+        let mut breaks = Vec::new();
+        let mut word_start = None;
+
+        for (index, ch) in text
+            .char_indices()
+            .chain(std::iter::once((text.len(), ' ')))
+        {
+            if ch.is_alphabetic() || ch == SOFT_HYPHEN {
+                word_start.get_or_insert(index);
+            } else if let Some(start) = word_start.take() {
+                breaks.extend(
+                    self.word_opportunities(&text[start..index])
+                        .into_iter()
+                        .map(|offset| start + offset),
+                );
+            }
+        }
+
+        breaks
+    }
+
+    /// Finds the break offsets within a single word.
+    fn word_opportunities(&self, word: &str) -> Vec<usize> {
+        if word.contains(SOFT_HYPHEN) {
+            return word
+                .match_indices(SOFT_HYPHEN)
+                .map(|(index, hyphen)| index + hyphen.len())
+                .filter(|&offset| offset < word.len())
+                .collect();
+        }
+
+        let Some(dictionary) = self.language.and_then(HyphenLanguage::dictionary) else {
+            return Vec::new();
+        };
+        let length = word.chars().count();
+        dictionary
+            .hyphenate(word)
+            .breaks
+            .into_iter()
+            .filter(|&offset| {
+                let before = word[..offset].chars().count();
+                before >= self.min_fragment && length - before >= self.min_fragment
+            })
+            .collect()
+    }
+}
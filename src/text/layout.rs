@@ -0,0 +1,968 @@
+use std::ops::Range;
+
+use image::RgbaImage;
+use rusttype::{PositionedGlyph, Scale, ScaledGlyph, point};
+
+use crate::geometry::rectangle::Rectangle;
+use crate::rendering::blend::{blend_at, composite_rotated, fill_area};
+use crate::rendering::color::ColorSpace;
+
+use super::hyphenate::{Hyphenation, SOFT_HYPHEN};
+use super::paragraph::{ParagraphStyle, TabAlignment};
+use super::rich::Span;
+use super::{Anchor, TextAlignment, WritingMode};
+
+/// Settings that control how spans are broken into lines and aligned.
+#[derive(Clone, Debug)]
+pub struct LayoutOptions {
+    /// Length available to each line (its height in vertical mode), `None` for unlimited.
+    pub max_width: Option<f32>,
+    pub alignment: TextAlignment,
+    /// Line pitch as a multiple of the largest font size on the line, `None` for the font's own.
+    pub line_height: Option<f32>,
+    pub word_wrap: bool,
+    pub writing_mode: WritingMode,
+    pub paragraph: ParagraphStyle,
+    pub hyphenation: Hyphenation,
+}
+
+/// A glyph placed relative to the top-left corner of its text block.
+#[derive(Clone)]
+pub struct LayoutGlyph<'a> {
+    pub glyph: PositionedGlyph<'a>,
+    pub ch: char,
+    /// Index of the span the glyph belongs to.
+    pub span: usize,
+    /// Byte offset of the character in its span's text.
+    pub index: usize,
+    /// Start of the glyph along its line: x for horizontal text, y for vertical text.
+    pub offset: f32,
+    /// Distance to the next glyph along the line, including justification.
+    pub advance: f32,
+    /// Extra stroke width in pixels for spans that need synthetic bold.
+    pub embolden: f32,
+    /// Whether layout added the glyph, like the hyphen of a hyphenated line, so it is not in the text.
+    pub inserted: bool,
+}
+
+impl LayoutGlyph<'_> {
+    /// Returns the pen position of the glyph, on its baseline.
+    pub fn position(&self) -> (f32, f32) {
+        let position = self.glyph.position();
+        (position.x, position.y)
+    }
+
+    /// Returns the box covered by the glyph's outline, `None` for blank glyphs such as spaces.
+    pub fn ink_bounds(&self) -> Option<Bounds> {
+        let exact = self.glyph.unpositioned().exact_bounding_box()?;
+        let (x, y) = self.position();
+        Some(Bounds {
+            x: x + exact.min.x,
+            y: y + exact.min.y,
+            width: exact.width() + self.embolden,
+            height: exact.height(),
+        })
+    }
+}
+
+/// An axis-aligned box in fractional pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Bounds {
+    /// Returns the smallest box containing both boxes.
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Bounds {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+/// A single line of laid out text, or a column in vertical mode.
+#[derive(Clone, Debug)]
+pub struct LayoutLine {
+    /// Range of this line's glyphs in `TextLayout::glyphs`.
+    pub glyphs: Range<usize>,
+    pub x: f32,
+    pub top: f32,
+    /// The baseline's y for horizontal lines, the column's center x for vertical ones.
+    pub baseline: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// The result of laying out spans: positioned glyphs grouped into lines.
+#[derive(Clone)]
+pub struct TextLayout<'a> {
+    pub glyphs: Vec<LayoutGlyph<'a>>,
+    pub lines: Vec<LayoutLine>,
+    pub width: f32,
+    pub height: f32,
+    pub writing_mode: WritingMode,
+}
+
+impl TextLayout<'_> {
+    /// Returns the box made of the line boxes, from advances and line pitches.
+    pub fn logical_bounds(&self) -> Bounds {
+        Bounds {
+            x: 0.0,
+            y: 0.0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Returns the box covered by glyph outlines, `None` when nothing would be drawn.
+    pub fn ink_bounds(&self) -> Option<Bounds> {
+        self.glyphs
+            .iter()
+            .filter_map(LayoutGlyph::ink_bounds)
+            .reduce(|bounds, glyph| bounds.union(&glyph))
+    }
+}
+
+/// A shaped character before line breaking.
+struct Cluster<'a> {
+    ch: char,
+    span: usize,
+    index: usize,
+    glyph: ScaledGlyph<'a>,
+    /// Advance along the line direction.
+    advance: f32,
+    kern: f32,
+    embolden: f32,
+    /// Default distance between tab stops, set for tabs only.
+    tab_interval: Option<f32>,
+    /// Width of the hyphen drawn when the line breaks after this cluster, if it may.
+    hyphen_after: Option<f32>,
+}
+
+/// A line produced by the line breaker, before positioning.
+struct BrokenLine {
+    clusters: Range<usize>,
+    /// Whether the line ended because it ran out of space rather than at a hard break.
+    wrapped: bool,
+    /// Span used for the metrics of empty lines.
+    span: usize,
+    /// Whether the line starts a paragraph and is indented.
+    first: bool,
+    /// Width of the hyphen ending the line when a word was broken.
+    hyphen: Option<f32>,
+}
+
+/// Lays out the spans into lines according to the options.
+pub fn layout<'a>(spans: &[Span<'a>], options: &LayoutOptions) -> TextLayout<'a> {
+    let vertical = options.writing_mode == WritingMode::Vertical;
+    let paragraph = &options.paragraph;
+    let mut clusters = shape(spans, vertical, &options.hyphenation);
+    let limit = if options.word_wrap {
+        options.max_width
+    } else {
+        None
+    };
+    // Vertical text is mostly CJK, which may break between any two characters
+    let broken = break_lines(&mut clusters, limit, vertical, paragraph);
+    let indent = |line: &BrokenLine| {
+        if line.first {
+            paragraph.first_line_indent
+        } else {
+            0.0
+        }
+    };
+
+    let natural_length = broken
+        .iter()
+        .map(|line| natural_width(&clusters, line, indent(line)))
+        .fold(0.0, f32::max);
+    let length = match (options.alignment, options.max_width) {
+        (TextAlignment::Justify, Some(max_width)) => natural_length.max(max_width),
+        _ => natural_length,
+    };
+
+    let metrics: Vec<LineExtents> = broken
+        .iter()
+        .map(|line| line_extents(spans, &clusters, line))
+        .collect();
+    let pitches: Vec<f32> = metrics
+        .iter()
+        .map(|metrics| {
+            options.line_height.map_or(
+                metrics.ascent + metrics.descent + metrics.line_gap,
+                |factor| metrics.size * factor,
+            )
+        })
+        .collect();
+
+    // Offset of each line across the block, with paragraph spacing between paragraphs
+    let mut offsets = Vec::with_capacity(broken.len());
+    let mut depth = 0.0;
+    for (index, (line, pitch)) in broken.iter().zip(&pitches).enumerate() {
+        if line.first && index > 0 {
+            depth += paragraph.space_before;
+        }
+        offsets.push(depth);
+        depth += pitch;
+        if !line.wrapped && index + 1 < broken.len() {
+            depth += paragraph.space_after;
+        }
+    }
+
+    let mut glyphs = Vec::new();
+    let mut lines = Vec::new();
+
+    for (((line, metrics), pitch), cross) in broken.iter().zip(&metrics).zip(&pitches).zip(offsets)
+    {
+        let range = line.clusters.clone();
+        let line_clusters = &clusters[range.clone()];
+        let natural = natural_width(&clusters, line, indent(line));
+        let word_gaps = line_clusters
+            .iter()
+            .filter(|cluster| is_word_gap(cluster.ch))
+            .count();
+        let letter_gaps = line_clusters
+            .windows(2)
+            .filter(|pair| {
+                !pair[0].ch.is_whitespace()
+                    && pair[0].ch != SOFT_HYPHEN
+                    && !pair[1].ch.is_whitespace()
+            })
+            .count();
+        let (start, (word_extra, letter_extra)) = match options.alignment {
+            TextAlignment::Left => (0.0, (0.0, 0.0)),
+            TextAlignment::Center => ((length - natural) / 2.0, (0.0, 0.0)),
+            TextAlignment::Right => (length - natural, (0.0, 0.0)),
+            TextAlignment::Justify if line.wrapped => (
+                0.0,
+                paragraph.justification.distribute(
+                    length - natural,
+                    word_gaps,
+                    letter_gaps,
+                    metrics.size,
+                ),
+            ),
+            TextAlignment::Justify => (0.0, (0.0, 0.0)),
+        };
+
+        // Horizontal lines stack downwards, vertical columns stack right to left
+        let (line_top, line_left) = if vertical {
+            (start, depth - cross - pitch)
+        } else {
+            (cross, start)
+        };
+        let baseline = if vertical {
+            line_left + pitch / 2.0
+        } else {
+            line_top + (pitch - metrics.ascent - metrics.descent) / 2.0 + metrics.ascent
+        };
+
+        let first = glyphs.len();
+        let mut caret = start + indent(line);
+        for (index, cluster) in line_clusters.iter().enumerate() {
+            let last = index + 1 == line_clusters.len();
+            // Soft hyphens are only drawn where they break the line
+            if cluster.ch == SOFT_HYPHEN && !(last && line.hyphen.is_some()) {
+                continue;
+            }
+            if index > 0 {
+                caret += cluster.kern;
+            }
+            let style = &spans[cluster.span].style;
+            let mut advance = cluster.advance;
+            if cluster.ch == SOFT_HYPHEN {
+                advance = line.hyphen.unwrap_or(advance);
+            } else if is_word_gap(cluster.ch) {
+                advance += word_extra;
+            } else if !cluster.ch.is_whitespace()
+                && line_clusters
+                    .get(index + 1)
+                    .is_some_and(|next| !next.ch.is_whitespace())
+            {
+                advance += letter_extra;
+            }
+
+            let position = if vertical {
+                // Upright glyphs centered in the column, each in a square em box
+                let (ascent, descent, _) = style.v_metrics();
+                let width = cluster.glyph.h_metrics().advance_width;
+                point(
+                    baseline - width / 2.0 - style.baseline_shift,
+                    caret + style.size * ascent / (ascent + descent),
+                )
+            } else {
+                point(caret, baseline - style.baseline_shift)
+            };
+
+            glyphs.push(LayoutGlyph {
+                glyph: cluster.glyph.clone().positioned(position),
+                ch: cluster.ch,
+                span: cluster.span,
+                index: cluster.index,
+                offset: caret,
+                advance,
+                embolden: cluster.embolden,
+                inserted: false,
+            });
+            caret += advance;
+        }
+
+        // A word broken by the patterns gets a hyphen that is not in the text
+        if let (Some(hyphen), Some(last)) = (line.hyphen, line_clusters.last())
+            && last.ch != SOFT_HYPHEN
+        {
+            let style = &spans[last.span].style;
+            glyphs.push(LayoutGlyph {
+                glyph: hyphen_glyph(&spans[last.span])
+                    .positioned(point(caret, baseline - style.baseline_shift)),
+                ch: '-',
+                span: last.span,
+                index: last.index + last.ch.len_utf8(),
+                offset: caret,
+                advance: hyphen,
+                embolden: last.embolden,
+                inserted: true,
+            });
+            caret += hyphen;
+        }
+
+        lines.push(if vertical {
+            LayoutLine {
+                glyphs: first..glyphs.len(),
+                x: line_left,
+                top: line_top,
+                baseline,
+                width: *pitch,
+                height: caret - start,
+            }
+        } else {
+            LayoutLine {
+                glyphs: first..glyphs.len(),
+                x: line_left,
+                top: line_top,
+                baseline,
+                width: caret - start,
+                height: *pitch,
+            }
+        });
+    }
+
+    let (width, height) = if vertical {
+        (depth, length)
+    } else {
+        (length, depth)
+    };
+
+    TextLayout {
+        glyphs,
+        lines,
+        width,
+        height,
+        writing_mode: options.writing_mode,
+    }
+}
+
+/// Looks up and measures the glyph for every character of every span.
+///
+/// Horizontal text also records where words may be hyphenated.
+fn shape<'a>(spans: &[Span<'a>], vertical: bool, hyphenation: &Hyphenation) -> Vec<Cluster<'a>> {
+    let mut clusters = Vec::new();
+
+    for (index, span) in spans.iter().enumerate() {
+        let style = &span.style;
+        let (font, synthetic) = style.font.face(style.weight);
+        let scale = Scale::uniform(style.size);
+        let embolden = if synthetic {
+            style.embolden_width()
+        } else {
+            0.0
+        };
+        let space = font.glyph(' ').scaled(scale).h_metrics().advance_width;
+        let hyphen = hyphen_glyph(span);
+        let hyphen_width = hyphen.h_metrics().advance_width + embolden;
+        let breaks = if vertical {
+            Vec::new()
+        } else {
+            hyphenation.opportunities(&span.text)
+        };
+
+        let mut previous = None;
+        for (byte, ch) in span.text.char_indices().filter(|&(_, ch)| ch != '\r') {
+            let glyph = match ch {
+                '\t' => font.glyph(' ').scaled(scale),
+                SOFT_HYPHEN => hyphen.clone(),
+                _ => font.glyph(ch).scaled(scale),
+            };
+            let id = glyph.id();
+            let horizontal = glyph.h_metrics().advance_width + embolden;
+            let (advance, kern) = match (ch, vertical) {
+                // Tabs get their advance from the tab stops while breaking lines
+                ('\n' | '\t' | SOFT_HYPHEN, _) => (0.0, 0.0),
+                // Spaces keep their width, everything else advances by one em
+                (_, true) if ch.is_whitespace() => (horizontal, 0.0),
+                (_, true) => (style.size, 0.0),
+                (_, false) => (
+                    horizontal,
+                    previous.map_or(0.0, |previous| font.pair_kerning(scale, previous, id)),
+                ),
+            };
+            let spacing = match ch {
+                '\n' | '\t' | SOFT_HYPHEN => 0.0,
+                _ if is_word_gap(ch) => style.letter_spacing + style.word_spacing,
+                _ => style.letter_spacing,
+            };
+            previous = Some(id);
+
+            clusters.push(Cluster {
+                ch,
+                span: index,
+                index: byte,
+                glyph,
+                advance: advance + spacing,
+                kern,
+                embolden,
+                tab_interval: (ch == '\t').then_some(space * 4.0),
+                hyphen_after: breaks
+                    .binary_search(&(byte + ch.len_utf8()))
+                    .is_ok()
+                    .then_some(hyphen_width),
+            });
+        }
+    }
+
+    clusters
+}
+
+/// Returns the hyphen glyph of a span's face.
+fn hyphen_glyph<'a>(span: &Span<'a>) -> ScaledGlyph<'a> {
+    let (font, _) = span.style.font.face(span.style.weight);
+    font.glyph('-').scaled(Scale::uniform(span.style.size))
+}
+
+/// Checks whether a character separates words and takes part in word spacing.
+fn is_word_gap(ch: char) -> bool {
+    ch == ' ' || ch == '\u{a0}'
+}
+
+/// Splits the clusters into lines at hard breaks and, with a limit, at word boundaries.
+///
+/// A hyphenation point is preferred over the word boundary before it when the hyphenated
+/// fragment still fits. With `break_anywhere`, a line without either may also break between
+/// characters. Tabs are given their advance here, since it depends on where on the line they end up.
+fn break_lines(
+    clusters: &mut [Cluster],
+    limit: Option<f32>,
+    break_anywhere: bool,
+    paragraph: &ParagraphStyle,
+) -> Vec<BrokenLine> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut first = true;
+    // Position along the line, including the first line indent
+    let mut width = paragraph.first_line_indent;
+    let mut break_at = None;
+    let mut hyphen_at = None;
+    let mut seen_word = false;
+    let mut index = 0;
+
+    while index < clusters.len() {
+        if clusters[index].ch == '\n' {
+            lines.push(BrokenLine {
+                clusters: trim_end(clusters, start..index),
+                wrapped: false,
+                span: clusters[index].span,
+                first,
+                hyphen: None,
+            });
+            start = index + 1;
+            first = true;
+            width = paragraph.first_line_indent;
+            break_at = None;
+            hyphen_at = None;
+            seen_word = false;
+            index += 1;
+            continue;
+        }
+
+        if clusters[index].ch == '\t' {
+            clusters[index].advance = tab_advance(clusters, index, width, paragraph);
+        }
+
+        let cluster = &clusters[index];
+        let step = if index == start {
+            cluster.advance
+        } else {
+            cluster.kern + cluster.advance
+        };
+
+        if !cluster.ch.is_whitespace() {
+            let at = match (break_at, hyphen_at) {
+                (Some(word), Some((hyphen, _))) if hyphen > word => hyphen_at,
+                (None, Some(_)) => hyphen_at,
+                (word, _) => word.map(|word| (word, None)),
+            }
+            .or((break_anywhere && index > start).then_some((index, None)));
+            if let (Some(limit), Some((at, hyphen))) = (limit, at)
+                && width + step > limit
+            {
+                lines.push(BrokenLine {
+                    clusters: trim_end(clusters, start..at),
+                    wrapped: true,
+                    span: cluster.span,
+                    first,
+                    hyphen,
+                });
+                // Lay out the carried over fragment again, its tabs and break points moved
+                start = at;
+                first = false;
+                width = 0.0;
+                break_at = None;
+                hyphen_at = None;
+                seen_word = false;
+                index = at;
+                continue;
+            }
+            if seen_word && index > start && clusters[index - 1].ch.is_whitespace() {
+                break_at = Some(index);
+            }
+            seen_word = true;
+        }
+
+        width += step;
+        if let Some(hyphen) = cluster.hyphen_after
+            && limit.is_none_or(|limit| width + hyphen <= limit)
+        {
+            hyphen_at = Some((index + 1, Some(hyphen)));
+        }
+        index += 1;
+    }
+
+    lines.push(BrokenLine {
+        clusters: trim_end(clusters, start..clusters.len()),
+        wrapped: false,
+        span: clusters.last().map_or(0, |cluster| cluster.span),
+        first,
+        hyphen: None,
+    });
+
+    lines
+}
+
+/// Returns the advance that takes a tab at `position` to its stop.
+///
+/// Right, centered and decimal stops measure the text up to the next tab or line break.
+fn tab_advance(
+    clusters: &[Cluster],
+    index: usize,
+    position: f32,
+    paragraph: &ParagraphStyle,
+) -> f32 {
+    let Some(stop) = paragraph.next_stop(position) else {
+        let interval = paragraph
+            .tab_interval
+            .or(clusters[index].tab_interval)
+            .filter(|interval| *interval > 0.0);
+        return interval.map_or(0.0, |interval| {
+            ((position / interval).floor() + 1.0) * interval - position
+        });
+    };
+
+    let end = clusters[index + 1..]
+        .iter()
+        .position(|cluster| matches!(cluster.ch, '\t' | '\n'))
+        .map_or(clusters.len(), |offset| index + 1 + offset);
+    let before_stop = match stop.alignment {
+        TabAlignment::Left => 0.0,
+        TabAlignment::Right => line_width(clusters, index + 1..end),
+        TabAlignment::Center => line_width(clusters, index + 1..end) / 2.0,
+        TabAlignment::Decimal(separator) => {
+            let separator = clusters[index + 1..end]
+                .iter()
+                .position(|cluster| cluster.ch == separator)
+                .map_or(end, |offset| index + 1 + offset);
+            line_width(clusters, index + 1..separator)
+        }
+    };
+
+    (stop.position - before_stop - position).max(0.0)
+}
+
+/// Removes trailing whitespace from a range of clusters.
+fn trim_end(clusters: &[Cluster], range: Range<usize>) -> Range<usize> {
+    let mut end = range.end;
+    while end > range.start && clusters[end - 1].ch.is_whitespace() {
+        end -= 1;
+    }
+    range.start..end
+}
+
+/// Measures a broken line with its indent and hyphen, before justification.
+fn natural_width(clusters: &[Cluster], line: &BrokenLine, indent: f32) -> f32 {
+    indent + line_width(clusters, line.clusters.clone()) + line.hyphen.unwrap_or(0.0)
+}
+
+/// Measures the advance of a range of clusters placed on one line.
+fn line_width(clusters: &[Cluster], range: Range<usize>) -> f32 {
+    clusters[range]
+        .iter()
+        .enumerate()
+        .map(|(index, cluster)| {
+            if index == 0 {
+                cluster.advance
+            } else {
+                cluster.kern + cluster.advance
+            }
+        })
+        .sum()
+}
+
+/// Vertical metrics of a line, combined over all spans on it.
+struct LineExtents {
+    ascent: f32,
+    descent: f32,
+    line_gap: f32,
+    size: f32,
+}
+
+/// Combines the vertical metrics of the spans used on a line.
+fn line_extents(spans: &[Span], clusters: &[Cluster], line: &BrokenLine) -> LineExtents {
+    let mut metrics = LineExtents {
+        ascent: 0.0,
+        descent: 0.0,
+        line_gap: 0.0,
+        size: 0.0,
+    };
+
+    let mut used: Vec<usize> = clusters[line.clusters.clone()]
+        .iter()
+        .map(|cluster| cluster.span)
+        .collect();
+    if used.is_empty() && !spans.is_empty() {
+        used.push(line.span);
+    }
+    used.dedup();
+
+    for index in used {
+        let style = &spans[index].style;
+        let (ascent, descent, line_gap) = style.v_metrics();
+        metrics.ascent = metrics.ascent.max(ascent + style.baseline_shift);
+        metrics.descent = metrics.descent.max(descent - style.baseline_shift);
+        metrics.line_gap = metrics.line_gap.max(line_gap);
+        metrics.size = metrics.size.max(style.size);
+    }
+
+    metrics
+}
+
+/// Where a laid out block ends up on the canvas.
+#[derive(Clone, Copy, Debug)]
+pub struct Placement {
+    /// Top-left corner of the block before rotation.
+    pub origin: (f32, f32),
+    /// Canvas point the block is rotated about, the block's anchor point.
+    pub pivot: (f32, f32),
+    /// Clockwise rotation in degrees.
+    pub rotation_deg: f32,
+}
+
+impl Placement {
+    /// Places a block of the given size so its anchor point sits on the field's anchor point.
+    ///
+    /// Without an anchor the block follows the alignment along the top edge of the field.
+    /// Without a field the block's top-left corner is at the canvas origin.
+    pub fn new(
+        field: Option<&Rectangle>,
+        anchor: Option<Anchor>,
+        alignment: TextAlignment,
+        size: (f32, f32),
+        rotation_deg: f32,
+    ) -> Self {
+        let anchor = anchor.unwrap_or(match alignment {
+            TextAlignment::Left | TextAlignment::Justify => Anchor::TopLeft,
+            TextAlignment::Center => Anchor::TopCenter,
+            TextAlignment::Right => Anchor::TopRight,
+        });
+        let (fx, fy) = anchor.fractions();
+
+        let pivot = field.map_or((fx * size.0, fy * size.1), |field| {
+            (
+                field.x1 as f32 + fx * field.width as f32,
+                field.y1 as f32 + fy * field.height as f32,
+            )
+        });
+
+        Placement {
+            origin: (pivot.0 - fx * size.0, pivot.1 - fy * size.1),
+            pivot,
+            rotation_deg,
+        }
+    }
+
+    /// Returns the canvas positions of the corners of a block of the given size.
+    pub fn corners(&self, size: (f32, f32)) -> [(f32, f32); 4] {
+        let (sin, cos) = self.rotation_deg.to_radians().sin_cos();
+        [(0.0, 0.0), (size.0, 0.0), (size.0, size.1), (0.0, size.1)].map(|(x, y)| {
+            let dx = self.origin.0 + x - self.pivot.0;
+            let dy = self.origin.1 + y - self.pivot.1;
+            (
+                self.pivot.0 + dx * cos - dy * sin,
+                self.pivot.1 + dx * sin + dy * cos,
+            )
+        })
+    }
+
+    /// Moves the placement by the given offset.
+    pub fn translated(&self, dx: f32, dy: f32) -> Self {
+        Placement {
+            origin: (self.origin.0 + dx, self.origin.1 + dy),
+            pivot: (self.pivot.0 + dx, self.pivot.1 + dy),
+            rotation_deg: self.rotation_deg,
+        }
+    }
+
+    /// Checks whether the rotated block lies within the field, allowing half a pixel of slack.
+    pub fn fits(&self, size: (f32, f32), field: &Rectangle) -> bool {
+        self.corners(size).iter().all(|&(x, y)| {
+            x >= field.x1 as f32 - 0.5
+                && x <= field.x2 as f32 + 0.5
+                && y >= field.y1 as f32 - 0.5
+                && y <= field.y2 as f32 + 0.5
+        })
+    }
+
+    /// Returns the axis-aligned bounds of the rotated block, clamped to the canvas origin.
+    pub fn bounds(&self, size: (f32, f32)) -> Rectangle {
+        let corners = self.corners(size);
+        let min = |f: fn(&(f32, f32)) -> f32| corners.iter().map(f).fold(f32::INFINITY, f32::min);
+        let max =
+            |f: fn(&(f32, f32)) -> f32| corners.iter().map(f).fold(f32::NEG_INFINITY, f32::max);
+
+        let x1 = min(|c| c.0).floor().max(0.0) as u32;
+        let y1 = min(|c| c.1).floor().max(0.0) as u32;
+        let x2 = (max(|c| c.0).ceil().max(0.0) as u32).max(x1);
+        let y2 = (max(|c| c.1).ceil().max(0.0) as u32).max(y1);
+
+        Rectangle {
+            x1,
+            y1,
+            x2,
+            y2,
+            width: x2 - x1,
+            height: y2 - y1,
+            border: None,
+            filled: false,
+            fill_color: None,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+/// Returns the longest line that fits in a `width` by `height` box when running at `degrees`.
+pub fn line_length_limit(width: f32, height: f32, degrees: f32) -> f32 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let along_x = if cos.abs() > 1e-4 {
+        width / cos.abs()
+    } else {
+        f32::INFINITY
+    };
+    let along_y = if sin.abs() > 1e-4 {
+        height / sin.abs()
+    } else {
+        f32::INFINITY
+    };
+    along_x.min(along_y)
+}
+
+/// Draws a laid out block of spans at its placement, rotating it when needed.
+pub fn draw(layout: &TextLayout, spans: &[Span], image: &mut RgbaImage, placement: &Placement) {
+    if placement.rotation_deg.rem_euclid(360.0) == 0.0 {
+        render(layout, spans, image, placement.origin);
+        return;
+    }
+
+    // Leave room for glyphs that reach outside their line boxes
+    let padding = spans
+        .iter()
+        .map(|span| span.style.size)
+        .fold(0.0, f32::max)
+        .ceil();
+    let mut block = RgbaImage::new(
+        (layout.width + padding * 2.0).ceil() as u32,
+        (layout.height + padding * 2.0).ceil() as u32,
+    );
+    render(layout, spans, &mut block, (padding, padding));
+
+    let source_pivot = (
+        placement.pivot.0 - placement.origin.0 + padding,
+        placement.pivot.1 - placement.origin.1 + padding,
+    );
+    composite_rotated(
+        image,
+        &block,
+        source_pivot,
+        placement.pivot,
+        placement.rotation_deg,
+    );
+}
+
+/// Draws a laid out block of spans with its top-left corner at `origin`.
+pub fn render(layout: &TextLayout, spans: &[Span], image: &mut RgbaImage, origin: (f32, f32)) {
+    let offset = (origin.0.round(), origin.1.round());
+    let vertical = layout.writing_mode == WritingMode::Vertical;
+
+    for line in &layout.lines {
+        let segments = segments(&layout.glyphs[line.glyphs.clone()]);
+
+        for segment in &segments {
+            let style = &spans[segment.span].style;
+            if let Some(background) = style.background {
+                let (x1, y1, x2, y2) = if vertical {
+                    let center = line.baseline - style.baseline_shift;
+                    (
+                        center - style.size / 2.0,
+                        segment.start,
+                        center + style.size / 2.0,
+                        segment.end,
+                    )
+                } else {
+                    let (ascent, descent, _) = style.v_metrics();
+                    let baseline = line.baseline - style.baseline_shift;
+                    (
+                        segment.start,
+                        baseline - ascent,
+                        segment.end,
+                        baseline + descent,
+                    )
+                };
+                fill_area(
+                    image,
+                    x1 + offset.0,
+                    y1 + offset.1,
+                    x2 + offset.0,
+                    y2 + offset.1,
+                    background,
+                );
+            }
+        }
+
+        for glyph in &layout.glyphs[line.glyphs.clone()] {
+            draw_glyph(
+                image,
+                glyph,
+                spans[glyph.span].style.color,
+                (offset.0 as i32, offset.1 as i32),
+            );
+        }
+
+        for segment in &segments {
+            let style = &spans[segment.span].style;
+            let thickness = style.decoration_thickness();
+            // Offsets of the decorations across the line, from the baseline or column center
+            let mut decorations = Vec::new();
+            if style.underline {
+                decorations.push(if vertical {
+                    style.size * 0.55
+                } else {
+                    style.size * 0.1
+                });
+            }
+            if style.strikethrough {
+                decorations.push(if vertical {
+                    -thickness / 2.0
+                } else {
+                    -style.size * 0.28 - thickness / 2.0
+                });
+            }
+
+            for position in decorations {
+                let across = line.baseline - style.baseline_shift + position;
+                let (x1, y1, x2, y2) = if vertical {
+                    (across, segment.start, across + thickness, segment.end)
+                } else {
+                    (segment.start, across, segment.end, across + thickness)
+                };
+                fill_area(
+                    image,
+                    x1 + offset.0,
+                    y1 + offset.1,
+                    x2 + offset.0,
+                    y2 + offset.1,
+                    style.color,
+                );
+            }
+        }
+    }
+}
+
+/// A run of glyphs on one line that share a span, measured along the line.
+struct Segment {
+    span: usize,
+    start: f32,
+    end: f32,
+}
+
+/// Groups consecutive glyphs of the same span.
+fn segments(glyphs: &[LayoutGlyph]) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for glyph in glyphs {
+        let end = glyph.offset + glyph.advance;
+        match segments.last_mut() {
+            Some(segment) if segment.span == glyph.span => segment.end = end,
+            _ => segments.push(Segment {
+                span: glyph.span,
+                start: glyph.offset,
+                end,
+            }),
+        }
+    }
+    segments
+}
+
+/// Rasterizes a single glyph, widening its strokes when synthetic bold is needed.
+fn draw_glyph(
+    image: &mut RgbaImage,
+    glyph: &LayoutGlyph,
+    color: rgb::Rgba<u8>,
+    offset: (i32, i32),
+) {
+    let Some(bounding_box) = glyph.glyph.pixel_bounding_box() else {
+        return;
+    };
+    let left = offset.0 + bounding_box.min.x;
+    let top = offset.1 + bounding_box.min.y;
+
+    let extra = glyph.embolden.ceil() as i32;
+    if extra <= 0 {
+        glyph.glyph.draw(|x, y, v| {
+            blend_at(image, left + x as i32, top + y as i32, color, v);
+        });
+        return;
+    }
+
+    let width = bounding_box.width() + extra;
+    let height = bounding_box.height();
+    let mut coverage = vec![0.0_f32; (width * height) as usize];
+    glyph.glyph.draw(|x, y, v| {
+        for dx in 0..=extra {
+            let index = (y as i32 * width + x as i32 + dx) as usize;
+            coverage[index] = coverage[index].max(v);
+        }
+    });
+
+    for y in 0..height {
+        for x in 0..width {
+            let v = coverage[(y * width + x) as usize];
+            if v > 0.0 {
+                blend_at(image, left + x, top + y, color, v);
+            }
+        }
+    }
+}
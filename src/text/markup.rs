@@ -0,0 +1,458 @@
+use rgb::Rgba;
+
+use super::rich::{FontFamily, FontWeight, Span, SpanStyle};
+
+/// Paired delimiters and the style they toggle.
+const DELIMITERS: [&str; 4] = ["**", "__", "~~", "=="];
+
+/// Background used by `==highlight==`.
+const HIGHLIGHT: Rgba<u8> = Rgba {
+    r: 255,
+    g: 230,
+    b: 0,
+    a: 255,
+};
+
+/// Parses markup into spans with the default parser. See `MarkupParser` for the syntax.
+pub fn parse<'a>(source: &str, base: &SpanStyle<'a>) -> Result<Vec<Span<'a>>, String> {
+    MarkupParser::new(base.clone()).parse(source)
+}
+
+/// Parser for the small inline markup language used by `RichText`.
+///
+/// Supported syntax:
+/// - `**bold**`, `__underline__`, `~~strikethrough~~` and `==highlight==`
+/// - `[b]`, `[u]`, `[s]`, `[sup]` and `[sub]`
+/// - `[color=#rrggbb]` and `[bg=#rrggbb]`, also in `#rgb` and `#rrggbbaa` form
+/// - `[size=24]` in pixels or `[size=150%]` relative to the enclosing size
+/// - `[shift=4]` to raise (or with a negative value lower) the baseline in pixels
+/// - `[tracking=2]` and `[word-spacing=4]` to add space between letters or words in pixels
+/// - `[font=name]` for families registered with `with_font`
+/// - `\` to escape the next character
+///
+/// Delimiters style the text between a pair that opens before and closes after non-space
+/// characters, outside of words. Unpaired or intra-word delimiters, as in `a == b` or
+/// `snake__case__name`, are kept as text.
+///
+/// Tags close with `[/name]` and must be closed in reverse order of opening. Brackets that do
+/// not form a known tag are kept as text, so untrusted input can only ever style itself.
+pub struct MarkupParser<'a> {
+    pub base: SpanStyle<'a>,
+    pub fonts: Vec<(String, FontFamily<'a>)>,
+}
+
+/// What opened a style frame, used to match the closing markup.
+#[derive(PartialEq)]
+enum Marker {
+    Delimiter(&'static str),
+    Tag(String),
+}
+
+/// A style pushed by an opening delimiter or tag.
+struct Frame<'a> {
+    marker: Marker,
+    style: SpanStyle<'a>,
+}
+
+impl<'a> MarkupParser<'a> {
+    pub fn new(base: SpanStyle<'a>) -> Self {
+        MarkupParser {
+            base,
+            fonts: Vec::new(),
+        }
+    }
+
+    /// Registers a family that `[font=name]` can select.
+    pub fn with_font(mut self, name: &str, family: FontFamily<'a>) -> Self {
+        self.fonts.push((name.to_string(), family));
+        self
+    }
+
+    /// Parses the source into styled spans.
+    pub fn parse(&self, source: &str) -> Result<Vec<Span<'a>>, String> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut spans = Vec::new();
+        let mut stack: Vec<Frame<'a>> = Vec::new();
+        let mut text = String::new();
+        let paired = pair_delimiters(&chars);
+        let mut index = 0;
+
+        while index < chars.len() {
+            let current = stack.last().map_or(&self.base, |frame| &frame.style);
+
+            if chars[index] == '\\' && index + 1 < chars.len() {
+                text.push(chars[index + 1]);
+                index += 2;
+                continue;
+            }
+
+            if let Some(delimiter) = delimiter_at(&chars, index) {
+                if !paired[index] {
+                    text.push_str(delimiter);
+                    index += delimiter.len();
+                    continue;
+                }
+                flush(&mut spans, &mut text, current);
+                let marker = Marker::Delimiter(delimiter);
+                if stack.last().is_some_and(|frame| frame.marker == marker) {
+                    stack.pop();
+                } else if stack.iter().any(|frame| frame.marker == marker) {
+                    return Err(format!("`{delimiter}` closed out of order"));
+                } else {
+                    let style = apply_delimiter(delimiter, current.clone());
+                    stack.push(Frame { marker, style });
+                }
+                index += delimiter.len();
+                continue;
+            }
+
+            if chars[index] == '['
+                && let Some(length) = chars[index + 1..].iter().position(|&c| c == ']')
+            {
+                let tag: String = chars[index + 1..index + 1 + length].iter().collect();
+                let end = index + length + 2;
+
+                if let Some(name) = tag.strip_prefix('/') {
+                    if is_tag(name) {
+                        flush(&mut spans, &mut text, current);
+                        match stack.pop() {
+                            Some(frame) if frame.marker == Marker::Tag(name.to_string()) => {}
+                            _ => return Err(format!("unexpected closing tag `[/{name}]`")),
+                        }
+                        index = end;
+                        continue;
+                    }
+                } else if let Some((name, style)) = self.apply_tag(&tag, current)? {
+                    flush(&mut spans, &mut text, current);
+                    stack.push(Frame {
+                        marker: Marker::Tag(name),
+                        style,
+                    });
+                    index = end;
+                    continue;
+                }
+            }
+
+            text.push(chars[index]);
+            index += 1;
+        }
+
+        let current = stack.last().map_or(&self.base, |frame| &frame.style);
+        flush(&mut spans, &mut text, current);
+
+        match stack.last().map(|frame| &frame.marker) {
+            Some(Marker::Delimiter(delimiter)) => Err(format!("unclosed `{delimiter}`")),
+            Some(Marker::Tag(name)) => Err(format!("unclosed tag `[{name}]`")),
+            None => Ok(spans),
+        }
+    }
+
+    /// Applies a known opening tag to the style, or returns `None` for anything else.
+    fn apply_tag(
+        &self,
+        tag: &str,
+        style: &SpanStyle<'a>,
+    ) -> Result<Option<(String, SpanStyle<'a>)>, String> {
+        let (name, value) = match tag.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (tag.trim(), None),
+        };
+        if !is_tag(name) {
+            return Ok(None);
+        }
+
+        let mut style = style.clone();
+        match (name, value) {
+            ("b", None) => style.weight = FontWeight::Bold,
+            ("u", None) => style.underline = true,
+            ("s", None) => style.strikethrough = true,
+            ("sup", None) => {
+                style.baseline_shift += style.size * 0.35;
+                style.size *= 0.7;
+            }
+            ("sub", None) => {
+                style.baseline_shift -= style.size * 0.15;
+                style.size *= 0.7;
+            }
+            ("color", Some(value)) => style.color = parse_color(value)?,
+            ("bg", Some(value)) => style.background = Some(parse_color(value)?),
+            ("size", Some(value)) => style.size = parse_size(value, style.size)?,
+            ("shift", Some(value)) => {
+                style.baseline_shift += value
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid baseline shift `{value}`"))?;
+            }
+            ("tracking", Some(value)) => {
+                style.letter_spacing = value
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid tracking `{value}`"))?;
+            }
+            ("word-spacing", Some(value)) => {
+                style.word_spacing = value
+                    .parse::<f32>()
+                    .map_err(|_| format!("invalid word spacing `{value}`"))?;
+            }
+            ("font", Some(value)) => {
+                style.font = self
+                    .fonts
+                    .iter()
+                    .find(|(font_name, _)| font_name == value)
+                    .map(|(_, family)| family.clone())
+                    .ok_or_else(|| format!("unknown font `{value}`"))?;
+            }
+            _ => return Ok(None),
+        }
+
+        Ok(Some((name.to_string(), style)))
+    }
+}
+
+/// Checks whether a tag name is part of the markup language.
+fn is_tag(name: &str) -> bool {
+    matches!(
+        name,
+        "b" | "u"
+            | "s"
+            | "sup"
+            | "sub"
+            | "color"
+            | "bg"
+            | "size"
+            | "shift"
+            | "tracking"
+            | "word-spacing"
+            | "font"
+    )
+}
+
+/// Returns the delimiter starting at `index`, if any.
+fn delimiter_at(chars: &[char], index: usize) -> Option<&'static str> {
+    DELIMITERS
+        .iter()
+        .find(|delimiter| starts_with(&chars[index..], delimiter))
+        .copied()
+}
+
+/// Marks the delimiters that open or close a pair, skipping escapes and tags as `parse` does.
+/// A closer pairs with the nearest matching opener, leaving any opened after it unpaired.
+fn pair_delimiters(chars: &[char]) -> Vec<bool> {
+    let mut paired = vec![false; chars.len()];
+    let mut openers: Vec<(&str, usize)> = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        if chars[index] == '\\' {
+            index += 2;
+            continue;
+        }
+
+        if let Some(delimiter) = delimiter_at(chars, index) {
+            let before = index.checked_sub(1).map(|i| chars[i]);
+            let after = chars.get(index + delimiter.len()).copied();
+            let can_open = after.is_some_and(|c| !c.is_whitespace())
+                && !before.is_some_and(char::is_alphanumeric);
+            let can_close = before.is_some_and(|c| !c.is_whitespace())
+                && !after.is_some_and(char::is_alphanumeric);
+
+            let opener = openers.iter().rposition(|(open, _)| *open == delimiter);
+            if can_close && let Some(position) = opener {
+                paired[openers[position].1] = true;
+                paired[index] = true;
+                openers.truncate(position);
+            } else if can_open {
+                openers.push((delimiter, index));
+            }
+            index += delimiter.len();
+            continue;
+        }
+
+        if chars[index] == '['
+            && let Some(length) = chars[index + 1..].iter().position(|&c| c == ']')
+        {
+            let tag: String = chars[index + 1..index + 1 + length].iter().collect();
+            let name = tag.trim_start_matches('/');
+            let name = name.split_once('=').map_or(name, |(name, _)| name);
+            if is_tag(name.trim()) {
+                index += length + 2;
+                continue;
+            }
+        }
+
+        index += 1;
+    }
+
+    paired
+}
+
+/// Applies the style toggled by a paired delimiter.
+fn apply_delimiter<'a>(delimiter: &str, mut style: SpanStyle<'a>) -> SpanStyle<'a> {
+    match delimiter {
+        "**" => style.weight = FontWeight::Bold,
+        "__" => style.underline = true,
+        "~~" => style.strikethrough = true,
+        _ => style.background = Some(HIGHLIGHT),
+    }
+    style
+}
+
+/// Moves the pending text into a new span with the given style.
+fn flush<'a>(spans: &mut Vec<Span<'a>>, text: &mut String, style: &SpanStyle<'a>) {
+    if !text.is_empty() {
+        spans.push(Span {
+            text: std::mem::take(text),
+            style: style.clone(),
+        });
+    }
+}
+
+/// Checks whether the characters start with the given pattern.
+fn starts_with(chars: &[char], pattern: &str) -> bool {
+    let mut chars = chars.iter();
+    pattern.chars().all(|p| chars.next() == Some(&p))
+}
+
+/// Parses `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` into a color.
+pub fn parse_color(value: &str) -> Result<Rgba<u8>, String> {
+    let invalid = || format!("invalid color `{value}`");
+    let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    let digits: Vec<u8> = match hex.len() {
+        3 | 4 => hex
+            .chars()
+            .map(|c| c.to_digit(16).map_or(0, |d| d as u8 * 17))
+            .collect(),
+        6 | 8 => (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0))
+            .collect(),
+        _ => return Err(invalid()),
+    };
+
+    Ok(Rgba::new(
+        digits[0],
+        digits[1],
+        digits[2],
+        digits.get(3).copied().unwrap_or(255),
+    ))
+}
+
+/// Parses an absolute size in pixels or a percentage of the current size.
+fn parse_size(value: &str, current: f32) -> Result<f32, String> {
+    let invalid = || format!("invalid size `{value}`");
+    let size = match value.strip_suffix('%') {
+        Some(percent) => percent.parse::<f32>().map_err(|_| invalid())? / 100.0 * current,
+        None => value.parse::<f32>().map_err(|_| invalid())?,
+    };
+    if size.is_finite() && size > 0.0 {
+        Ok(size)
+    } else {
+        Err(invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::helpers::open_sans;
+
+    fn base() -> SpanStyle<'static> {
+        SpanStyle::new(FontFamily::new(open_sans().unwrap()), 20.0)
+    }
+
+    /// Each span as its text prefixed with `b`, `u`, `s` and `h` for the styles it has.
+    fn styled(source: &str) -> Vec<String> {
+        parse(source, &base())
+            .unwrap()
+            .into_iter()
+            .map(|span| {
+                let style = &span.style;
+                let flags = [
+                    (style.weight == FontWeight::Bold, 'b'),
+                    (style.underline, 'u'),
+                    (style.strikethrough, 's'),
+                    (style.background.is_some(), 'h'),
+                ];
+                let flags: String = flags
+                    .into_iter()
+                    .filter_map(|(on, flag)| on.then_some(flag))
+                    .collect();
+                format!("{flags}:{}", span.text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn paired_delimiters_style_their_text() {
+        assert_eq!(
+            styled("a **b** __c__ ~~d~~ ==e=="),
+            [":a ", "b:b", ": ", "u:c", ": ", "s:d", ": ", "h:e"]
+        );
+    }
+
+    #[test]
+    fn delimiters_and_tags_nest() {
+        assert_eq!(styled("**a [u]b ~~c~~[/u]**"), ["b:a ", "bu:b ", "bus:c"]);
+        let spans = parse("[size=200%][sup]x[/sup][/size]", &base()).unwrap();
+        assert_eq!(spans[0].style.size, 40.0 * 0.7);
+        assert!(spans[0].style.baseline_shift > 0.0);
+    }
+
+    #[test]
+    fn unpaired_delimiters_are_text() {
+        assert_eq!(styled("a == b"), [":a == b"]);
+        assert_eq!(styled("~~"), [":~~"]);
+        assert_eq!(styled("a **b"), [":a **b"]);
+        assert_eq!(styled("** a **"), [":** a **"]);
+    }
+
+    #[test]
+    fn intra_word_delimiters_are_text() {
+        assert_eq!(styled("snake__case__name"), [":snake__case__name"]);
+        assert_eq!(styled("2**3**4"), [":2**3**4"]);
+    }
+
+    #[test]
+    fn crossing_delimiters_leave_the_inner_one_as_text() {
+        assert_eq!(styled("**a __b** c__"), ["b:a __b", ": c__"]);
+    }
+
+    #[test]
+    fn escapes_keep_markup_as_text() {
+        assert_eq!(styled(r"\**x\**"), [":**x**"]);
+        assert_eq!(styled(r"\[b]x"), [":[b]x"]);
+        assert_eq!(styled(r"a\\b"), [r":a\b"]);
+    }
+
+    #[test]
+    fn unknown_tags_are_text() {
+        assert_eq!(styled("[foo]x[/foo]"), [":[foo]x[/foo]"]);
+        assert_eq!(styled("[b"), [":[b"]);
+    }
+
+    #[test]
+    fn tags_must_close_in_order() {
+        assert!(parse("[b][u]x[/b][/u]", &base()).is_err());
+        assert!(parse("[b]x", &base()).is_err());
+        assert!(parse("x[/b]", &base()).is_err());
+    }
+
+    #[test]
+    fn invalid_tag_values_fail() {
+        assert!(parse("[color=red]x[/color]", &base()).is_err());
+        assert!(parse("[size=-2]x[/size]", &base()).is_err());
+        assert!(parse("[font=missing]x[/font]", &base()).is_err());
+    }
+
+    #[test]
+    fn colors_parse_in_every_form() {
+        assert_eq!(parse_color("#f00"), Ok(Rgba::new(255, 0, 0, 255)));
+        assert_eq!(parse_color("#f008"), Ok(Rgba::new(255, 0, 0, 136)));
+        assert_eq!(parse_color("#102030"), Ok(Rgba::new(16, 32, 48, 255)));
+        assert_eq!(parse_color("#10203040"), Ok(Rgba::new(16, 32, 48, 64)));
+        assert!(parse_color("102030").is_err());
+        assert!(parse_color("#12345").is_err());
+    }
+}
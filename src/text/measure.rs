@@ -0,0 +1,54 @@
+use super::Text;
+use super::layout::{Bounds, Placement, TextLayout};
+use super::overflow::OverflowReport;
+
+/// Size and position information for text, worked out without drawing it.
+///
+/// Positions are relative to the top-left corner of the unrotated text block; `placement`
+/// maps the block onto the canvas.
+pub struct Measurement<'a> {
+    /// The laid out lines and their positioned glyphs.
+    pub layout: TextLayout<'a>,
+    /// Font size chosen by fitting.
+    pub font_size: f32,
+    /// Baselines of the lines: y for horizontal text, the column's center x for vertical text.
+    pub baselines: Vec<f32>,
+    /// Box made of the line boxes, from advances and line pitches.
+    pub logical_bounds: Bounds,
+    /// Box covered by glyph outlines, `None` when nothing would be drawn.
+    pub ink_bounds: Option<Bounds>,
+    pub placement: Placement,
+    /// Shrinking and overflow handling needed to fit, e.g. to flag truncated records.
+    pub report: OverflowReport,
+}
+
+/// Lays out the text as it would be drawn with `available_width` pixels per line.
+///
+/// The width replaces the field's width, or `max_width` for text without a field, so the
+/// result can size a box around the text. Fitting and overflow handling run as for drawing.
+pub fn measure<'a>(text: &Text<'a>, available_width: f32) -> Measurement<'a> {
+    let mut text = text.clone();
+    match &mut text.field {
+        Some(field) => {
+            field.width = available_width.max(0.0) as u32;
+            field.x2 = field.x1 + field.width;
+        }
+        None => text.max_width = Some(available_width.max(0.0) as u32),
+    }
+
+    let fitted = text.fit();
+    Measurement {
+        font_size: fitted.report.font_size,
+        baselines: fitted
+            .layout
+            .lines
+            .iter()
+            .map(|line| line.baseline)
+            .collect(),
+        logical_bounds: fitted.layout.logical_bounds(),
+        ink_bounds: fitted.layout.ink_bounds(),
+        placement: fitted.placement,
+        report: fitted.report,
+        layout: fitted.layout,
+    }
+}
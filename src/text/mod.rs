@@ -1,14 +1,30 @@
 pub mod helpers;
+pub mod hyphenate;
+pub mod layout;
+pub mod markup;
+pub mod measure;
+pub mod overflow;
+pub mod paragraph;
+pub mod path;
+pub mod rich;
 
 use rgb::Rgba;
-use rusttype::{Font, Scale};
+use rusttype::Font;
 
+use crate::error::ImageSlapperError;
 use crate::geometry::rectangle::Rectangle;
+use crate::rendering::color::ColorSpace;
 use crate::rendering::draw::Drawable;
 use image::DynamicImage;
 
+use hyphenate::Hyphenation;
+use layout::{LayoutOptions, Placement, TextLayout};
+use overflow::{Overflow, OverflowReport};
+use paragraph::ParagraphStyle;
+use rich::{FontFamily, Span, SpanStyle};
+
 /// Options for Text structs text alignment.
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TextAlignment {
     Left,
     Center,
@@ -17,7 +33,7 @@ pub enum TextAlignment {
 }
 
 /// Options for Text anchor points.
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Anchor {
     TopLeft,
     TopCenter,
@@ -30,162 +46,312 @@ pub enum Anchor {
     BottomRight,
 }
 
+/// Direction that lines of text run in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WritingMode {
+    /// Left to right lines stacked top to bottom.
+    #[default]
+    Horizontal,
+    /// Top to bottom columns of upright glyphs stacked right to left, for CJK and spine labels.
+    Vertical,
+}
+
+impl Anchor {
+    /// Returns the horizontal and vertical position of the anchor as fractions of a box.
+    pub fn fractions(&self) -> (f32, f32) {
+        match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::TopCenter => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::CenterLeft => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::CenterRight => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::BottomCenter => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
 /// A struct representing a text object.
+#[derive(Clone)]
 pub struct Text<'a> {
     pub content: String,
     pub font: Option<Font<'a>>,
     pub font_size: f32,
+    /// Smallest size the text may shrink to before `overflow` applies, `None` to never shrink.
+    pub min_font_size: Option<f32>,
     pub color: Option<Rgba<u8>>,
+    /// The space `color` is given in.
+    pub color_space: ColorSpace,
     pub field: Option<Rectangle>,
     pub alignment: TextAlignment,
     pub anchor: Option<Anchor>,
+    /// Wrapping width for text without a field.
     pub max_width: Option<u32>,
+    pub max_lines: Option<usize>,
     pub line_height: Option<f32>,
+    /// Clockwise rotation in degrees about the anchor point.
     pub rotation_deg: Option<f32>,
     pub word_wrap: bool,
+    pub writing_mode: WritingMode,
+    pub overflow: Overflow,
+    /// Extra space after every character in pixels.
+    pub letter_spacing: f32,
+    /// Extra space added to every space character in pixels.
+    pub word_spacing: f32,
+    pub paragraph: ParagraphStyle,
+    pub hyphenation: Hyphenation,
 }
 
-/// A trait for drawable objects.
-impl Drawable for Text<'_> {
-    /// Draws the text on the given image.
-    fn draw(&self, image: &mut DynamicImage) {
-        if let Some(font) = &self.font {
-            let mut scale = Scale::uniform(self.font_size);
-            let color = self.color.unwrap_or(Rgba::new(0, 0, 0, 255));
-            let color = image::Rgba([color.r, color.g, color.b, color.a]);
-
-            let mut img = image.to_rgba8();
-            let draw_x = self.field.map_or(0, |field| field.x1);
-            let draw_y = self.field.map_or(0, |field| field.y1);
-
-            if let Some(field) = &self.field {
-                let max_width = field.width as f32;
-                let mut caret = 0.0;
-
-                while caret < max_width {
-                    caret = 0.0;
-                    scale = Scale::uniform(scale.x + 1.0);
-                    for c in self.content.chars() {
-                        if let Some(glyph) = font.glyph(c).into() {
-                            caret += glyph.scaled(scale).h_metrics().advance_width;
-                        }
-                    }
-                    if caret > max_width {
-                        scale = Scale::uniform(scale.x - 1.0);
-                        break;
+/// Text laid out for its field and ready to draw.
+pub struct FittedText<'a> {
+    pub spans: Vec<Span<'a>>,
+    pub layout: TextLayout<'a>,
+    pub placement: Placement,
+    pub report: OverflowReport,
+}
+
+impl<'a> Text<'a> {
+    /// Converts the text into a single span at the given font size.
+    pub fn spans(&self, font_size: f32) -> Vec<Span<'a>> {
+        self.spans_for(&self.content, font_size)
+    }
+
+    /// Converts other content into a single span with this text's style.
+    fn spans_for(&self, content: &str, font_size: f32) -> Vec<Span<'a>> {
+        let Some(font) = &self.font else {
+            return Vec::new();
+        };
+        let mut style = SpanStyle::new(FontFamily::new(font.clone()), font_size);
+        if let Some(color) = self.color {
+            style.color = self.color_space.to_srgb(color);
+        }
+        style.letter_spacing = self.letter_spacing;
+        style.word_spacing = self.word_spacing;
+        vec![Span::new(content, style)]
+    }
+
+    /// Layout options for the text, wrapping at the field's extent along the line direction.
+    pub fn layout_options(&self) -> LayoutOptions {
+        let direction = match self.writing_mode {
+            WritingMode::Horizontal => self.rotation(),
+            WritingMode::Vertical => self.rotation() + 90.0,
+        };
+        let max_width = match &self.field {
+            Some(field) => Some(layout::line_length_limit(
+                field.width as f32,
+                field.height as f32,
+                direction,
+            )),
+            None => self.max_width.map(|width| width as f32),
+        };
+
+        LayoutOptions {
+            max_width,
+            alignment: self.alignment,
+            line_height: self.line_height,
+            word_wrap: self.word_wrap,
+            writing_mode: self.writing_mode,
+            paragraph: self.paragraph.clone(),
+            hyphenation: self.hyphenation,
+        }
+    }
+
+    /// Lays out the text at the given font size.
+    pub fn layout_at(&self, font_size: f32) -> TextLayout<'a> {
+        layout::layout(&self.spans(font_size), &self.layout_options())
+    }
+
+    /// Places a laid out block of this text in its field.
+    pub fn placement(&self, layout: &TextLayout) -> Placement {
+        Placement::new(
+            self.field.as_ref(),
+            self.anchor,
+            self.alignment,
+            (layout.width, layout.height),
+            self.rotation(),
+        )
+    }
+
+    /// Checks whether a layout respects `max_lines` and, once placed, stays inside the field.
+    pub fn fits(&self, layout: &TextLayout) -> bool {
+        self.max_lines
+            .is_none_or(|max_lines| layout.lines.len() <= max_lines)
+            && self.field.as_ref().is_none_or(|field| {
+                self.placement(layout)
+                    .fits((layout.width, layout.height), field)
+            })
+    }
+
+    /// Picks the font size the text is drawn with.
+    ///
+    /// With a field this is the largest size from `font_size` upwards whose rotated block still
+    /// fits the field, found by binary search. When even `font_size` does not fit, the text
+    /// shrinks towards `min_font_size` instead.
+    pub fn fitted_font_size(&self) -> f32 {
+        if self.font.is_none() {
+            return self.font_size;
+        }
+        let fits = |size: f32| self.fits(&self.layout_at(size));
+
+        let (mut low, mut high) = if fits(self.font_size) {
+            let Some(field) = &self.field else {
+                return self.font_size;
+            };
+            (self.font_size, field.width.max(field.height) as f32 + 1.0)
+        } else {
+            match self.min_font_size {
+                Some(min_font_size) if min_font_size < self.font_size => {
+                    if !fits(min_font_size) {
+                        return min_font_size;
                     }
+                    (min_font_size, self.font_size)
                 }
+                _ => return self.font_size,
             }
+        };
 
-            let v_metrics = font.v_metrics(scale);
-            let mut glyphs = Vec::new();
-            let mut caret = draw_x as f32;
-
-            let line_width: f32 = self
-                .content
-                .chars()
-                .filter_map(|c| {
-                    std::convert::Into::<Option<rusttype::Glyph<'_>>>::into(font.glyph(c))
-                        .map(|glyph| glyph.scaled(scale).h_metrics().advance_width)
-                })
-                .sum();
-
-            let alignment_offset = match self.alignment {
-                TextAlignment::Left => 0.0,
-                TextAlignment::Center => {
-                    (self.field.map_or(0, |field| field.width) as f32 - line_width) / 2.0
-                }
-                TextAlignment::Right => {
-                    self.field.map_or(0, |field| field.width) as f32 - line_width
-                }
-                TextAlignment::Justify => 0.0,
-            };
+        while high - low > 0.5 {
+            let middle = (low + high) / 2.0;
+            if fits(middle) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
 
-            let anchor_offset = match self.anchor {
-                Some(Anchor::TopLeft) => (0.0, 0.0),
-                Some(Anchor::TopCenter) => {
-                    (self.field.map_or(0, |field| field.width) as f32 / 2.0, 0.0)
-                }
-                Some(Anchor::TopRight) => (self.field.map_or(0, |field| field.width) as f32, 0.0),
-                Some(Anchor::CenterLeft) => {
-                    (0.0, self.field.map_or(0, |field| field.height) as f32 / 2.0)
-                }
-                Some(Anchor::Center) => (
-                    self.field.map_or(0, |field| field.width) as f32 / 2.0,
-                    self.field.map_or(0, |field| field.height) as f32 / 2.0,
-                ),
-                Some(Anchor::CenterRight) => (
-                    self.field.map_or(0, |field| field.width) as f32,
-                    self.field.map_or(0, |field| field.height) as f32 / 2.0,
-                ),
-                Some(Anchor::BottomLeft) => {
-                    (0.0, self.field.map_or(0, |field| field.height) as f32)
-                }
-                Some(Anchor::BottomCenter) => (
-                    self.field.map_or(0, |field| field.width) as f32 / 2.0,
-                    self.field.map_or(0, |field| field.height) as f32,
-                ),
-                Some(Anchor::BottomRight) => (
-                    self.field.map_or(0, |field| field.width) as f32,
-                    self.field.map_or(0, |field| field.height) as f32,
-                ),
-                None => (0.0, 0.0),
-            };
+    /// Lays out the text for its field, shrinking and then applying the overflow policy as needed.
+    pub fn fit(&self) -> FittedText<'a> {
+        let font_size = self.fitted_font_size();
+        let options = self.layout_options();
+        let mut spans = self.spans(font_size);
+        let mut layout = layout::layout(&spans, &options);
+        let mut report = OverflowReport {
+            font_size,
+            shrunk: font_size < self.font_size,
+            applied: None,
+            truncated: false,
+        };
 
-            let draw_x = (draw_x as f32 - anchor_offset.0) as u32;
-            let draw_y = (draw_y as f32 - anchor_offset.1) as u32;
-
-            caret += alignment_offset;
-
-            for c in self.content.chars() {
-                if let Some(glyph) =
-                    std::convert::Into::<Option<rusttype::Glyph<'_>>>::into(font.glyph(c))
-                {
-                    let scaled_glyph = glyph
-                        .scaled(scale)
-                        .positioned(rusttype::point(caret, draw_y as f32 + v_metrics.ascent));
-                    caret += scaled_glyph.unpositioned().h_metrics().advance_width;
-
-                    if self.alignment == TextAlignment::Justify && c == ' ' {
-                        let remaining_space =
-                            self.field.map_or(0, |field| field.width) as f32 - line_width;
-                        let space_count = self.content.matches(' ').count() as f32;
-                        caret += remaining_space / space_count;
-                    }
+        if !self.fits(&layout) {
+            report.applied = Some(self.overflow);
 
-                    glyphs.push(scaled_glyph);
-                }
-            }
+            match (self.overflow, &self.font) {
+                (Overflow::Ellipsis(position), Some(font)) => {
+                    let ellipsis = overflow::ellipsis(font);
+                    let shortened = |kept: usize| {
+                        let content = overflow::truncate(&self.content, kept, position, ellipsis);
+                        self.spans_for(&content, font_size)
+                    };
 
-            for glyph in glyphs {
-                if let Some(bounding_box) = glyph.pixel_bounding_box() {
-                    glyph.draw(|x, y, v| {
-                        let px = draw_x + x + bounding_box.min.x as u32;
-                        let py = draw_y + y + bounding_box.min.y as u32;
-                        if px < img.width() && py < img.height() {
-                            let pixel = img.get_pixel_mut(px, py);
-                            let bg = pixel.0;
-                            let alpha = (v * color[3] as f32) as u8;
-
-                            let blended = [
-                                ((color[0] as u16 * alpha as u16
-                                    + bg[0] as u16 * (255 - alpha) as u16)
-                                    / 255) as u8,
-                                ((color[1] as u16 * alpha as u16
-                                    + bg[1] as u16 * (255 - alpha) as u16)
-                                    / 255) as u8,
-                                ((color[2] as u16 * alpha as u16
-                                    + bg[2] as u16 * (255 - alpha) as u16)
-                                    / 255) as u8,
-                                bg[3],
-                            ];
-                            *pixel = image::Rgba(blended);
+                    // Keep as many characters as possible, even zero leaves the ellipsis
+                    let (mut low, mut high) = (0, self.content.chars().count());
+                    while high - low > 1 {
+                        let middle = (low + high) / 2;
+                        if self.fits(&layout::layout(&shortened(middle), &options)) {
+                            low = middle;
+                        } else {
+                            high = middle;
                         }
-                    });
+                    }
+                    spans = shortened(low);
+                    layout = layout::layout(&spans, &options);
+                    report.truncated = true;
+                }
+                _ => {
+                    if let Some(max_lines) = self.max_lines
+                        && layout.lines.len() > max_lines
+                    {
+                        let end = layout.lines[..max_lines]
+                            .iter()
+                            .rev()
+                            .find_map(|line| {
+                                layout.glyphs[line.glyphs.clone()]
+                                    .iter()
+                                    .rfind(|glyph| !glyph.inserted)
+                            })
+                            .map_or(0, |glyph| glyph.index + glyph.ch.len_utf8());
+                        spans = self.spans_for(&self.content[..end], font_size);
+                        layout = layout::layout(&spans, &options);
+                        report.truncated = true;
+                    }
+                    if self.overflow == Overflow::Clip && self.field.is_some() {
+                        report.truncated = true;
+                    }
                 }
             }
+        }
 
-            *image = DynamicImage::ImageRgba8(img);
+        FittedText {
+            placement: self.placement(&layout),
+            spans,
+            layout,
+            report,
         }
     }
+
+    /// Returns the canvas area the text covers once rotated, e.g. to mark it as a dirty region.
+    pub fn bounding_box(&self) -> Option<Rectangle> {
+        self.font.as_ref()?;
+        let fitted = self.fit();
+        let bounds = fitted
+            .placement
+            .bounds((fitted.layout.width, fitted.layout.height));
+
+        match (&self.field, self.overflow) {
+            (Some(field), Overflow::Clip) => Some(bounds.intersection(field)),
+            _ => Some(bounds),
+        }
+    }
+
+    /// Turns the text upside down by rotating it a further 180°.
+    ///
+    /// Text without an anchor is centered in its field so it stays inside it.
+    pub fn inverted(mut self) -> Self {
+        self.rotation_deg = Some(self.rotation() + 180.0);
+        self.anchor = self.anchor.or(Some(Anchor::Center));
+        self
+    }
+
+    fn rotation(&self) -> f32 {
+        self.rotation_deg.unwrap_or(0.0)
+    }
+}
+
+/// A trait for drawable objects.
+impl Drawable for Text<'_> {
+    /// Draws the text on the given image.
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError> {
+        if self.font.is_none() {
+            return Err(ImageSlapperError::Font {
+                name: "(none)".to_string(),
+                context: format!("no font set to draw \"{}\"", self.content),
+                path: None,
+            });
+        }
+
+        let fitted = self.fit();
+        let mut img = image.to_rgba8();
+
+        match (&self.field, self.overflow) {
+            (Some(field), Overflow::Clip) => {
+                // Draw into a copy of the field so nothing lands outside it
+                let mut region =
+                    image::imageops::crop_imm(&img, field.x1, field.y1, field.width, field.height)
+                        .to_image();
+                let placement = fitted
+                    .placement
+                    .translated(-(field.x1 as f32), -(field.y1 as f32));
+                layout::draw(&fitted.layout, &fitted.spans, &mut region, &placement);
+                image::imageops::replace(&mut img, &region, field.x1.into(), field.y1.into());
+            }
+            _ => layout::draw(&fitted.layout, &fitted.spans, &mut img, &fitted.placement),
+        }
+
+        *image = DynamicImage::ImageRgba8(img);
+        Ok(())
+    }
 }
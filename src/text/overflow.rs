@@ -0,0 +1,77 @@
+use rusttype::Font;
+
+/// What to do with text that still does not fit its field at the smallest allowed size.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Overflow {
+    /// Draw the text past the field.
+    #[default]
+    Visible,
+    /// Only draw the parts of the text inside the field.
+    Clip,
+    /// Cut the content and mark the cut with an ellipsis.
+    Ellipsis(EllipsisPosition),
+}
+
+/// Where an ellipsis replaces the content that does not fit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EllipsisPosition {
+    /// Cut at the last character that fits.
+    #[default]
+    End,
+    /// Keep the start and the end of the content, e.g. for file names.
+    Middle,
+    /// Cut after the last whole word that fits.
+    Word,
+}
+
+/// How text was made to fit its field, so callers can flag truncated data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OverflowReport {
+    /// Font size the text was laid out at.
+    pub font_size: f32,
+    /// Whether the font size went below the requested `font_size` to fit.
+    pub shrunk: bool,
+    /// Policy applied to text that did not fit, `None` when it fit.
+    pub applied: Option<Overflow>,
+    /// Whether part of the content is hidden by dropped lines, clipping or an ellipsis.
+    pub truncated: bool,
+}
+
+/// Returns the ellipsis the font can draw, falling back to three periods.
+pub fn ellipsis(font: &Font) -> &'static str {
+    if font.glyph('…').id().0 == 0 {
+        "..."
+    } else {
+        "…"
+    }
+}
+
+/// Shortens `content` to its first `kept` characters, placing an ellipsis at `position`.
+pub fn truncate(content: &str, kept: usize, position: EllipsisPosition, ellipsis: &str) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let kept = kept.min(chars.len());
+
+    match position {
+        EllipsisPosition::End => {
+            let head: String = chars[..kept].iter().collect();
+            format!("{}{ellipsis}", head.trim_end())
+        }
+        EllipsisPosition::Word => {
+            let mut end = kept;
+            // Back up to a word boundary unless the cut already falls on one
+            if end < chars.len()
+                && !chars[end].is_whitespace()
+                && let Some(space) = chars[..end].iter().rposition(|c| c.is_whitespace())
+            {
+                end = space;
+            }
+            let head: String = chars[..end].iter().collect();
+            format!("{}{ellipsis}", head.trim_end())
+        }
+        EllipsisPosition::Middle => {
+            let head: String = chars[..kept.div_ceil(2)].iter().collect();
+            let tail: String = chars[chars.len() - kept / 2..].iter().collect();
+            format!("{}{ellipsis}{}", head.trim_end(), tail.trim_start())
+        }
+    }
+}
@@ -0,0 +1,134 @@
+/// How text after a tab is aligned to its tab stop.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TabAlignment {
+    /// The text starts at the stop.
+    #[default]
+    Left,
+    /// The text ends at the stop.
+    Right,
+    /// The text is centered on the stop.
+    Center,
+    /// The given separator sits on the stop, e.g. `'.'` for columns of prices.
+    Decimal(char),
+}
+
+/// A position a tab advances to, measured from the start of the line box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TabStop {
+    pub position: f32,
+    pub alignment: TabAlignment,
+}
+
+impl TabStop {
+    pub fn new(position: f32, alignment: TabAlignment) -> Self {
+        TabStop {
+            position,
+            alignment,
+        }
+    }
+}
+
+/// Limits on how justified lines are stretched.
+///
+/// Leftover width is shared between the gaps between words and the gaps between letters in
+/// proportion to their weights. A gap that reaches its limit passes the rest to the other kind,
+/// and a line that cannot be filled within both limits stays short rather than being torn apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Justification {
+    /// Weight of a gap between letters relative to a gap between words.
+    pub letter_weight: f32,
+    /// Most extra width per word gap in ems, `None` for unlimited.
+    pub max_word_spacing: Option<f32>,
+    /// Most extra width per letter gap in ems, `None` for unlimited.
+    pub max_letter_spacing: Option<f32>,
+}
+
+impl Default for Justification {
+    fn default() -> Self {
+        Justification {
+            letter_weight: 0.1,
+            max_word_spacing: Some(1.0),
+            max_letter_spacing: Some(0.1),
+        }
+    }
+}
+
+impl Justification {
+    /// Splits `extra` width over the gaps of a line, returning the extra per word and per letter gap.
+    pub fn distribute(
+        &self,
+        extra: f32,
+        word_gaps: usize,
+        letter_gaps: usize,
+        em: f32,
+    ) -> (f32, f32) {
+        let words = word_gaps as f32;
+        let letters = letter_gaps as f32;
+        let letter_weight = if letter_gaps > 0 {
+            self.letter_weight.max(0.0)
+        } else {
+            0.0
+        };
+        let total = words + letters * letter_weight;
+        if extra <= 0.0 || total <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        let word_limit = self
+            .max_word_spacing
+            .map_or(f32::INFINITY, |limit| limit * em);
+        let letter_limit = self
+            .max_letter_spacing
+            .map_or(f32::INFINITY, |limit| limit * em);
+
+        let unit = extra / total;
+        let (mut word, mut letter) = (unit, unit * letter_weight);
+        if word_gaps == 0 {
+            word = 0.0;
+        }
+
+        if word > word_limit {
+            word = word_limit;
+            if letter_gaps > 0 && letter_weight > 0.0 {
+                letter = (extra - word * words) / letters;
+            }
+        }
+        if letter > letter_limit {
+            letter = letter_limit;
+            if word_gaps > 0 {
+                word = ((extra - letter * letters) / words).min(word_limit);
+            }
+        }
+
+        (word.max(0.0), letter.max(0.0))
+    }
+}
+
+/// Paragraph-level spacing: indents, space between paragraphs, tab stops and justification.
+///
+/// Paragraphs are separated by hard line breaks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParagraphStyle {
+    /// Indent of the first line of each paragraph in pixels.
+    pub first_line_indent: f32,
+    /// Space added above every paragraph but the first.
+    pub space_before: f32,
+    /// Space added below every paragraph but the last.
+    pub space_after: f32,
+    /// Explicit tab stops, in any order.
+    pub tab_stops: Vec<TabStop>,
+    /// Distance between the implicit stops past the explicit ones, `None` for four spaces.
+    pub tab_interval: Option<f32>,
+    pub justification: Justification,
+}
+
+impl ParagraphStyle {
+    /// Returns the first explicit stop past `position`.
+    pub fn next_stop(&self, position: f32) -> Option<TabStop> {
+        self.tab_stops
+            .iter()
+            .filter(|stop| stop.position > position)
+            .min_by(|a, b| a.position.total_cmp(&b.position))
+            .copied()
+    }
+}
@@ -0,0 +1,223 @@
+use image::{DynamicImage, RgbaImage};
+
+use crate::error::ImageSlapperError;
+use crate::geometry::path::{MeasuredPath, Path, PathPoint};
+use crate::rendering::blend::composite_rotated;
+use crate::rendering::draw::Drawable;
+
+use super::hyphenate::Hyphenation;
+use super::layout::{self, LayoutLine, LayoutOptions, TextLayout};
+use super::paragraph::ParagraphStyle;
+use super::rich::Span;
+use super::{Text, TextAlignment, WritingMode};
+
+/// Which side of the path glyphs sit on, seen in the direction of travel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PathPlacement {
+    /// Baselines on the path, so a left to right path has the text above it.
+    #[default]
+    Above,
+    /// The middle of the line box on the path.
+    Center,
+    /// The top of the line box on the path, so the text hangs below it.
+    Below,
+}
+
+/// Text laid out along a path, e.g. around a circular badge, each glyph turned to follow it.
+///
+/// The alignment of `text` places the text along the path, and `Justify` spreads the glyphs
+/// over its whole length. The field, anchor, rotation and wrapping settings of `text` do not
+/// apply, and a `min_font_size` lets the text shrink to fit the path.
+#[derive(Clone)]
+pub struct PathText<'a> {
+    pub text: Text<'a>,
+    pub path: Path,
+    /// Distance in pixels to move the text along the path from where the alignment puts it.
+    pub start_offset: f32,
+    pub placement: PathPlacement,
+}
+
+/// Where a glyph of path text is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathGlyph {
+    /// Index of the glyph in the layout.
+    pub glyph: usize,
+    /// Point on the path the glyph is attached to, with its rotation.
+    pub point: PathPoint,
+}
+
+impl<'a> PathText<'a> {
+    pub fn new(text: Text<'a>, path: Path) -> Self {
+        PathText {
+            text,
+            path,
+            start_offset: 0.0,
+            placement: PathPlacement::Above,
+        }
+    }
+
+    /// Lays the text out as a single straight line at the given font size.
+    pub fn layout_at(&self, font_size: f32) -> (Vec<Span<'a>>, TextLayout<'a>) {
+        let content = self.text.content.replace('\n', " ");
+        let spans = self.text.spans_for(&content, font_size);
+        let options = LayoutOptions {
+            max_width: None,
+            alignment: TextAlignment::Left,
+            line_height: self.text.line_height,
+            word_wrap: false,
+            writing_mode: WritingMode::Horizontal,
+            paragraph: ParagraphStyle::default(),
+            hyphenation: Hyphenation::default(),
+        };
+        let layout = layout::layout(&spans, &options);
+        (spans, layout)
+    }
+
+    /// Returns the font size to draw at, shrunk towards `min_font_size` if the text is longer
+    /// than the path.
+    pub fn fitted_font_size(&self, path: &MeasuredPath) -> f32 {
+        let fits = |size: f32| self.layout_at(size).1.width <= path.length();
+        let font_size = self.text.font_size;
+        match self.text.min_font_size {
+            Some(min_font_size) if min_font_size < font_size && !fits(font_size) => {
+                let (mut low, mut high) = (min_font_size, font_size);
+                while high - low > 0.5 {
+                    let middle = (low + high) / 2.0;
+                    if fits(middle) {
+                        low = middle;
+                    } else {
+                        high = middle;
+                    }
+                }
+                low
+            }
+            _ => font_size,
+        }
+    }
+
+    /// Lays out the text and finds where on the path each glyph goes.
+    pub fn glyphs(&self) -> (Vec<Span<'a>>, TextLayout<'a>, Vec<PathGlyph>) {
+        let path = self.path.measure();
+        let (spans, layout) = self.layout_at(self.fitted_font_size(&path));
+        let Some(line) = layout.lines.first() else {
+            return (spans, layout, Vec::new());
+        };
+
+        let length = path.length();
+        let count = layout.glyphs.len();
+        let (start, gap) = match self.text.alignment {
+            TextAlignment::Left => (0.0, 0.0),
+            TextAlignment::Center => ((length - layout.width) / 2.0, 0.0),
+            TextAlignment::Right => (length - layout.width, 0.0),
+            TextAlignment::Justify => {
+                // A closed path also needs a gap where the text meets its own start
+                let gaps = if self.path.is_closed() {
+                    count
+                } else {
+                    count.saturating_sub(1)
+                };
+                let extra = length - self.start_offset.abs() - layout.width;
+                (
+                    0.0,
+                    if gaps > 0 {
+                        extra.max(0.0) / gaps as f32
+                    } else {
+                        0.0
+                    },
+                )
+            }
+        };
+
+        let anchor = match self.placement {
+            PathPlacement::Above => line.baseline,
+            PathPlacement::Center => line.top + line.height / 2.0,
+            PathPlacement::Below => line.top,
+        };
+
+        let placed = layout
+            .glyphs
+            .iter()
+            .enumerate()
+            .map(|(index, glyph)| {
+                let along = start
+                    + self.start_offset
+                    + glyph.offset
+                    + glyph.advance / 2.0
+                    + gap * index as f32;
+                let mut point = path.sample(along);
+                // Move from the path to the glyph's baseline, across the direction of travel
+                let (sin, cos) = point.angle_deg.to_radians().sin_cos();
+                let across = line.baseline - anchor;
+                point.x -= sin * across;
+                point.y += cos * across;
+                PathGlyph {
+                    glyph: index,
+                    point,
+                }
+            })
+            .collect();
+
+        (spans, layout, placed)
+    }
+}
+
+impl Drawable for PathText<'_> {
+    /// Draws each glyph rotated to the path's direction at its position.
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError> {
+        let (spans, layout, placed) = self.glyphs();
+        let Some(line) = layout.lines.first() else {
+            return Ok(());
+        };
+
+        // Leave room for glyphs that reach outside their line boxes
+        let padding = spans
+            .iter()
+            .map(|span| span.style.size)
+            .fold(0.0, f32::max)
+            .ceil();
+
+        let mut img = image.to_rgba8();
+        for placed in placed {
+            let glyph = &layout.glyphs[placed.glyph];
+            if glyph.ch.is_whitespace() {
+                continue;
+            }
+
+            // Render the glyph upright on its own, then turn it onto the path
+            let single = TextLayout {
+                glyphs: vec![glyph.clone()],
+                lines: vec![LayoutLine {
+                    glyphs: 0..1,
+                    x: glyph.offset,
+                    top: line.top,
+                    baseline: line.baseline,
+                    width: glyph.advance,
+                    height: line.height,
+                }],
+                width: glyph.advance,
+                height: line.height,
+                writing_mode: WritingMode::Horizontal,
+            };
+            let mut cell = RgbaImage::new(
+                (glyph.advance + padding * 2.0).ceil() as u32,
+                (line.height + padding * 2.0).ceil() as u32,
+            );
+            let origin = ((padding - glyph.offset).round(), padding - line.top);
+            layout::render(&single, &spans, &mut cell, origin);
+
+            let source_pivot = (
+                origin.0 + glyph.offset + glyph.advance / 2.0,
+                origin.1.round() + line.baseline,
+            );
+            composite_rotated(
+                &mut img,
+                &cell,
+                source_pivot,
+                (placed.point.x, placed.point.y),
+                placed.point.angle_deg,
+            );
+        }
+        *image = DynamicImage::ImageRgba8(img);
+        Ok(())
+    }
+}
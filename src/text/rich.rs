@@ -0,0 +1,222 @@
+use image::DynamicImage;
+use rgb::Rgba;
+use rusttype::{Font, Scale};
+
+use crate::error::ImageSlapperError;
+use crate::geometry::rectangle::Rectangle;
+use crate::rendering::draw::Drawable;
+
+use super::hyphenate::Hyphenation;
+use super::layout::{self, LayoutOptions, Placement};
+use super::markup;
+use super::paragraph::ParagraphStyle;
+use super::{Anchor, TextAlignment, WritingMode};
+
+/// Font weights a span can request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FontWeight {
+    #[default]
+    Regular,
+    Bold,
+}
+
+/// The faces of one font family, used to resolve a span's weight.
+#[derive(Clone)]
+pub struct FontFamily<'a> {
+    pub regular: Font<'a>,
+    pub bold: Option<Font<'a>>,
+}
+
+impl<'a> FontFamily<'a> {
+    /// Creates a family with only a regular face, bold is synthesized.
+    pub fn new(regular: Font<'a>) -> Self {
+        FontFamily {
+            regular,
+            bold: None,
+        }
+    }
+
+    /// Adds a dedicated bold face to the family.
+    pub fn with_bold(mut self, bold: Font<'a>) -> Self {
+        self.bold = Some(bold);
+        self
+    }
+
+    /// Returns the face for the weight and whether it must be emboldened synthetically.
+    pub fn face(&self, weight: FontWeight) -> (&Font<'a>, bool) {
+        match (weight, &self.bold) {
+            (FontWeight::Regular, _) => (&self.regular, false),
+            (FontWeight::Bold, Some(bold)) => (bold, false),
+            (FontWeight::Bold, None) => (&self.regular, true),
+        }
+    }
+}
+
+/// The visual style of a run of rich text.
+#[derive(Clone)]
+pub struct SpanStyle<'a> {
+    pub font: FontFamily<'a>,
+    pub weight: FontWeight,
+    pub size: f32,
+    pub color: Rgba<u8>,
+    pub underline: bool,
+    pub strikethrough: bool,
+    /// Highlight color painted behind the run.
+    pub background: Option<Rgba<u8>>,
+    /// Vertical offset from the baseline in pixels, positive values raise the run.
+    pub baseline_shift: f32,
+    /// Extra space after every character in pixels, negative values tighten the run.
+    pub letter_spacing: f32,
+    /// Extra space added to every space character in pixels.
+    pub word_spacing: f32,
+}
+
+impl<'a> SpanStyle<'a> {
+    /// Creates a plain black style with the given font and size.
+    pub fn new(font: FontFamily<'a>, size: f32) -> Self {
+        SpanStyle {
+            font,
+            weight: FontWeight::Regular,
+            size,
+            color: Rgba::new(0, 0, 0, 255),
+            underline: false,
+            strikethrough: false,
+            background: None,
+            baseline_shift: 0.0,
+            letter_spacing: 0.0,
+            word_spacing: 0.0,
+        }
+    }
+
+    /// Returns the ascent, descent and line gap of the resolved face, all as positive distances.
+    pub fn v_metrics(&self) -> (f32, f32, f32) {
+        let (font, _) = self.font.face(self.weight);
+        let metrics = font.v_metrics(Scale::uniform(self.size));
+        (metrics.ascent, -metrics.descent, metrics.line_gap)
+    }
+
+    /// Width in pixels added to each glyph's strokes when bold has to be synthesized.
+    pub fn embolden_width(&self) -> f32 {
+        (self.size / 20.0).round().max(1.0)
+    }
+
+    /// Thickness of underline and strikethrough lines in pixels.
+    pub fn decoration_thickness(&self) -> f32 {
+        (self.size / 16.0).round().max(1.0)
+    }
+}
+
+/// A run of text sharing one style.
+#[derive(Clone)]
+pub struct Span<'a> {
+    pub text: String,
+    pub style: SpanStyle<'a>,
+}
+
+impl<'a> Span<'a> {
+    pub fn new(text: &str, style: SpanStyle<'a>) -> Self {
+        Span {
+            text: text.to_string(),
+            style,
+        }
+    }
+}
+
+/// Text made of differently styled spans that are wrapped and aligned together.
+pub struct RichText<'a> {
+    pub spans: Vec<Span<'a>>,
+    pub field: Option<Rectangle>,
+    pub alignment: TextAlignment,
+    pub anchor: Option<Anchor>,
+    pub line_height: Option<f32>,
+    /// Clockwise rotation in degrees about the anchor point.
+    pub rotation_deg: Option<f32>,
+    pub word_wrap: bool,
+    pub writing_mode: WritingMode,
+    pub paragraph: ParagraphStyle,
+    pub hyphenation: Hyphenation,
+}
+
+impl<'a> RichText<'a> {
+    /// Creates left-aligned, wrapping rich text from spans.
+    pub fn new(spans: Vec<Span<'a>>, field: Option<Rectangle>) -> Self {
+        RichText {
+            spans,
+            field,
+            alignment: TextAlignment::Left,
+            anchor: None,
+            line_height: None,
+            rotation_deg: None,
+            word_wrap: true,
+            writing_mode: WritingMode::Horizontal,
+            paragraph: ParagraphStyle::default(),
+            hyphenation: Hyphenation::default(),
+        }
+    }
+
+    /// Parses markup into spans, starting from the `base` style. See `markup` for the syntax.
+    pub fn from_markup(
+        source: &str,
+        base: SpanStyle<'a>,
+        field: Option<Rectangle>,
+    ) -> Result<Self, String> {
+        Ok(RichText::new(markup::parse(source, &base)?, field))
+    }
+
+    /// Lays out the spans for the text's field.
+    pub fn layout(&self) -> layout::TextLayout<'a> {
+        let direction = match self.writing_mode {
+            WritingMode::Horizontal => self.rotation(),
+            WritingMode::Vertical => self.rotation() + 90.0,
+        };
+        layout::layout(
+            &self.spans,
+            &LayoutOptions {
+                max_width: self.field.map(|field| {
+                    layout::line_length_limit(field.width as f32, field.height as f32, direction)
+                }),
+                alignment: self.alignment,
+                line_height: self.line_height,
+                word_wrap: self.word_wrap,
+                writing_mode: self.writing_mode,
+                paragraph: self.paragraph.clone(),
+                hyphenation: self.hyphenation,
+            },
+        )
+    }
+
+    /// Places a laid out block of this text in its field.
+    pub fn placement(&self, layout: &layout::TextLayout) -> Placement {
+        Placement::new(
+            self.field.as_ref(),
+            self.anchor,
+            self.alignment,
+            (layout.width, layout.height),
+            self.rotation(),
+        )
+    }
+
+    /// Returns the canvas area the text covers once rotated, e.g. to mark it as a dirty region.
+    pub fn bounding_box(&self) -> Rectangle {
+        let layout = self.layout();
+        self.placement(&layout)
+            .bounds((layout.width, layout.height))
+    }
+
+    fn rotation(&self) -> f32 {
+        self.rotation_deg.unwrap_or(0.0)
+    }
+}
+
+impl Drawable for RichText<'_> {
+    /// Draws the spans on the given image.
+    fn draw(&self, image: &mut DynamicImage) -> Result<(), ImageSlapperError> {
+        let layout = self.layout();
+        let placement = self.placement(&layout);
+
+        let mut img = image.to_rgba8();
+        layout::draw(&layout, &self.spans, &mut img, &placement);
+        *image = DynamicImage::ImageRgba8(img);
+        Ok(())
+    }
+}